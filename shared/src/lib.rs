@@ -1,12 +1,17 @@
 #[cfg(feature = "diesel")]
 pub mod schema;
+pub mod template;
 
 #[cfg(feature = "diesel")]
 use crate::schema::*;
+use chrono::{DateTime, Utc};
 #[cfg(feature = "diesel")]
 use diesel::{Identifiable, Queryable};
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use template::{TemplateError, MAX_EXPANDED_TARGETS};
+use validator::{Validate, ValidationError};
 
 #[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -24,6 +29,48 @@ pub struct Link {
 
     pub stripe_session_id: Option<String>,
     pub payment_status: Option<PaymentStatus>,
+    /// When the current checkout expires, after that the link can be garbage collected
+    pub payment_expires_at: Option<DateTime<Utc>>,
+    #[serde(skip, default)]
+    pub account_id: Option<i32>,
+    #[serde(skip, default)]
+    pub domain_id: Option<i32>,
+    #[serde(skip, default)]
+    pub workspace_id: Option<i32>,
+    #[serde(skip, default)]
+    pub management_token_hash: Option<String>,
+    #[serde(default)]
+    pub visibility: LinkVisibility,
+    #[serde(skip, default)]
+    pub password_hash: Option<String>,
+    #[serde(skip, default)]
+    pub signing_secret: Option<String>,
+    /// Requests are forwarded to the targets instead of being redirected
+    #[serde(default)]
+    pub proxy: bool,
+    /// Visitors with the same `group` query parameter are sent to the same target
+    #[serde(default)]
+    pub group_affinity: bool,
+    /// Visitors choose a target on a landing page instead of being sent to a random one
+    #[serde(default)]
+    pub picker: bool,
+    /// Url of a json list the targets are loaded from, it may contain credentials of the owner
+    #[serde(skip, default)]
+    pub target_source: Option<String>,
+    #[serde(skip, default)]
+    pub target_source_checked_at: Option<DateTime<Utc>>,
+    #[serde(skip, default)]
+    pub target_source_error: Option<String>,
+    /// Url that is asked for new targets when the link runs out of capacity
+    #[serde(skip, default)]
+    pub provisioning_webhook: Option<String>,
+    #[serde(skip, default)]
+    pub provisioning_secret: Option<String>,
+    #[serde(skip, default)]
+    pub provisioning_threshold: i32,
+    /// Set once the link gets a webhook subscription, it stays set when they are deleted again
+    #[serde(skip, default)]
+    pub has_webhooks: bool,
 }
 
 #[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
@@ -34,39 +81,430 @@ pub struct Target {
     pub link_id: i32,
     pub target_url: String,
     pub redirects: i32,
+    /// Short name shown instead of the url
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Hex color like `#1f2937`
+    #[serde(default)]
+    pub color: Option<String>,
+    /// How often the target is chosen relative to the other targets of the link
+    #[serde(default = "default_weight")]
+    pub weight: i32,
+    /// Redirects after which the target is full and no longer chosen
+    #[serde(default)]
+    pub capacity: Option<i32>,
+    /// Targets that disappeared from the target source of the link are kept for their stats
+    #[serde(default)]
+    pub active: bool,
+}
+
+fn default_weight() -> i32 {
+    1
+}
+
+impl Target {
+    /// Whether the target reached its capacity.
+    pub fn is_full(&self) -> bool {
+        self.capacity
+            .map(|capacity| self.redirects >= capacity)
+            .unwrap_or(false)
+    }
+
+    /// The label of the target, or its url without the scheme if it has none.
+    pub fn display_name(&self) -> &str {
+        match &self.label {
+            Some(label) => label,
+            None => {
+                let url = self
+                    .target_url
+                    .split_once("://")
+                    .map(|(_, rest)| rest)
+                    .unwrap_or(&self.target_url);
+                url.trim_end_matches('/')
+            }
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Validate, Clone, Eq, PartialEq, Debug)]
+/// How many more visitors fit into the targets of a link, none if one of them has no capacity.
+pub fn remaining_capacity(targets: &[Target]) -> Option<i64> {
+    targets
+        .iter()
+        .map(|target| {
+            target
+                .capacity
+                .map(|capacity| i64::from(capacity - target.redirects).max(0))
+        })
+        .sum()
+}
+
+#[derive(Serialize, Deserialize, Validate, Clone, Eq, PartialEq, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateTargetDto {
     #[validate(url)]
     pub target_url: String,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 64))]
+    pub label: Option<String>,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 500))]
+    pub description: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_color")]
+    pub color: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 1, max = 1000))]
+    pub weight: Option<i32>,
+    #[serde(default)]
+    #[validate(range(min = 1))]
+    pub capacity: Option<i32>,
+}
+
+impl CreateTargetDto {
+    /// The targets the url template of this target expands to, they share its label,
+    /// description and color.
+    pub fn expand(&self) -> Result<Vec<CreateTargetDto>, TemplateError> {
+        Ok(template::expand(&self.target_url)?
+            .into_iter()
+            .map(|target_url| CreateTargetDto {
+                target_url,
+                ..self.clone()
+            })
+            .collect())
+    }
+}
+
+/// Colors are stored as `#rrggbb`, so they can be used in css as they are.
+fn validate_color(color: &str) -> Result<(), ValidationError> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("color"))
+    }
 }
 
 #[derive(Serialize, Deserialize, Validate)]
 #[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_create_link"))]
 pub struct CreateLinkDto {
+    /// Custom slug, only used for links on a custom domain
     #[serde(default)]
     pub url: Option<String>,
+    /// Verified custom domain of the account the link should be created on
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Workspace the link should belong to, requires the editor role in it
+    #[serde(default)]
+    pub workspace: Option<i32>,
+    #[serde(default)]
+    pub visibility: LinkVisibility,
+    /// Password visitors have to enter before they are redirected
+    #[serde(default)]
+    #[validate(length(min = 1, max = 128))]
+    pub password: Option<String>,
+    /// Only allow visits through signed urls, which can be minted after the link was created
+    #[serde(default)]
+    pub signed: bool,
+    /// Forward requests to the targets instead of redirecting, like a reverse proxy
+    #[serde(default)]
+    pub proxy: bool,
+    /// Send visitors with the same `group` query parameter to the same target
+    #[serde(default)]
+    pub group_affinity: bool,
+    /// Let visitors choose a target on a landing page, can't be combined with the proxy mode
+    #[serde(default)]
+    pub picker: bool,
     #[serde(default)]
     pub permanent_redirect: bool,
-    #[validate(length(min = 1))]
+    /// Target urls can be templates like `room-{1..20}`, which are expanded before validation
+    #[serde(default)]
     #[validate]
     pub targets: Vec<CreateTargetDto>,
+    /// Url of a json list of targets that is polled for changes, the targets are loaded from
+    /// it instead of taken from the request
+    #[serde(default)]
+    #[validate(url)]
+    pub target_source: Option<String>,
+    /// Url that is asked for new targets once fewer than `provisioning_threshold` more
+    /// visitors fit into the targets, can't be combined with a target source
+    #[serde(default)]
+    #[validate(url)]
+    pub provisioning_webhook: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 1))]
+    pub provisioning_threshold: Option<i32>,
+    /// Webhooks that are told about events of the link, starting with its creation
+    #[serde(default)]
+    #[validate(length(max = 10))]
+    #[validate]
+    pub webhooks: Vec<CreateWebhookDto>,
+}
+
+/// Links need targets, unless they are loaded from a target source.
+fn validate_create_link(create: &CreateLinkDto) -> Result<(), ValidationError> {
+    if create.targets.is_empty() && create.target_source.is_none() {
+        Err(ValidationError::new("targets"))
+    } else {
+        Ok(())
+    }
+}
+
+impl CreateLinkDto {
+    /// Replaces the targets by the targets their url templates expand to.
+    pub fn expand_targets(&mut self) -> Result<(), TemplateError> {
+        let mut targets = vec![];
+        for target in &self.targets {
+            targets.extend(target.expand()?);
+            if targets.len() > MAX_EXPANDED_TARGETS {
+                return Err(TemplateError::TooManyTargets);
+            }
+        }
+
+        self.targets = targets;
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum CreateResult {
-    Link(LinkDto),
+    Link(Box<LinkDto>),
     StripeRedirect(String),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct LinkDto {
     #[serde(flatten)]
     pub link: Link,
 
     pub targets: Vec<Target>,
+
+    /// The custom domain the link lives on, or none for the public base url
+    #[serde(default)]
+    pub domain: Option<String>,
+
+    /// Visitors have to enter a password before they are redirected
+    #[serde(default)]
+    pub password_protected: bool,
+
+    /// The link can only be visited through signed urls
+    #[serde(default)]
+    pub signed: bool,
+
+    /// How many distinct groups each target received, only filled for links with group affinity
+    #[serde(default)]
+    pub target_groups: Vec<TargetGroupsDto>,
+
+    /// Where the targets are loaded from, only included for the owners of the link
+    #[serde(default)]
+    pub target_source: Option<TargetSourceDto>,
+
+    /// The provisioning webhook and its recent calls, only included for the owners of the link
+    #[serde(default)]
+    pub provisioning: Option<ProvisioningDto>,
+
+    /// Token that allows managing the link, only included right after it was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub management_token: Option<String>,
+
+    /// Secret the provisioning requests are signed with, only included right after the link
+    /// was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provisioning_secret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetSourceDto {
+    pub url: String,
+    /// When the source was last fetched, successfully or not
+    pub checked_at: Option<DateTime<Utc>>,
+    /// Why the last fetch failed, the targets are those of the last successful one then
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisioningDto {
+    pub url: String,
+    pub threshold: i32,
+    /// How many more visitors fit into the targets, none if one of them has no capacity
+    pub remaining_capacity: Option<i64>,
+    /// The most recent calls of the webhook, newest first
+    pub events: Vec<ProvisioningEvent>,
+}
+
+/// A call of the provisioning webhook of a link.
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisioningEvent {
+    pub id: i32,
+    #[serde(skip, default)]
+    pub link_id: i32,
+    /// The remaining capacity that triggered the call
+    pub remaining_capacity: i32,
+    pub attempts: i32,
+    pub added_targets: i32,
+    /// Why the webhook failed after all attempts
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Something that happened to a link, which its webhook subscriptions can be told about.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "diesel", derive(diesel_derive_enum::DbEnum))]
+#[cfg_attr(
+    feature = "diesel",
+    ExistingTypePath = "crate::schema::sql_types::LinkEvent"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkEvent {
+    Created,
+    /// The payment succeeded or the link was approved
+    Paid,
+    /// The link was flagged as fraudulent and stopped redirecting
+    Fraud,
+    /// A target reached its capacity
+    TargetFull,
+    /// The link reached one of the redirect counts of the subscription
+    ClickThreshold,
+    /// The checkout expired without being paid and the link was deleted
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Validate, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWebhookDto {
+    #[validate(url)]
+    pub url: String,
+    /// The events that are delivered, all of them if this is empty
+    #[serde(default)]
+    pub events: Vec<LinkEvent>,
+    /// Redirect counts of the link that trigger a `click_threshold` event
+    #[serde(default)]
+    #[validate(length(max = 20), custom = "validate_click_thresholds")]
+    pub click_thresholds: Vec<i32>,
+}
+
+fn validate_click_thresholds(thresholds: &[i32]) -> Result<(), ValidationError> {
+    if thresholds.iter().all(|threshold| *threshold >= 1) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("click_thresholds"))
+    }
+}
+
+/// A webhook that is told about events of a link. Only the owners of the link can see them,
+/// the secret included.
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscription {
+    pub id: i32,
+    #[serde(skip, default)]
+    pub link_id: i32,
+    pub url: String,
+    /// The deliveries are signed with this secret
+    pub secret: String,
+    pub events: Vec<LinkEvent>,
+    pub click_thresholds: Vec<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookSubscription {
+    pub fn wants(&self, event: LinkEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// An event on its way to a webhook, kept as a log once it was delivered or given up on.
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[cfg_attr(feature = "diesel", diesel(table_name = webhook_deliveries))]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: i32,
+    #[serde(skip, default)]
+    pub link_id: i32,
+    /// None once the subscription was removed
+    pub subscription_id: Option<i32>,
+    pub event: LinkEvent,
+    pub url: String,
+    #[serde(skip, default)]
+    pub secret: String,
+    /// The signed json body
+    pub payload: String,
+    pub attempts: i32,
+    /// None once the event was delivered or given up on
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A single redirect of a link.
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Click {
+    pub id: i32,
+    #[serde(skip, default)]
+    pub link_id: i32,
+    pub target_id: i32,
+    /// The participant id of the signed url that was used, if there was one
+    pub participant: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// The group code the visitor came with, for links with group affinity
+    pub group_code: Option<String>,
+}
+
+/// A click together with the target it went to, so exports don't need to look the target up.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickDto {
+    #[serde(flatten)]
+    pub click: Click,
+    pub target_url: String,
+    pub label: Option<String>,
+    pub color: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetGroupsDto {
+    pub target_id: i32,
+    pub groups: i64,
+}
+
+#[derive(Serialize, Deserialize, Validate, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MintSignedUrlsDto {
+    /// One url is minted for each participant id
+    #[serde(default)]
+    #[validate(length(max = 10000))]
+    pub participants: Vec<String>,
+    /// Number of additional urls without a participant id
+    #[serde(default)]
+    #[validate(range(max = 10000))]
+    pub anonymous: u32,
+    /// When the urls stop working, they are valid forever if this isn't set
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedUrlDto {
+    pub participant: Option<String>,
+    pub url: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -87,3 +525,286 @@ pub enum PaymentStatus {
     Succeeded,
     Failed,
 }
+
+/// Who can see the stats and targets of a link on its info page.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "diesel", derive(diesel_derive_enum::DbEnum))]
+#[cfg_attr(
+    feature = "diesel",
+    ExistingTypePath = "crate::schema::sql_types::LinkVisibility"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkVisibility {
+    /// Anyone with the link can see its stats and targets
+    #[default]
+    Public,
+    /// Anyone with the link can see its stats, but the targets are hidden
+    StatsOnly,
+    /// Only the owner or someone with the management token can see anything
+    Private,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateVisibilityDto {
+    pub visibility: LinkVisibility,
+}
+
+#[derive(Serialize, Deserialize, Validate, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdatePasswordDto {
+    /// The new password, or none to remove the password protection
+    #[validate(length(min = 1, max = 128))]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Validate, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolveDto {
+    /// Identifies the visitor for links with group affinity, the same key gets the same target
+    #[serde(default)]
+    #[validate(length(min = 1, max = 64))]
+    pub visitor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Validate, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagFraudDto {
+    /// Shown to visitors of the link instead of the redirect
+    #[serde(default)]
+    #[validate(length(min = 1, max = 500))]
+    pub reason: Option<String>,
+}
+
+/// How links are paid for on this instance.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentProviderKind {
+    #[default]
+    Stripe,
+    Disabled,
+    ManualApproval,
+}
+
+/// Instance settings the frontend needs to know about.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicConfig {
+    /// The url under which this instance is reachable, without a trailing slash
+    pub public_base_url: String,
+    pub payment_provider: PaymentProviderKind,
+    /// The price of a link that isn't whitelisted, if links cost anything
+    pub price: Option<Price>,
+    /// The sizes of the credit bundles that can be bought
+    pub credit_bundles: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Price {
+    /// Amount in the smallest unit of the currency, e.g. cents
+    pub amount: i64,
+    /// Lowercase ISO currency code
+    pub currency: String,
+}
+
+impl Display for Price {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{:02} {}",
+            self.amount / 100,
+            self.amount % 100,
+            self.currency.to_uppercase()
+        )
+    }
+}
+
+/// What creating a link with the given targets would cost.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QuoteDto {
+    pub whitelisted: bool,
+    /// The link would be paid with a credit of the account making the request
+    pub pays_with_credit: bool,
+    pub payment_required: bool,
+    pub approval_required: bool,
+    pub price: Option<Price>,
+    /// Targets that are on the blacklist, the link can't be created while there are any
+    pub blocked_targets: Vec<String>,
+}
+
+/// The body of failed api requests.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorDto {
+    /// Machine readable, e.g. `validation_failed` or `blocked_targets`
+    pub code: String,
+    pub message: String,
+    /// What's wrong with the fields of the request body, by their path like `targets[0].targetUrl`.
+    /// Checks spanning a whole object are reported as `__all__` of it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Vec<FieldErrorDto>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldErrorDto {
+    /// The check that failed, e.g. `url` or `length`
+    pub code: String,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "diesel", derive(diesel_derive_enum::DbEnum))]
+#[cfg_attr(
+    feature = "diesel",
+    ExistingTypePath = "crate::schema::sql_types::CreditReason"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditReason {
+    Purchase,
+    LinkCreation,
+}
+
+/// A single change to the credit balance of an account.
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[cfg_attr(feature = "diesel", diesel(table_name = credit_ledger))]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditLedgerEntry {
+    pub id: i32,
+    #[serde(skip, default)]
+    pub account_id: i32,
+    pub amount: i32,
+    pub reason: CreditReason,
+    #[serde(skip, default)]
+    pub purchase_id: Option<i32>,
+    #[serde(skip, default)]
+    pub link_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditsDto {
+    pub balance: i32,
+    pub history: Vec<CreditLedgerEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountCreatedDto {
+    /// The api token of the new account, it can't be retrieved again later
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuyCreditsDto {
+    /// Size of the bundle to buy, has to be one of the configured bundles
+    pub credits: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckoutDto {
+    pub url: String,
+}
+
+/// A custom domain links can be created on once its owner proved control over it.
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Domain {
+    pub id: i32,
+    pub host: String,
+    #[serde(skip, default)]
+    pub account_id: i32,
+    /// Has to be published in a `_hurlurl-challenge` TXT record or a challenge file
+    pub verification_token: String,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateDomainDto {
+    pub host: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "diesel", derive(diesel_derive_enum::DbEnum))]
+#[cfg_attr(
+    feature = "diesel",
+    ExistingTypePath = "crate::schema::sql_types::WorkspaceRole"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceRole {
+    /// Can do everything editors can and manage the members of the workspace
+    Owner,
+    /// Can create and manage the links of the workspace
+    Editor,
+    /// Can only see the links of the workspace and their stats
+    Viewer,
+}
+
+impl WorkspaceRole {
+    pub fn can_edit(&self) -> bool {
+        matches!(self, WorkspaceRole::Owner | WorkspaceRole::Editor)
+    }
+
+    pub fn can_manage_members(&self) -> bool {
+        *self == WorkspaceRole::Owner
+    }
+}
+
+/// A group of accounts that share ownership of links.
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Workspace {
+    pub id: i32,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A workspace together with the role the requesting account has in it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceDto {
+    #[serde(flatten)]
+    pub workspace: Workspace,
+    pub role: WorkspaceRole,
+}
+
+#[cfg_attr(feature = "diesel", derive(Queryable, Identifiable))]
+#[cfg_attr(feature = "diesel", diesel(primary_key(workspace_id, account_id)))]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceMember {
+    pub workspace_id: i32,
+    pub account_id: i32,
+    pub role: WorkspaceRole,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWorkspaceDto {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceRoleDto {
+    pub role: WorkspaceRole,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteDto {
+    /// Code the invited account accepts the invite with, it can't be retrieved again later
+    pub code: String,
+    pub role: WorkspaceRole,
+}
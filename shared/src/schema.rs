@@ -1,15 +1,92 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "credit_reason"))]
+    pub struct CreditReason;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "link_event"))]
+    pub struct LinkEvent;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "link_visibility"))]
+    pub struct LinkVisibility;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "payment_status"))]
     pub struct PaymentStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "workspace_role"))]
+    pub struct WorkspaceRole;
+}
+
+diesel::table! {
+    accounts (id) {
+        id -> Int4,
+        token_hash -> Text,
+        credits -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    clicks (id) {
+        id -> Int4,
+        link_id -> Int4,
+        target_id -> Int4,
+        participant -> Nullable<Text>,
+        created_at -> Timestamptz,
+        group_code -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::CreditReason;
+
+    credit_ledger (id) {
+        id -> Int4,
+        account_id -> Int4,
+        amount -> Int4,
+        reason -> CreditReason,
+        purchase_id -> Nullable<Int4>,
+        link_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+    }
 }
 
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::PaymentStatus;
 
+    credit_purchases (id) {
+        id -> Int4,
+        account_id -> Int4,
+        credits -> Int4,
+        stripe_session_id -> Text,
+        payment_status -> PaymentStatus,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    domains (id) {
+        id -> Int4,
+        host -> Varchar,
+        account_id -> Int4,
+        verification_token -> Text,
+        verified_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::PaymentStatus;
+    use super::sql_types::LinkVisibility;
+
     links (id) {
         id -> Int4,
         url -> Varchar,
@@ -20,6 +97,36 @@ diesel::table! {
         created_by_ip -> Nullable<Inet>,
         stripe_session_id -> Nullable<Text>,
         payment_status -> Nullable<PaymentStatus>,
+        payment_expires_at -> Nullable<Timestamptz>,
+        account_id -> Nullable<Int4>,
+        domain_id -> Nullable<Int4>,
+        workspace_id -> Nullable<Int4>,
+        management_token_hash -> Nullable<Text>,
+        visibility -> LinkVisibility,
+        password_hash -> Nullable<Text>,
+        signing_secret -> Nullable<Text>,
+        proxy -> Bool,
+        group_affinity -> Bool,
+        picker -> Bool,
+        target_source -> Nullable<Text>,
+        target_source_checked_at -> Nullable<Timestamptz>,
+        target_source_error -> Nullable<Text>,
+        provisioning_webhook -> Nullable<Text>,
+        provisioning_secret -> Nullable<Text>,
+        provisioning_threshold -> Int4,
+        has_webhooks -> Bool,
+    }
+}
+
+diesel::table! {
+    provisioning_events (id) {
+        id -> Int4,
+        link_id -> Int4,
+        remaining_capacity -> Int4,
+        attempts -> Int4,
+        added_targets -> Int4,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -29,9 +136,113 @@ diesel::table! {
         link_id -> Int4,
         target_url -> Varchar,
         redirects -> Int4,
+        label -> Nullable<Text>,
+        description -> Nullable<Text>,
+        color -> Nullable<Text>,
+        weight -> Int4,
+        capacity -> Nullable<Int4>,
+        active -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::LinkEvent;
+
+    webhook_deliveries (id) {
+        id -> Int4,
+        link_id -> Int4,
+        subscription_id -> Nullable<Int4>,
+        event -> LinkEvent,
+        url -> Text,
+        secret -> Text,
+        payload -> Text,
+        attempts -> Int4,
+        next_attempt_at -> Nullable<Timestamptz>,
+        delivered_at -> Nullable<Timestamptz>,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::LinkEvent;
+
+    webhook_subscriptions (id) {
+        id -> Int4,
+        link_id -> Int4,
+        url -> Text,
+        secret -> Text,
+        events -> Array<LinkEvent>,
+        click_thresholds -> Array<Int4>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::WorkspaceRole;
+
+    workspace_invites (id) {
+        id -> Int4,
+        workspace_id -> Int4,
+        code_hash -> Text,
+        role -> WorkspaceRole,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::WorkspaceRole;
+
+    workspace_members (workspace_id, account_id) {
+        workspace_id -> Int4,
+        account_id -> Int4,
+        role -> WorkspaceRole,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    workspaces (id) {
+        id -> Int4,
+        name -> Text,
+        created_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(clicks -> links (link_id));
+diesel::joinable!(clicks -> targets (target_id));
+diesel::joinable!(credit_ledger -> accounts (account_id));
+diesel::joinable!(credit_ledger -> credit_purchases (purchase_id));
+diesel::joinable!(credit_ledger -> links (link_id));
+diesel::joinable!(credit_purchases -> accounts (account_id));
+diesel::joinable!(domains -> accounts (account_id));
+diesel::joinable!(links -> accounts (account_id));
+diesel::joinable!(links -> domains (domain_id));
+diesel::joinable!(links -> workspaces (workspace_id));
+diesel::joinable!(provisioning_events -> links (link_id));
 diesel::joinable!(targets -> links (link_id));
+diesel::joinable!(workspace_invites -> workspaces (workspace_id));
+diesel::joinable!(workspace_members -> accounts (account_id));
+diesel::joinable!(workspace_members -> workspaces (workspace_id));
+diesel::joinable!(webhook_deliveries -> webhook_subscriptions (subscription_id));
+diesel::joinable!(webhook_subscriptions -> links (link_id));
 
-diesel::allow_tables_to_appear_in_same_query!(links, targets,);
+diesel::allow_tables_to_appear_in_same_query!(
+    accounts,
+    clicks,
+    credit_ledger,
+    credit_purchases,
+    domains,
+    links,
+    provisioning_events,
+    targets,
+    webhook_deliveries,
+    webhook_subscriptions,
+    workspace_invites,
+    workspace_members,
+    workspaces,
+);
@@ -0,0 +1,255 @@
+use std::fmt::{Display, Formatter};
+
+/// A link can't get more targets than this from templates, so a typo like `{1..100000}`
+/// doesn't create a giant link.
+pub const MAX_EXPANDED_TARGETS: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A `{` without a closing `}`
+    Unclosed,
+    /// A range whose bounds aren't numbers, like `{a..z}`
+    InvalidRange,
+    /// The templates expand to more than [MAX_EXPANDED_TARGETS] targets
+    TooManyTargets,
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::Unclosed => write!(f, "Missing closing }}"),
+            TemplateError::InvalidRange => write!(f, "Ranges need numbers, like {{1..20}}"),
+            TemplateError::TooManyTargets => {
+                write!(f, "Expands to more than {MAX_EXPANDED_TARGETS} targets")
+            }
+        }
+    }
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    /// `{1..20}`, bounds with leading zeros like `{01..20}` pad all values to the same width
+    Range {
+        start: u64,
+        end: u64,
+        width: usize,
+    },
+    /// `{eu,us,asia}`
+    List(Vec<&'a str>),
+}
+
+impl Part<'_> {
+    fn len(&self) -> u64 {
+        match self {
+            Part::Text(_) => 1,
+            Part::Range { start, end, .. } => start.abs_diff(*end).saturating_add(1),
+            Part::List(values) => values.len() as u64,
+        }
+    }
+
+    fn value(&self, index: u64) -> String {
+        match self {
+            Part::Text(text) => text.to_string(),
+            Part::Range { start, end, width } => {
+                let value = if start <= end {
+                    start + index
+                } else {
+                    start - index
+                };
+                format!("{value:0width$}")
+            }
+            Part::List(values) => values[index as usize].to_string(),
+        }
+    }
+}
+
+/// Braces without `..` or `,` inside aren't a template and are kept as they are.
+fn parse_group(group: &str) -> Result<Option<Part<'_>>, TemplateError> {
+    if let Some((start, end)) = group.split_once("..") {
+        let padded = |bound: &str| bound.len() > 1 && bound.starts_with('0');
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+
+        Ok(Some(Part::Range {
+            start: start.parse().map_err(|_| TemplateError::InvalidRange)?,
+            end: end.parse().map_err(|_| TemplateError::InvalidRange)?,
+            width,
+        }))
+    } else if group.contains(',') {
+        Ok(Some(Part::List(group.split(',').collect())))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Part<'_>>, TemplateError> {
+    let mut parts = vec![];
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        let close = open + rest[open..].find('}').ok_or(TemplateError::Unclosed)?;
+
+        parts.push(Part::Text(&rest[..open]));
+        parts.push(parse_group(&rest[open + 1..close])?.unwrap_or(Part::Text(&rest[open..=close])));
+        rest = &rest[close + 1..];
+    }
+    parts.push(Part::Text(rest));
+
+    Ok(parts)
+}
+
+/// Expands a target url template like `https://game.example/room-{1..20}` or
+/// `https://{eu,us,asia}.game.example` to one url per value, in order. Templates with several
+/// groups expand to every combination, urls without a template expand to themselves.
+pub fn expand(template: &str) -> Result<Vec<String>, TemplateError> {
+    let parts = parse(template)?;
+
+    // Checked before expanding anything, `{1..99999999999}` must not allocate
+    parts
+        .iter()
+        .try_fold(1u64, |count, part| {
+            count
+                .checked_mul(part.len())
+                .filter(|count| *count <= MAX_EXPANDED_TARGETS as u64)
+        })
+        .ok_or(TemplateError::TooManyTargets)?;
+
+    let mut expanded = vec![String::new()];
+    for part in &parts {
+        expanded = expanded
+            .iter()
+            .flat_map(|prefix| {
+                (0..part.len()).map(move |index| format!("{prefix}{}", part.value(index)))
+            })
+            .collect();
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(template: &str) -> Vec<String> {
+        expand(template).unwrap()
+    }
+
+    #[test]
+    fn ranges_expand_to_every_number() {
+        assert_eq!(
+            expanded("https://game.example/room-{1..3}"),
+            [
+                "https://game.example/room-1",
+                "https://game.example/room-2",
+                "https://game.example/room-3",
+            ]
+        );
+        assert_eq!(
+            expanded("https://game.example/{3..1}"),
+            [
+                "https://game.example/3",
+                "https://game.example/2",
+                "https://game.example/1",
+            ]
+        );
+    }
+
+    #[test]
+    fn leading_zeros_pad_every_value() {
+        let urls = expanded("https://game.example/room-{01..10}");
+
+        assert_eq!(urls.len(), 10);
+        assert_eq!(urls[0], "https://game.example/room-01");
+        assert_eq!(urls[8], "https://game.example/room-09");
+        assert_eq!(urls[9], "https://game.example/room-10");
+        assert_eq!(
+            expanded("https://game.example/{8..010}"),
+            [
+                "https://game.example/008",
+                "https://game.example/009",
+                "https://game.example/010",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_expand_to_every_value() {
+        assert_eq!(
+            expanded("https://{a,b}.game.example"),
+            ["https://a.game.example", "https://b.game.example"]
+        );
+    }
+
+    #[test]
+    fn several_groups_expand_to_every_combination() {
+        assert_eq!(
+            expanded("https://{eu,us}.game.example/{1..2}"),
+            [
+                "https://eu.game.example/1",
+                "https://eu.game.example/2",
+                "https://us.game.example/1",
+                "https://us.game.example/2",
+            ]
+        );
+    }
+
+    #[test]
+    fn braces_without_a_template_are_kept() {
+        assert_eq!(expanded("https://game.example"), ["https://game.example"]);
+        assert_eq!(
+            expanded("https://game.example/{id}/{1..2}"),
+            ["https://game.example/{id}/1", "https://game.example/{id}/2"]
+        );
+        assert_eq!(
+            expanded("https://game.example/}"),
+            ["https://game.example/}"]
+        );
+    }
+
+    #[test]
+    fn unbalanced_braces_are_rejected() {
+        assert_eq!(
+            expand("https://game.example/room-{1..3"),
+            Err(TemplateError::Unclosed)
+        );
+        assert_eq!(
+            expand("https://game.example/{1..2}/{a,b"),
+            Err(TemplateError::Unclosed)
+        );
+    }
+
+    #[test]
+    fn ranges_need_numbers() {
+        assert_eq!(
+            expand("https://game.example/{a..z}"),
+            Err(TemplateError::InvalidRange)
+        );
+        assert_eq!(
+            expand("https://game.example/{1..}"),
+            Err(TemplateError::InvalidRange)
+        );
+    }
+
+    #[test]
+    fn expansion_is_bounded() {
+        let max = format!("https://game.example/{{1..{MAX_EXPANDED_TARGETS}}}");
+        assert_eq!(expanded(&max).len(), MAX_EXPANDED_TARGETS);
+
+        let over = format!("https://game.example/{{0..{MAX_EXPANDED_TARGETS}}}");
+        assert_eq!(expand(&over), Err(TemplateError::TooManyTargets));
+
+        // Every group is fine on its own, their combinations aren't
+        assert_eq!(
+            expand("https://game.example/{1..20}/{1..30}"),
+            Err(TemplateError::TooManyTargets)
+        );
+        assert_eq!(
+            expand("https://game.example/{0..18446744073709551615}"),
+            Err(TemplateError::TooManyTargets)
+        );
+    }
+}
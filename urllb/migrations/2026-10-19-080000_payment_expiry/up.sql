@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+alter table links
+    add column payment_expires_at timestamptz;
+
+--- stripe checkout sessions expire after 24 hours by default
+update links
+set payment_expires_at = now() + interval '1 day'
+where payment_status = 'pending'
+  and stripe_session_id is not null;
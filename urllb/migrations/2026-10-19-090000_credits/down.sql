@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+drop table credit_ledger;
+drop type credit_reason;
+drop table credit_purchases;
+
+alter table links
+    drop column account_id;
+
+drop table accounts;
@@ -0,0 +1,39 @@
+-- Your SQL goes here
+
+create table accounts
+(
+    id serial primary key,
+    --- sha256 of the api token, the token itself is only shown once
+    token_hash text unique not null,
+    --- the current balance, always equal to the sum of the account's ledger entries
+    credits integer not null default 0 check (credits >= 0),
+    created_at timestamptz not null default now()
+);
+
+alter table links
+    add column account_id integer references accounts(id) on delete set null;
+
+create table credit_purchases
+(
+    id serial primary key,
+    account_id integer references accounts(id) on delete cascade not null,
+    credits integer not null,
+    stripe_session_id text not null,
+    payment_status payment_status not null default 'pending',
+    created_at timestamptz not null default now()
+);
+
+create type credit_reason as enum ('purchase', 'link_creation');
+
+create table credit_ledger
+(
+    id serial primary key,
+    account_id integer references accounts(id) on delete cascade not null,
+    amount integer not null,
+    reason credit_reason not null,
+    purchase_id integer references credit_purchases(id) on delete set null,
+    link_id integer references links(id) on delete set null,
+    created_at timestamptz not null default now()
+);
+
+create index credit_ledger_account_id on credit_ledger(account_id);
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+
+drop index links_domain_url;
+drop index links_url_without_domain;
+delete from targets where link_id in (select id from links where domain_id is not null);
+delete from links where domain_id is not null;
+alter table links
+    add constraint links_url_key unique (url);
+
+alter table links
+    drop column domain_id;
+
+drop table domains;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+
+create table domains
+(
+    id serial primary key,
+    host varchar not null,
+    account_id integer references accounts(id) on delete cascade not null,
+    --- has to be published in a dns txt record or a challenge file to prove ownership
+    verification_token text not null,
+    verified_at timestamptz,
+    created_at timestamptz not null default now()
+);
+
+--- anyone can claim a host, but only one account can verify it
+create unique index domains_verified_host on domains(host) where verified_at is not null;
+
+alter table links
+    add column domain_id integer references domains(id);
+
+--- slugs are unique per domain, links without a domain live on the public base url
+alter table links
+    drop constraint links_url_key;
+create unique index links_url_without_domain on links(url) where domain_id is null;
+create unique index links_domain_url on links(domain_id, url) where domain_id is not null;
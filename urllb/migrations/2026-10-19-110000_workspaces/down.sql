@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+alter table links
+    drop column workspace_id;
+
+drop table workspace_invites;
+drop table workspace_members;
+drop table workspaces;
+drop type workspace_role;
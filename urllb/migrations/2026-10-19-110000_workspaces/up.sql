@@ -0,0 +1,36 @@
+-- Your SQL goes here
+
+create type workspace_role as enum ('owner', 'editor', 'viewer');
+
+create table workspaces
+(
+    id serial primary key,
+    name text not null,
+    created_at timestamptz not null default now()
+);
+
+create table workspace_members
+(
+    workspace_id integer references workspaces(id) on delete cascade not null,
+    account_id integer references accounts(id) on delete cascade not null,
+    role workspace_role not null,
+    created_at timestamptz not null default now(),
+    primary key (workspace_id, account_id)
+);
+
+create index workspace_members_account_id on workspace_members(account_id);
+
+create table workspace_invites
+(
+    id serial primary key,
+    workspace_id integer references workspaces(id) on delete cascade not null,
+    --- sha256 of the invite code, the code itself is only shown once
+    code_hash text unique not null,
+    role workspace_role not null,
+    created_at timestamptz not null default now()
+);
+
+alter table links
+    add column workspace_id integer references workspaces(id) on delete set null;
+
+create index links_workspace_id on links(workspace_id);
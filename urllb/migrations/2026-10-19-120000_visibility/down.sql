@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+alter table links
+    drop column visibility,
+    drop column management_token_hash;
+
+drop type link_visibility;
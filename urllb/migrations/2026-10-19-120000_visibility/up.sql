@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+create type link_visibility as enum ('public', 'stats_only', 'private');
+
+alter table links
+    --- sha256 of the token that allows managing the link without an account
+    add column management_token_hash text,
+    add column visibility link_visibility not null default 'public';
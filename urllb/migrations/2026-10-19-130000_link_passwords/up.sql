@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+alter table links
+    --- argon2 hash of the password visitors have to enter before they are redirected
+    add column password_hash text;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+drop table clicks;
+
+alter table links
+    drop column signing_secret;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+alter table links
+    --- secret signed urls of the link are verified with, links without one can be opened by anyone
+    add column signing_secret text;
+
+create table clicks
+(
+    id serial primary key,
+    link_id integer references links(id) on delete cascade not null,
+    target_id integer references targets(id) on delete cascade not null,
+    --- participant id of the signed url the click came from
+    participant text,
+    created_at timestamptz not null default now()
+);
+
+create index clicks_link_id on clicks(link_id);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+alter table links
+    --- forward requests to the targets instead of redirecting to them
+    add column proxy boolean not null default false;
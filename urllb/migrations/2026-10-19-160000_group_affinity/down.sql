@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+alter table clicks
+    drop column group_code;
+
+alter table links
+    drop column group_affinity;
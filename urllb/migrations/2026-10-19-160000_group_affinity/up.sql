@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+alter table links
+    --- visitors with the same group code are sent to the same target
+    add column group_affinity boolean not null default false;
+
+alter table clicks
+    add column group_code text;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+alter table links
+    --- visitors choose a target on a landing page instead of being redirected at random
+    add column picker boolean not null default false;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+alter table targets
+    drop column color,
+    drop column description,
+    drop column label;
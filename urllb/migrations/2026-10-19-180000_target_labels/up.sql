@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+alter table targets
+    add column label text,
+    add column description text,
+    --- hex color like #1f2937
+    add column color text;
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+
+alter table targets
+    drop column active,
+    drop column capacity,
+    drop column weight;
+
+alter table links
+    drop column target_source_error,
+    drop column target_source_checked_at,
+    drop column target_source;
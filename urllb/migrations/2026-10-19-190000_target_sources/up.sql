@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+alter table links
+    --- url of a json list the targets of the link are loaded from
+    add column target_source text,
+    add column target_source_checked_at timestamptz,
+    --- why the last update from the target source failed, the previous targets are kept
+    add column target_source_error text;
+
+alter table targets
+    add column weight int4 not null default 1,
+    --- redirects after which the target is full and no longer chosen
+    add column capacity int4,
+    --- targets that disappeared from the target source are kept for their stats
+    add column active bool not null default true;
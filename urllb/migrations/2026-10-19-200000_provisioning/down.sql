@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+drop table provisioning_events;
+
+alter table links
+    drop column provisioning_threshold,
+    drop column provisioning_secret,
+    drop column provisioning_webhook;
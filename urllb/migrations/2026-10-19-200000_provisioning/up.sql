@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+alter table links
+    --- url that is asked for new targets when the link runs out of capacity
+    add column provisioning_webhook text,
+    --- secret the provisioning requests are signed with
+    add column provisioning_secret text,
+    --- remaining capacity below which new targets are requested
+    add column provisioning_threshold int4 not null default 1;
+
+create table provisioning_events
+(
+    id serial primary key,
+    link_id integer references links(id) on delete cascade not null,
+    remaining_capacity int4 not null,
+    attempts int4 not null,
+    added_targets int4 not null default 0,
+    --- why the webhook failed after all attempts
+    error text,
+    created_at timestamptz not null default now()
+);
+
+create index provisioning_events_link_id on provisioning_events(link_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+drop table webhook_deliveries;
+drop table webhook_subscriptions;
+drop type link_event;
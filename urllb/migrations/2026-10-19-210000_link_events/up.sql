@@ -0,0 +1,41 @@
+-- Your SQL goes here
+
+create type link_event as enum ('created', 'paid', 'fraud', 'target_full', 'click_threshold', 'expired');
+
+create table webhook_subscriptions
+(
+    id serial primary key,
+    link_id integer references links(id) on delete cascade not null,
+    url text not null,
+    --- secret the deliveries are signed with
+    secret text not null,
+    --- events that are delivered, all of them if empty
+    events link_event[] not null default '{}',
+    --- redirect counts of the link that trigger a click_threshold event
+    click_thresholds int4[] not null default '{}',
+    created_at timestamptz not null default now()
+);
+
+create index webhook_subscriptions_link_id on webhook_subscriptions(link_id);
+
+create table webhook_deliveries
+(
+    id serial primary key,
+    --- no foreign key, the expired event of a link is delivered after the link is deleted
+    link_id integer not null,
+    subscription_id integer references webhook_subscriptions(id) on delete set null,
+    event link_event not null,
+    url text not null,
+    secret text not null,
+    payload text not null,
+    attempts int4 not null default 0,
+    --- null once the event was delivered or given up on
+    next_attempt_at timestamptz default now(),
+    delivered_at timestamptz,
+    --- why the last attempt failed
+    error text,
+    created_at timestamptz not null default now()
+);
+
+create index webhook_deliveries_link_id on webhook_deliveries(link_id);
+create index webhook_deliveries_next_attempt_at on webhook_deliveries(next_attempt_at) where next_attempt_at is not null;
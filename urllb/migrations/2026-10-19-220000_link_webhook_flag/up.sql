@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+alter table links
+    --- set once the link gets a webhook subscription, so redirects of links without any skip the event queries
+    add column has_webhooks bool not null default false;
+
+update links
+set has_webhooks = true
+where exists(select from webhook_subscriptions where webhook_subscriptions.link_id = links.id);
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+
+use shared::schema::links;
+
+use crate::account::{hash_token, AccountAuth};
+use crate::db::{Connection, Pool};
+use crate::domains::Namespace;
+use crate::error::{self, Api, ApiError, Error};
+use crate::models::{Account, Link, UpdateVisibilityDto};
+use crate::password::is_unlocked;
+use crate::service::get_link_and_targets;
+use crate::signing::{self, SignedQuery};
+use crate::workspace::link_role;
+use crate::{Config, Params};
+
+const MANAGEMENT_TOKEN_HEADER: &str = "x-management-token";
+
+/// Extractor for the management token of a link, sent in the `X-Management-Token` header. It
+/// isn't accepted in the query, where it would end up in access logs, traces and referrers.
+pub struct ManagementToken(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ManagementToken {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, StatusCode> {
+        Ok(ManagementToken(
+            parts
+                .headers
+                .get(MANAGEMENT_TOKEN_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        ))
+    }
+}
+
+/// What a visitor brings along to get past the checks of a visit, the unlock cookie of a
+/// password protected link and the signature of a signed url.
+pub struct VisitorAccess {
+    headers: HeaderMap,
+    signed_query: SignedQuery,
+    config: Arc<Config>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for VisitorAccess {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, ApiError> {
+        let Extension(config) = Extension::<Arc<Config>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let Query(signed_query) = Query::<SignedQuery>::try_from_uri(&parts.uri)?;
+
+        Ok(VisitorAccess {
+            headers: parts.headers.clone(),
+            signed_query,
+            config,
+        })
+    }
+}
+
+impl VisitorAccess {
+    /// Whether the visitor could visit the link, and so may see its targets.
+    pub fn allows(&self, link: &Link) -> bool {
+        is_unlocked(&self.headers, &self.config.cookie_secret, link)
+            && signing::verify(link, &self.signed_query).is_ok()
+    }
+}
+
+impl ManagementToken {
+    fn matches(&self, link: &Link) -> bool {
+        match (&self.0, &link.management_token_hash) {
+            (Some(token), Some(hash)) => hash_token(token) == *hash,
+            _ => false,
+        }
+    }
+
+    fn is_present(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// Whether the request may see everything about the link, regardless of its visibility.
+pub async fn can_view<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    account: Option<&Account>,
+    token: &ManagementToken,
+) -> error::Result<bool> {
+    Ok(token.matches(link) || link_role(connection, link, account).await?.is_some())
+}
+
+/// Checks that the request may change the link, either with its management token or as
+/// the owner or an editor. Requests without any credentials are answered with a 401.
+pub async fn require_manage<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    account: Option<&Account>,
+    token: &ManagementToken,
+) -> Result<(), StatusCode> {
+    if token.matches(link) {
+        return Ok(());
+    }
+
+    if account.is_none() && !token.is_present() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    match link_role(connection, link, account).await? {
+        Some(role) if role.can_edit() => Ok(()),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+/// Whether the request gets a 401 or 403 when it isn't allowed to see a link.
+pub fn denied(account: Option<&Account>, token: &ManagementToken) -> StatusCode {
+    if account.is_none() && !token.is_present() {
+        StatusCode::UNAUTHORIZED
+    } else {
+        StatusCode::FORBIDDEN
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_visibility(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+    Api(Json(body)): Api<Json<UpdateVisibilityDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    diesel::update(&link)
+        .set(links::visibility.eq(body.visibility))
+        .execute(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
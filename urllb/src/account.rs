@@ -0,0 +1,405 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use nanoid::nanoid;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use shared::schema::{accounts, credit_ledger, credit_purchases};
+
+use crate::admin::bearer_token;
+use crate::db::{Connection, Pool};
+use crate::error::{self, Api, ApiError, Error};
+use crate::models::{
+    Account, AccountCreatedDto, BuyCreditsDto, CheckoutDto, CreditLedgerEntry, CreditPurchase,
+    CreditReason, CreditsDto, NewAccount, NewCreditLedgerEntry, NewCreditPurchase, PaymentStatus,
+};
+use crate::payment::Payments;
+use crate::Config;
+
+/// A bundle of credits that can be bought with a single checkout.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct CreditBundle {
+    pub credits: i32,
+    pub price_id: String,
+}
+
+impl FromStr for CreditBundle {
+    type Err = String;
+
+    /// Parses bundles in the form of `credits:stripe_price_id`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (credits, price_id) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid credit bundle {s}, expected credits:price_id"))?;
+
+        Ok(CreditBundle {
+            credits: credits
+                .parse()
+                .map_err(|_| format!("Invalid credit amount in bundle {s}"))?,
+            price_id: price_id.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for CreditBundle {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Extractor for the account belonging to the bearer token of the request.
+pub struct AccountAuth(pub Account);
+
+#[async_trait]
+impl FromRequestParts<Pool> for AccountAuth {
+    type Rejection = ApiError;
+
+    #[tracing::instrument(skip_all)]
+    async fn from_request_parts(parts: &mut Parts, pool: &Pool) -> Result<Self, ApiError> {
+        let token = bearer_token(&parts.headers).ok_or_else(invalid_account_token)?;
+
+        let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+        let account = accounts::table
+            .filter(accounts::token_hash.eq(hash_token(token)))
+            .first::<Account>(&mut connection)
+            .await
+            .optional()
+            .map_err(Error::DieselError)?
+            .ok_or_else(invalid_account_token)?;
+
+        Ok(AccountAuth(account))
+    }
+}
+
+fn invalid_account_token() -> ApiError {
+    ApiError::new(
+        StatusCode::UNAUTHORIZED,
+        "invalid_account_token",
+        "The request needs the bearer token of an account",
+    )
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn create_account(State(pool): State<Pool>) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let token = nanoid!(32);
+
+    diesel::insert_into(accounts::table)
+        .values(NewAccount {
+            token_hash: &hash_token(&token),
+        })
+        .execute(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok((StatusCode::CREATED, Json(AccountCreatedDto { token })))
+}
+
+/// Returns the credit balance of the account and the full history of changes to it.
+#[tracing::instrument(skip_all)]
+pub async fn credits(
+    AccountAuth(account): AccountAuth,
+    State(pool): State<Pool>,
+    Extension(payments): Extension<Payments>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    settle_credit_purchases(&mut connection, &payments, account.id).await?;
+
+    let balance = accounts::table
+        .find(account.id)
+        .select(accounts::credits)
+        .first::<i32>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    let history = credit_ledger::table
+        .filter(credit_ledger::account_id.eq(account.id))
+        .order(credit_ledger::id.desc())
+        .load::<CreditLedgerEntry>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(Json(CreditsDto { balance, history }))
+}
+
+/// Starts the checkout for one of the configured credit bundles.
+#[tracing::instrument(skip_all)]
+pub async fn buy_credits(
+    AccountAuth(account): AccountAuth,
+    State(pool): State<Pool>,
+    Extension(payments): Extension<Payments>,
+    Extension(config): Extension<Arc<Config>>,
+    Api(Json(body)): Api<Json<BuyCreditsDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let bundle = config
+        .credit_bundles
+        .iter()
+        .find(|bundle| bundle.credits == body.credits)
+        .ok_or_else(|| {
+            ApiError::bad_request(
+                "unknown_bundle",
+                "There is no bundle with this many credits",
+            )
+            .with_field("credits", "unknown_bundle")
+        })?;
+
+    let checkout = payments
+        .start_credit_payment(&bundle.price_id)
+        .await?
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                "credits_unavailable",
+                "Credits can't be bought with the configured payment provider",
+            )
+        })?;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    diesel::insert_into(credit_purchases::table)
+        .values(NewCreditPurchase {
+            account_id: account.id,
+            credits: bundle.credits,
+            stripe_session_id: &checkout.session_id,
+        })
+        .execute(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(Json(CheckoutDto { url: checkout.url }))
+}
+
+/// Checks all pending credit purchases of the account and adds the credits of completed ones.
+#[tracing::instrument(skip_all)]
+pub async fn settle_credit_purchases<'c>(
+    connection: &mut Connection<'c>,
+    payments: &Payments,
+    account_id: i32,
+) -> error::Result<()> {
+    let pending = credit_purchases::table
+        .filter(credit_purchases::account_id.eq(account_id))
+        .filter(credit_purchases::payment_status.eq(PaymentStatus::Pending))
+        .load::<CreditPurchase>(connection)
+        .await?;
+
+    for purchase in pending {
+        let status = payments
+            .check_credit_payment(&purchase.stripe_session_id)
+            .await?;
+
+        if status != PaymentStatus::Pending {
+            complete_credit_purchase(connection, &purchase, status).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn complete_credit_purchase<'c>(
+    connection: &mut Connection<'c>,
+    purchase: &CreditPurchase,
+    status: PaymentStatus,
+) -> error::Result<()> {
+    let purchase = purchase.clone();
+
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                // Only the request that moves the purchase out of pending gets to add the
+                // credits, so concurrent requests can't credit the same purchase twice
+                let updated = diesel::update(
+                    credit_purchases::table
+                        .find(purchase.id)
+                        .filter(credit_purchases::payment_status.eq(PaymentStatus::Pending)),
+                )
+                .set(credit_purchases::payment_status.eq(&status))
+                .execute(connection)
+                .await?;
+
+                if updated == 0 || status != PaymentStatus::Succeeded {
+                    return Ok(());
+                }
+
+                diesel::update(accounts::table.find(purchase.account_id))
+                    .set(accounts::credits.eq(accounts::credits + purchase.credits))
+                    .execute(connection)
+                    .await?;
+
+                diesel::insert_into(credit_ledger::table)
+                    .values(NewCreditLedgerEntry {
+                        account_id: purchase.account_id,
+                        amount: purchase.credits,
+                        reason: CreditReason::Purchase,
+                        purchase_id: Some(purchase.id),
+                        link_id: None,
+                    })
+                    .execute(connection)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::{CreateLinkDto, Link};
+    use crate::service::{create_link_with_credit, LinkOwner};
+    use crate::test_support::{create_test_account, json_response, pool, SessionPayments};
+
+    async fn account(pool: &Pool, credits: i32) -> Account {
+        let account = create_test_account(pool).await;
+
+        let mut connection = pool.get().await.unwrap();
+        diesel::update(&account)
+            .set(accounts::credits.eq(credits))
+            .get_result::<Account>(&mut connection)
+            .await
+            .unwrap()
+    }
+
+    async fn purchase(pool: &Pool, account: &Account, credits: i32, session: &str) {
+        let mut connection = pool.get().await.unwrap();
+        diesel::insert_into(credit_purchases::table)
+            .values(NewCreditPurchase {
+                account_id: account.id,
+                credits,
+                stripe_session_id: &format!("{session}-{}", nanoid!()),
+            })
+            .execute(&mut connection)
+            .await
+            .unwrap();
+    }
+
+    async fn credits_of(pool: &Pool, account: &Account) -> CreditsDto {
+        let payments: Payments = Arc::new(SessionPayments);
+        let response = credits(
+            AccountAuth(account.clone()),
+            State(pool.clone()),
+            Extension(payments),
+        )
+        .await
+        .map(IntoResponse::into_response)
+        .unwrap();
+
+        let (status, credits) = json_response::<CreditsDto>(response).await;
+        assert_eq!(status, StatusCode::OK);
+        credits
+    }
+
+    fn reasons(credits: &CreditsDto) -> Vec<(CreditReason, i32)> {
+        credits
+            .history
+            .iter()
+            .map(|entry| (entry.reason, entry.amount))
+            .collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn completed_purchases_are_credited_once() {
+        let pool = pool().await;
+        let account = account(&pool, 0).await;
+        purchase(&pool, &account, 10, "paid").await;
+        purchase(&pool, &account, 50, "failed").await;
+        purchase(&pool, &account, 25, "open").await;
+
+        let first = credits_of(&pool, &account).await;
+        assert_eq!(first.balance, 10);
+        assert_eq!(reasons(&first), [(CreditReason::Purchase, 10)]);
+
+        // Checking again doesn't credit the same purchase twice
+        let second = credits_of(&pool, &account).await;
+        assert_eq!(second.balance, 10);
+        assert_eq!(second.history.len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn purchases_that_cant_be_checked_stay_pending() {
+        let pool = pool().await;
+        let account = account(&pool, 0).await;
+        purchase(&pool, &account, 10, "broken").await;
+
+        let payments: Payments = Arc::new(SessionPayments);
+        let mut connection = pool.get().await.unwrap();
+        assert!(
+            settle_credit_purchases(&mut connection, &payments, account.id)
+                .await
+                .is_err()
+        );
+
+        let pending = credit_purchases::table
+            .filter(credit_purchases::account_id.eq(account.id))
+            .select(credit_purchases::payment_status)
+            .first::<PaymentStatus>(&mut connection)
+            .await
+            .unwrap();
+        assert_eq!(pending, PaymentStatus::Pending);
+    }
+
+    async fn create_with_credit(pool: &Pool, account: &Account) -> Option<Link> {
+        let create: CreateLinkDto =
+            serde_json::from_value(json!({ "targets": [{ "targetUrl": "https://example.com" }] }))
+                .unwrap();
+
+        let mut connection = pool.get().await.unwrap();
+        create_link_with_credit(
+            &mut connection,
+            &create,
+            &nanoid!(12),
+            "127.0.0.1/32".parse().unwrap(),
+            account.id,
+            LinkOwner {
+                account: None,
+                domain: None,
+                management_token_hash: &hash_token(&nanoid!(32)),
+            },
+            None,
+        )
+        .await
+        .unwrap()
+        .map(|(link, _)| link)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn links_use_up_one_credit_each() {
+        let pool = pool().await;
+        let account = account(&pool, 1).await;
+
+        let link = create_with_credit(&pool, &account).await.unwrap();
+        assert_eq!(link.account_id, Some(account.id));
+        assert!(create_with_credit(&pool, &account).await.is_none());
+
+        let credits = credits_of(&pool, &account).await;
+        assert_eq!(credits.balance, 0);
+        assert_eq!(reasons(&credits), [(CreditReason::LinkCreation, -1)]);
+    }
+}
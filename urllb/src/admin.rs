@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Path, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use validator::Validate;
+
+use shared::PaymentStatus;
+
+use crate::db::Pool;
+use crate::domains::Namespace;
+use crate::error::{Api, ApiError, Error};
+use crate::models::FlagFraudDto;
+use crate::service::{
+    flag_link_fraud, get_link_and_targets, get_links_with_payment_status, into_link_dtos,
+    set_link_payment_status,
+};
+use crate::{Config, Params};
+
+/// Extractor that only succeeds if the request carries the configured admin token
+/// as a bearer token.
+pub struct Admin;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(config) = Extension::<Arc<Config>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let admin_token = config.admin_token.as_deref().ok_or(StatusCode::NOT_FOUND)?;
+
+        if bearer_token(&parts.headers) == Some(admin_token) {
+            Ok(Admin)
+        } else {
+            Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_admin_token",
+                "The request needs the admin token as bearer token",
+            ))
+        }
+    }
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Lists all links that are waiting for a payment or an approval.
+pub async fn pending_links(
+    _: Admin,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let links = get_links_with_payment_status(&mut connection, PaymentStatus::Pending).await?;
+
+    Ok(Json(into_link_dtos(&mut connection, links).await?))
+}
+
+pub async fn approve_link(
+    admin: Admin,
+    path: Path<Params>,
+    state: State<Pool>,
+    namespace: Namespace,
+) -> Result<impl IntoResponse, ApiError> {
+    review_link(admin, path, state, namespace, PaymentStatus::Succeeded).await
+}
+
+pub async fn reject_link(
+    admin: Admin,
+    path: Path<Params>,
+    state: State<Pool>,
+    namespace: Namespace,
+) -> Result<impl IntoResponse, ApiError> {
+    review_link(admin, path, state, namespace, PaymentStatus::Failed).await
+}
+
+async fn review_link(
+    _: Admin,
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    status: PaymentStatus,
+) -> Result<StatusCode, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    if link.payment_status != Some(PaymentStatus::Pending) {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "not_pending",
+            "The link isn't waiting for a review",
+        ));
+    }
+
+    set_link_payment_status(&mut connection, &link, status).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Flags a link as fraudulent, e.g. after it was reported for phishing. Visitors get a warning
+/// with the reason instead of being redirected.
+pub async fn flag_fraud(
+    _: Admin,
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    Api(Json(body)): Api<Json<FlagFraudDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    body.validate()?;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    flag_link_fraud(&mut connection, &link, body.reason.as_deref()).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
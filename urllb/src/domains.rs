@@ -0,0 +1,574 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use nanoid::nanoid;
+use serde::Deserialize;
+
+use shared::schema::domains;
+
+use crate::account::AccountAuth;
+use crate::db::{Connection, Pool};
+use crate::error::{self, Api, ApiError, Error};
+use crate::models::{CreateDomainDto, Domain, NewDomain};
+use crate::Config;
+
+/// Slugs that would be shadowed by other routes.
+const RESERVED_SLUGS: &[&str] = &["api", "info", "static", "metrics"];
+
+/// Extractor for the link namespace of a request.
+///
+/// Requests for a verified custom domain resolve slugs on that domain, everything else uses the
+/// public base url. The api also accepts a `domain` query parameter, so links on custom domains
+/// can be managed from the base url as well.
+pub struct Namespace(pub Option<Domain>);
+
+impl Namespace {
+    pub fn domain_id(&self) -> Option<i32> {
+        self.0.as_ref().map(|domain| domain.id)
+    }
+
+    pub fn host(&self) -> Option<String> {
+        self.0.as_ref().map(|domain| domain.host.clone())
+    }
+}
+
+#[derive(Deserialize)]
+struct NamespaceQuery {
+    domain: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<Pool> for Namespace {
+    type Rejection = StatusCode;
+
+    #[tracing::instrument(skip_all)]
+    async fn from_request_parts(parts: &mut Parts, pool: &Pool) -> Result<Self, StatusCode> {
+        let Extension(config) = Extension::<Arc<Config>>::from_request_parts(parts, pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let Query(query) = Query::<NamespaceQuery>::from_request_parts(parts, pool)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        if let Some(host) = query.domain {
+            let mut connection = pool.get().await.map_err(Error::PoolError)?;
+            let domain = get_verified_domain(&mut connection, &host.to_lowercase())
+                .await?
+                .ok_or(StatusCode::NOT_FOUND)?;
+            return Ok(Namespace(Some(domain)));
+        }
+
+        let host = match request_host(parts) {
+            Some(host) if host != config.base_host() => host,
+            _ => return Ok(Namespace(None)),
+        };
+
+        // Unknown hosts, e.g. localhost or the bare ip, fall back to the public namespace
+        let mut connection = pool.get().await.map_err(Error::PoolError)?;
+        Ok(Namespace(
+            get_verified_domain(&mut connection, &host).await?,
+        ))
+    }
+}
+
+/// The host the request was sent to, lowercased and without the port.
+fn request_host(parts: &Parts) -> Option<String> {
+    let host = parts
+        .headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| parts.uri.host())?;
+
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host,
+    };
+
+    Some(host.to_lowercase())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_verified_domain<'c>(
+    connection: &mut Connection<'c>,
+    host: &str,
+) -> error::Result<Option<Domain>> {
+    Ok(domains::table
+        .filter(domains::host.eq(host))
+        .filter(domains::verified_at.is_not_null())
+        .first::<Domain>(connection)
+        .await
+        .optional()?)
+}
+
+/// Checks whether a slug can be chosen for a link on a custom domain.
+pub fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= 64
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && !RESERVED_SLUGS.contains(&slug)
+}
+
+fn is_valid_host(host: &str) -> bool {
+    host.len() <= 253
+        && host.contains('.')
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+/// Checks that the owner of a domain published its verification token, either in a
+/// `_hurlurl-challenge.<host>` TXT record or in a file at
+/// `http://<host>/.well-known/hurlurl-challenge/<token>`.
+pub struct DomainVerifier {
+    resolver: TokioAsyncResolver,
+    http: reqwest::Client,
+    http_port: u16,
+}
+
+impl DomainVerifier {
+    /// Uses the given nameserver for all lookups, or the system resolver if there is none.
+    pub fn new(nameserver: Option<SocketAddr>, http_port: u16) -> Self {
+        let mut options = ResolverOpts::default();
+        // A user that just published the record would otherwise have to wait for the ttl
+        // of the negative answer before they can retry
+        options.cache_size = 0;
+
+        let resolver = match nameserver {
+            Some(nameserver) => TokioAsyncResolver::tokio(
+                ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(
+                        &[nameserver.ip()],
+                        nameserver.port(),
+                        true,
+                    ),
+                ),
+                options,
+            ),
+            None => TokioAsyncResolver::tokio(ResolverConfig::default(), options),
+        };
+
+        Self {
+            resolver,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("Failed to build http client"),
+            http_port,
+        }
+    }
+
+    pub async fn verify(&self, domain: &Domain) -> bool {
+        self.check_txt_record(domain).await || self.check_challenge_file(domain).await
+    }
+
+    async fn check_txt_record(&self, domain: &Domain) -> bool {
+        let name = format!("_hurlurl-challenge.{}.", domain.host);
+
+        match self.resolver.txt_lookup(name).await {
+            Ok(records) => records.iter().any(|record| {
+                record
+                    .txt_data()
+                    .iter()
+                    .any(|data| data.as_ref() == domain.verification_token.as_bytes())
+            }),
+            Err(err) => {
+                tracing::debug!("TXT lookup for {} failed: {}", domain.host, err);
+                false
+            }
+        }
+    }
+
+    async fn check_challenge_file(&self, domain: &Domain) -> bool {
+        // The host is resolved with our own resolver, so both checks see the same dns
+        let ip = match self.resolver.lookup_ip(format!("{}.", domain.host)).await {
+            Ok(ips) => match ips.iter().next() {
+                Some(ip) => ip,
+                None => return false,
+            },
+            Err(err) => {
+                tracing::debug!("Lookup of {} failed: {}", domain.host, err);
+                return false;
+            }
+        };
+
+        let url = format!(
+            "http://{}/.well-known/hurlurl-challenge/{}",
+            SocketAddr::new(ip, self.http_port),
+            domain.verification_token
+        );
+
+        let response = self
+            .http
+            .get(url)
+            .header(header::HOST.as_str(), &domain.host)
+            .send()
+            .await;
+
+        match response {
+            Ok(response) if response.status().is_success() => response
+                .text()
+                .await
+                .map(|body| body.trim() == domain.verification_token)
+                .unwrap_or(false),
+            Ok(_) => false,
+            Err(err) => {
+                tracing::debug!("Challenge request to {} failed: {}", domain.host, err);
+                false
+            }
+        }
+    }
+}
+
+fn domain_taken() -> ApiError {
+    ApiError::new(
+        StatusCode::CONFLICT,
+        "domain_taken",
+        "The domain was verified by another account",
+    )
+}
+
+/// Claims a domain for the account. It can only be used for links once it has been verified.
+#[tracing::instrument(skip_all)]
+pub async fn create_domain(
+    AccountAuth(account): AccountAuth,
+    State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    Api(Json(body)): Api<Json<CreateDomainDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let host = body.host.trim().trim_end_matches('.').to_lowercase();
+
+    if !is_valid_host(&host) || host == config.base_host() {
+        return Err(ApiError::bad_request(
+            "invalid_host",
+            "Not a domain that can be used for links",
+        )
+        .with_field("host", "invalid_host"));
+    }
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    if let Some(domain) = get_verified_domain(&mut connection, &host).await? {
+        return if domain.account_id == account.id {
+            Ok((StatusCode::OK, Json(domain)))
+        } else {
+            Err(domain_taken())
+        };
+    }
+
+    let existing = domains::table
+        .filter(domains::host.eq(&host))
+        .filter(domains::account_id.eq(account.id))
+        .first::<Domain>(&mut connection)
+        .await
+        .optional()
+        .map_err(Error::DieselError)?;
+
+    if let Some(domain) = existing {
+        return Ok((StatusCode::OK, Json(domain)));
+    }
+
+    let domain = diesel::insert_into(domains::table)
+        .values(NewDomain {
+            host: &host,
+            account_id: account.id,
+            verification_token: &nanoid!(32),
+        })
+        .get_result::<Domain>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok((StatusCode::CREATED, Json(domain)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn list_domains(
+    AccountAuth(account): AccountAuth,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let account_domains = domains::table
+        .filter(domains::account_id.eq(account.id))
+        .order(domains::id.asc())
+        .load::<Domain>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(Json(account_domains))
+}
+
+/// Checks the verification token of a claimed domain and marks it as verified if it was found.
+#[tracing::instrument(skip_all)]
+pub async fn verify_domain(
+    AccountAuth(account): AccountAuth,
+    Path(host): Path<String>,
+    State(pool): State<Pool>,
+    Extension(verifier): Extension<Arc<DomainVerifier>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let domain = domains::table
+        .filter(domains::host.eq(host.to_lowercase()))
+        .filter(domains::account_id.eq(account.id))
+        .first::<Domain>(&mut connection)
+        .await
+        .optional()
+        .map_err(Error::DieselError)?
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                "domain_not_found",
+                "The domain wasn't claimed by this account",
+            )
+        })?;
+
+    if domain.verified_at.is_some() {
+        return Ok(Json(domain));
+    }
+
+    if get_verified_domain(&mut connection, &domain.host)
+        .await?
+        .is_some()
+    {
+        return Err(domain_taken());
+    }
+
+    if !verifier.verify(&domain).await {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "verification_failed",
+            "The verification token wasn't found in a TXT record or a challenge file",
+        ));
+    }
+
+    let domain = diesel::update(&domain)
+        .set(domains::verified_at.eq(Utc::now()))
+        .get_result::<Domain>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(Json(domain))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use axum::Router;
+    use hickory_resolver::proto::op::{Message, MessageType};
+    use hickory_resolver::proto::rr::rdata::{A, TXT};
+    use hickory_resolver::proto::rr::{RData, Record, RecordType};
+    use tokio::net::UdpSocket;
+
+    use super::*;
+    use crate::models::NewAccount;
+    use crate::test_support::{pool, request_parts};
+
+    /// A nameserver that resolves every host to 127.0.0.1 and answers challenge lookups with
+    /// the given TXT record.
+    async fn spawn_nameserver(challenge: Option<&str>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let challenge = challenge.map(str::to_string);
+
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buffer).await {
+                let request = Message::from_vec(&buffer[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(request.op_code())
+                    .set_recursion_desired(request.recursion_desired())
+                    .set_recursion_available(true);
+
+                for query in request.queries() {
+                    response.add_query(query.clone());
+
+                    let is_challenge = query.name().to_ascii().starts_with("_hurlurl-challenge.");
+                    let data = match query.query_type() {
+                        RecordType::A if !is_challenge => Some(RData::A(A::new(127, 0, 0, 1))),
+                        RecordType::TXT if is_challenge => challenge
+                            .as_ref()
+                            .map(|challenge| RData::TXT(TXT::new(vec![challenge.clone()]))),
+                        _ => None,
+                    };
+                    if let Some(data) = data {
+                        response.add_answer(Record::from_rdata(query.name().clone(), 60, data));
+                    }
+                }
+
+                socket
+                    .send_to(&response.to_vec().unwrap(), peer)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        addr
+    }
+
+    /// A web server that serves the challenge file with the given content for `host`.
+    fn spawn_challenge_server(host: &'static str, content: &'static str) -> u16 {
+        let app = Router::new().route(
+            "/.well-known/hurlurl-challenge/:token",
+            get(move |headers: axum::http::HeaderMap| async move {
+                match headers.get(header::HOST) {
+                    Some(value) if value == host => Ok(content),
+                    _ => Err(StatusCode::NOT_FOUND),
+                }
+            }),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let port = server.local_addr().port();
+        tokio::spawn(server);
+        port
+    }
+
+    /// A port nothing listens on.
+    fn closed_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn domain(host: &str, token: &str) -> Domain {
+        Domain {
+            id: 1,
+            host: host.to_string(),
+            account_id: 1,
+            verification_token: token.to_string(),
+            verified_at: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn domains_are_verified_with_a_txt_record() {
+        let nameserver = spawn_nameserver(Some("token-123")).await;
+        let verifier = DomainVerifier::new(Some(nameserver), closed_port());
+
+        assert!(verifier.verify(&domain("team.example", "token-123")).await);
+        assert!(!verifier.verify(&domain("team.example", "token-456")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_are_verified_with_a_challenge_file() {
+        let nameserver = spawn_nameserver(None).await;
+        let port = spawn_challenge_server("team.example", "token-123\n");
+        let verifier = DomainVerifier::new(Some(nameserver), port);
+
+        assert!(verifier.verify(&domain("team.example", "token-123")).await);
+        assert!(!verifier.verify(&domain("team.example", "token-456")).await);
+        // The challenge server only answers for its own host
+        assert!(!verifier.verify(&domain("other.example", "token-123")).await);
+    }
+
+    #[test]
+    fn hosts_and_slugs_are_validated() {
+        assert!(is_valid_host("go.team.example"));
+        assert!(!is_valid_host("localhost"));
+        assert!(!is_valid_host("Go.team.example"));
+        assert!(!is_valid_host("-go.team.example"));
+        assert!(!is_valid_host("go..example"));
+
+        assert!(is_valid_slug("finals-2024"));
+        assert!(!is_valid_slug("api"));
+        assert!(!is_valid_slug("finals/2024"));
+        assert!(!is_valid_slug(""));
+    }
+
+    async fn namespace(pool: &Pool, uri: &str, host: &str) -> Result<Option<i32>, StatusCode> {
+        let mut parts = request_parts(uri, &[("host", host)]);
+        Namespace::from_request_parts(&mut parts, pool)
+            .await
+            .map(|namespace| namespace.domain_id())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn verified_domains_get_their_own_namespace() {
+        let pool = pool().await;
+        let mut connection = pool.get().await.unwrap();
+
+        let account_id = diesel::insert_into(shared::schema::accounts::table)
+            .values(NewAccount {
+                token_hash: &nanoid!(32),
+            })
+            .returning(shared::schema::accounts::id)
+            .get_result::<i32>(&mut connection)
+            .await
+            .unwrap();
+
+        let alphabet: Vec<char> = ('a'..='z').collect();
+        let verified = format!("{}.example", nanoid!(12, &alphabet));
+        let unverified = format!("{}.example", nanoid!(12, &alphabet));
+        let mut domain_ids = vec![];
+        for host in [&verified, &unverified] {
+            let domain = diesel::insert_into(domains::table)
+                .values(NewDomain {
+                    host,
+                    account_id,
+                    verification_token: &nanoid!(32),
+                })
+                .get_result::<Domain>(&mut connection)
+                .await
+                .unwrap();
+            domain_ids.push(domain.id);
+        }
+        diesel::update(domains::table.filter(domains::id.eq(domain_ids[0])))
+            .set(domains::verified_at.eq(Utc::now()))
+            .execute(&mut connection)
+            .await
+            .unwrap();
+
+        let verified_with_port = format!("{verified}:3000");
+        assert_eq!(
+            namespace(&pool, "/finals", &verified_with_port).await,
+            Ok(Some(domain_ids[0]))
+        );
+        assert_eq!(namespace(&pool, "/finals", &unverified).await, Ok(None));
+        assert_eq!(
+            namespace(&pool, "/finals", "localhost:3000").await,
+            Ok(None)
+        );
+
+        let query = format!("/api/links/finals?domain={}", verified.to_uppercase());
+        assert_eq!(
+            namespace(&pool, &query, "localhost:3000").await,
+            Ok(Some(domain_ids[0]))
+        );
+        let query = format!("/api/links/finals?domain={unverified}");
+        assert_eq!(
+            namespace(&pool, &query, "localhost:3000").await,
+            Err(StatusCode::NOT_FOUND)
+        );
+    }
+}
@@ -1,6 +1,17 @@
-use axum::http::StatusCode;
+use std::collections::BTreeMap;
+
+use axum::async_trait;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use diesel_async::pooled_connection::PoolError;
 use tracing::error;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::models::{ErrorDto, FieldErrorDto};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -12,11 +23,393 @@ pub enum Error {
     StripeError(#[from] stripe::StripeError),
     #[error("Pool error: {0}")]
     PoolError(#[from] bb8::RunError<PoolError>),
+    #[error("Stripe checkout session has no url")]
+    MissingCheckoutUrl,
+    #[error("Invalid stripe checkout session id")]
+    InvalidCheckoutSession,
+    #[error("Stripe price is invalid or has no fixed amount")]
+    InvalidPrice,
+    #[error("QR code error: {0}")]
+    Qr(#[from] qrcode::types::QrError),
+    #[error("PNG encoding error: {0}")]
+    Png(#[from] png::EncodingError),
+    #[error("Password hashing error: {0}")]
+    PasswordHash(#[from] argon2::password_hash::Error),
+    #[error("Blocking task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+    #[error("Stats error: {0}")]
+    Stats(#[from] anyhow::Error),
 }
 
 impl From<Error> for StatusCode {
+    fn from(err: Error) -> Self {
+        ApiError::from(err).status
+    }
+}
+
+/// A failed api request, answered with an [`ErrorDto`] as json.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    body: ErrorDto,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            body: ErrorDto {
+                code: code.to_string(),
+                message: message.into(),
+                fields: BTreeMap::new(),
+            },
+        }
+    }
+
+    pub fn bad_request(code: &str, message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    /// For looking up what the request names, like `link`. Only a missing row is a 404, other
+    /// database errors stay internal errors.
+    pub fn not_found(what: &str) -> impl FnOnce(Error) -> ApiError + '_ {
+        move |err| match err {
+            Error::DieselError(diesel::result::Error::NotFound) => ApiError::new(
+                StatusCode::NOT_FOUND,
+                &format!("{what}_not_found"),
+                format!("The {what} doesn't exist"),
+            ),
+            err => err.into(),
+        }
+    }
+
+    /// Points out the field of the request body that caused the error.
+    pub fn with_field(mut self, path: &str, code: &str) -> Self {
+        self.body
+            .fields
+            .entry(path.to_string())
+            .or_default()
+            .push(FieldErrorDto {
+                code: code.to_string(),
+                message: None,
+            });
+        self
+    }
+}
+
+/// Errors that are only a status get a code derived from it, like `not_found`.
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        let reason = status.canonical_reason().unwrap_or("Unknown error");
+        ApiError::new(
+            status,
+            &reason.to_lowercase().replace([' ', '-'], "_"),
+            reason,
+        )
+    }
+}
+
+/// Bodies that aren't json or don't fit the request are the fault of the client.
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::MissingJsonContentType(_) => ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                rejection.body_text(),
+            ),
+            _ => ApiError::bad_request("invalid_json", rejection.body_text()),
+        }
+    }
+}
+
+/// Query strings with missing or invalid parameters are the fault of the client.
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::bad_request("invalid_query", rejection.body_text())
+    }
+}
+
+/// Path segments that don't parse, e.g. a workspace id that isn't a number, are the fault of the
+/// client. Anything else means the route and the handler don't match.
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        match rejection {
+            PathRejection::FailedToDeserializePathParams(_) => {
+                ApiError::bad_request("invalid_path", rejection.body_text())
+            }
+            _ => {
+                error!("Path extraction failed: {}", rejection.body_text());
+                ApiError::from(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut error = ApiError::bad_request("validation_failed", "The request is invalid");
+        collect_field_errors(&errors, "", &mut error.body.fields);
+        error
+    }
+}
+
+/// The details of internal errors are only logged, they are nothing the client can fix.
+impl From<Error> for ApiError {
     fn from(err: Error) -> Self {
         error!("Internal server error: {:?}", err);
-        StatusCode::INTERNAL_SERVER_ERROR
+        match err {
+            Error::StripeError(_) | Error::MissingCheckoutUrl | Error::InvalidCheckoutSession => {
+                ApiError::new(
+                    StatusCode::BAD_GATEWAY,
+                    "payment_provider_error",
+                    "The payment provider couldn't be reached",
+                )
+            }
+            Error::PoolError(_) => ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "database_unavailable",
+                "The database is not available right now",
+            ),
+            _ => ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+                "Something went wrong on our side",
+            ),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+/// Runs the extractor inside and answers its rejection with an [`ApiError`] instead of plain
+/// text, e.g. `Api(Json(body)): Api<Json<CreateLinkDto>>`.
+pub struct Api<E>(pub E);
+
+#[async_trait]
+impl<S, E> FromRequestParts<S> for Api<E>
+where
+    S: Send + Sync,
+    E: FromRequestParts<S>,
+    ApiError: From<E::Rejection>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> std::result::Result<Self, ApiError> {
+        Ok(Api(E::from_request_parts(parts, state).await?))
+    }
+}
+
+#[async_trait]
+impl<S, B, E> FromRequest<S, B> for Api<E>
+where
+    S: Send + Sync,
+    B: Send + 'static,
+    E: FromRequest<S, B>,
+    ApiError: From<E::Rejection>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request<B>, state: &S) -> std::result::Result<Self, ApiError> {
+        Ok(Api(E::from_request(request, state).await?))
+    }
+}
+
+/// Flattens nested validation errors into paths with the json names of the fields.
+fn collect_field_errors(
+    errors: &ValidationErrors,
+    prefix: &str,
+    fields: &mut BTreeMap<String, Vec<FieldErrorDto>>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = match (prefix, *field) {
+            ("", field) => camel_case(field),
+            (prefix, "__all__") => format!("{prefix}.__all__"),
+            (prefix, field) => format!("{prefix}.{}", camel_case(field)),
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                fields
+                    .entry(path)
+                    .or_default()
+                    .extend(errors.iter().map(|error| FieldErrorDto {
+                        code: error.code.to_string(),
+                        message: error.message.as_ref().map(|message| message.to_string()),
+                    }))
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, &path, fields),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    collect_field_errors(errors, &format!("{path}[{index}]"), fields);
+                }
+            }
+        }
+    }
+}
+
+fn camel_case(field: &str) -> String {
+    if field == "__all__" {
+        return field.to_string();
+    }
+
+    let mut words = field.split('_');
+    let first = words.next().unwrap_or_default().to_string();
+    words.fold(first, |mut name, word| {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+        name
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use validator::Validate;
+
+    use super::*;
+    use crate::models::{CreateLinkDto, CreateWebhookDto};
+
+    fn validation_error(errors: ValidationErrors) -> ApiError {
+        let error = ApiError::from(errors);
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.body.code, "validation_failed");
+        error
+    }
+
+    fn codes(error: &ApiError, path: &str) -> Vec<String> {
+        error.body.fields[path]
+            .iter()
+            .map(|error| error.code.clone())
+            .collect()
+    }
+
+    #[test]
+    fn fields_get_their_json_names() {
+        assert_eq!(camel_case("url"), "url");
+        assert_eq!(camel_case("target_url"), "targetUrl");
+        assert_eq!(camel_case("click_thresholds"), "clickThresholds");
+        assert_eq!(camel_case("__all__"), "__all__");
+    }
+
+    #[test]
+    fn field_errors_are_collected() {
+        let webhook: CreateWebhookDto = serde_json::from_value(json!({
+            "url": "not a url",
+            "clickThresholds": [0],
+        }))
+        .unwrap();
+
+        let error = validation_error(webhook.validate().unwrap_err());
+
+        assert_eq!(
+            error.body.fields.keys().collect::<Vec<_>>(),
+            ["clickThresholds", "url"]
+        );
+        assert_eq!(codes(&error, "url"), ["url"]);
+    }
+
+    #[test]
+    fn errors_of_list_entries_get_their_index() {
+        let create: CreateLinkDto = serde_json::from_value(json!({
+            "password": "",
+            "targets": [
+                { "targetUrl": "https://game.example/1" },
+                { "targetUrl": "not a url", "weight": 0 },
+            ],
+        }))
+        .unwrap();
+
+        let error = validation_error(create.validate().unwrap_err());
+
+        assert_eq!(
+            error.body.fields.keys().collect::<Vec<_>>(),
+            ["password", "targets[1].targetUrl", "targets[1].weight"]
+        );
+        assert_eq!(codes(&error, "targets[1].targetUrl"), ["url"]);
+        assert_eq!(codes(&error, "targets[1].weight"), ["range"]);
+    }
+
+    #[test]
+    fn errors_of_the_whole_request_are_kept() {
+        let create: CreateLinkDto = serde_json::from_value(json!({})).unwrap();
+
+        let error = validation_error(create.validate().unwrap_err());
+
+        assert_eq!(codes(&error, "__all__"), ["targets"]);
+    }
+
+    /// Answers `GET /:id?page=<n>` with the id and page, through [`Api`] extractors.
+    async fn spawn_api() -> String {
+        #[derive(serde::Deserialize)]
+        struct Page {
+            page: u32,
+        }
+
+        let app = axum::Router::new().route(
+            "/:id",
+            axum::routing::get(
+                |Api(axum::extract::Path(id)): Api<axum::extract::Path<i32>>,
+                 Api(axum::extract::Query(query)): Api<axum::extract::Query<Page>>| async move {
+                    format!("{id} {}", query.page)
+                },
+            ),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+
+    async fn get(url: String) -> (StatusCode, serde_json::Value) {
+        let response = reqwest::get(url).await.unwrap();
+        let status = response.status();
+        let body = response.bytes().await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn rejected_paths_and_queries_are_answered_with_json() {
+        let api = spawn_api().await;
+
+        let ok = reqwest::get(format!("{api}/1?page=2")).await.unwrap();
+        assert_eq!(ok.text().await.unwrap(), "1 2");
+
+        let (status, body) = get(format!("{api}/abc?page=2")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_path");
+
+        let (status, body) = get(format!("{api}/1?page=first")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_query");
+
+        let (status, body) = get(format!("{api}/1")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_query");
+    }
+
+    #[test]
+    fn only_missing_rows_are_not_found() {
+        let error =
+            ApiError::not_found("link")(Error::DieselError(diesel::result::Error::NotFound));
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+        assert_eq!(error.body.code, "link_not_found");
+
+        let error = ApiError::not_found("link")(Error::DieselError(
+            diesel::result::Error::BrokenTransactionManager,
+        ));
+        assert_eq!(error.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.body.code, "internal_error");
     }
 }
@@ -0,0 +1,427 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use diesel_async::AsyncPgConnection;
+use futures_util::future::join_all;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{error, info, instrument, warn};
+use validator::Validate;
+
+use crate::access::{can_view, denied, require_manage, ManagementToken};
+use crate::account::AccountAuth;
+use crate::db::Pool;
+use crate::domains::Namespace;
+use crate::error::{Api, ApiError, Error, Result};
+use crate::models::{
+    CreateWebhookDto, Link, LinkEvent, NewWebhookDelivery, Target, WebhookDelivery,
+};
+use crate::service::{
+    claim_due_webhook_deliveries, create_webhook_subscription, delete_webhook_subscription,
+    enqueue_webhook_deliveries, get_link_and_targets, get_webhook_deliveries,
+    get_webhook_subscriptions, record_webhook_attempt,
+};
+use crate::webhooks::Webhooks;
+use crate::Params;
+
+/// The first retry of a delivery waits this long, every further one twice as long as the one before.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+/// Longer than any webhook call may take, so a delivery isn't sent twice at the same time.
+const DELIVERY_LEASE: Duration = Duration::from_secs(5 * 60);
+const DELIVERY_BATCH_SIZE: i64 = 100;
+const MAX_SUBSCRIPTIONS: usize = 10;
+const MAX_LOGGED_DELIVERIES: i64 = 100;
+
+/// Something that happened to a link, with the details that go into the payload.
+pub enum Event<'a> {
+    Created,
+    Paid,
+    Fraud { reason: Option<&'a str> },
+    TargetFull { target: &'a Target },
+    ClickThreshold { redirects: i32 },
+    Expired,
+}
+
+impl Event<'_> {
+    fn kind(&self) -> LinkEvent {
+        match self {
+            Event::Created => LinkEvent::Created,
+            Event::Paid => LinkEvent::Paid,
+            Event::Fraud { .. } => LinkEvent::Fraud,
+            Event::TargetFull { .. } => LinkEvent::TargetFull,
+            Event::ClickThreshold { .. } => LinkEvent::ClickThreshold,
+            Event::Expired => LinkEvent::Expired,
+        }
+    }
+
+    fn data(&self) -> Value {
+        match self {
+            Event::Created | Event::Paid | Event::Expired => json!({}),
+            Event::Fraud { reason } => json!({ "reason": reason }),
+            Event::TargetFull { target } => json!({
+                "targetUrl": target.target_url,
+                "label": target.label,
+                "capacity": target.capacity,
+            }),
+            Event::ClickThreshold { redirects } => json!({ "redirects": redirects }),
+        }
+    }
+}
+
+/// The json body of a delivery, receivers can drop duplicates by the id.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Payload<'a> {
+    id: String,
+    event: LinkEvent,
+    link: &'a str,
+    created_at: DateTime<Utc>,
+    data: Value,
+}
+
+#[derive(Deserialize)]
+pub struct SubscriptionParams {
+    link: String,
+    subscription: i32,
+}
+
+/// Queues a delivery of the event for every subscription of the link that wants it.
+#[instrument(skip_all)]
+pub async fn emit(connection: &mut AsyncPgConnection, link: &Link, event: Event<'_>) -> Result<()> {
+    let kind = event.kind();
+    let subscriptions = get_webhook_subscriptions(connection, link)
+        .await?
+        .into_iter()
+        .filter(|subscription| subscription.wants(kind))
+        .filter(|subscription| match event {
+            Event::ClickThreshold { redirects } => {
+                subscription.click_thresholds.contains(&redirects)
+            }
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+
+    if subscriptions.is_empty() {
+        return Ok(());
+    }
+
+    let payload = serde_json::to_string(&Payload {
+        id: nanoid!(),
+        event: kind,
+        link: &link.url,
+        created_at: Utc::now(),
+        data: event.data(),
+    })
+    .expect("Event payloads can be serialized");
+
+    let deliveries = subscriptions
+        .iter()
+        .map(|subscription| NewWebhookDelivery {
+            link_id: link.id,
+            subscription_id: subscription.id,
+            event: kind,
+            url: &subscription.url,
+            secret: &subscription.secret,
+            payload: &payload,
+        })
+        .collect::<Vec<_>>();
+
+    enqueue_webhook_deliveries(connection, &deliveries).await
+}
+
+/// Queues the events a redirect can cause, with the counts after the redirect.
+#[instrument(skip_all)]
+pub async fn redirected(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+    target: &Target,
+    link_redirects: i32,
+    target_redirects: i32,
+) -> Result<()> {
+    // Most links have no webhooks, their redirects shouldn't cost any queries
+    if !link.has_webhooks {
+        return Ok(());
+    }
+
+    if target.capacity == Some(target_redirects) {
+        emit(connection, link, Event::TargetFull { target }).await?;
+    }
+
+    emit(
+        connection,
+        link,
+        Event::ClickThreshold {
+            redirects: link_redirects,
+        },
+    )
+    .await
+}
+
+/// Periodically sends the queued deliveries. Failed ones are tried again with exponential
+/// backoff, until they were tried `max_attempts` times.
+pub fn spawn(pool: Pool, webhooks: Arc<Webhooks>, interval: Duration, max_attempts: u32) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            match deliver_due(&pool, &webhooks, max_attempts).await {
+                Ok((0, 0)) => {}
+                Ok((delivered, failed)) => {
+                    info!("Delivered {delivered} link events, {failed} failed")
+                }
+                Err(err) => error!("Failed to deliver link events: {:?}", err),
+            }
+        }
+    });
+}
+
+/// Returns the number of delivered and of failed deliveries.
+#[instrument(skip_all)]
+async fn deliver_due(pool: &Pool, webhooks: &Webhooks, max_attempts: u32) -> Result<(u64, u64)> {
+    let due = {
+        let mut connection = pool.get().await.map_err(Error::PoolError)?;
+        let lease_until = Utc::now()
+            + chrono::Duration::from_std(DELIVERY_LEASE)
+                .unwrap_or_else(|_| chrono::Duration::zero());
+        claim_due_webhook_deliveries(&mut connection, DELIVERY_BATCH_SIZE, lease_until).await?
+    };
+
+    let mut delivered = 0;
+    let mut failed = 0;
+
+    for result in join_all(
+        due.iter()
+            .map(|delivery| deliver(pool, webhooks, delivery, max_attempts)),
+    )
+    .await
+    {
+        if result? {
+            delivered += 1;
+        } else {
+            failed += 1;
+        }
+    }
+
+    Ok((delivered, failed))
+}
+
+#[instrument(skip_all)]
+async fn deliver(
+    pool: &Pool,
+    webhooks: &Webhooks,
+    delivery: &WebhookDelivery,
+    max_attempts: u32,
+) -> Result<bool> {
+    let result = webhooks
+        .send(&delivery.url, &delivery.secret, delivery.payload.as_bytes())
+        .await;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let Err(err) = result else {
+        record_webhook_attempt(&mut connection, delivery, None, None).await?;
+        return Ok(true);
+    };
+
+    let attempts = u32::try_from(delivery.attempts).unwrap_or(0) + 1;
+    let next_attempt_at = (err.is_transient() && attempts < max_attempts).then(|| {
+        let backoff = INITIAL_BACKOFF.saturating_mul(1 << (attempts - 1).min(16));
+        Utc::now()
+            + chrono::Duration::from_std(backoff).unwrap_or_else(|_| chrono::Duration::zero())
+    });
+
+    warn!(
+        "Delivering {:?} of link {} to {} failed: {}",
+        delivery.event, delivery.link_id, delivery.url, err
+    );
+
+    record_webhook_attempt(
+        &mut connection,
+        delivery,
+        Some(&err.to_string()),
+        next_attempt_at,
+    )
+    .await?;
+
+    Ok(false)
+}
+
+/// Lists the webhook subscriptions of a link together with their secrets.
+pub async fn list_webhooks(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> std::result::Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    Ok(Json(
+        get_webhook_subscriptions(&mut connection, &link).await?,
+    ))
+}
+
+pub async fn create_webhook(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+    Api(Json(body)): Api<Json<CreateWebhookDto>>,
+) -> std::result::Result<impl IntoResponse, ApiError> {
+    body.validate()?;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    if get_webhook_subscriptions(&mut connection, &link)
+        .await?
+        .len()
+        >= MAX_SUBSCRIPTIONS
+    {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "too_many_webhooks",
+            format!("A link can have at most {MAX_SUBSCRIPTIONS} webhook subscriptions"),
+        ));
+    }
+
+    let subscription = create_webhook_subscription(&mut connection, &link, &body).await?;
+
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+pub async fn delete_webhook(
+    Path(params): Path<SubscriptionParams>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> std::result::Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    if !delete_webhook_subscription(&mut connection, &link, params.subscription).await? {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "webhook_not_found",
+            "The webhook subscription doesn't exist",
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The most recent deliveries of a link, with their payloads and why they failed.
+pub async fn webhook_deliveries(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> std::result::Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    if !can_view(&mut connection, &link, account, &token).await? {
+        return Err(denied(account, &token).into());
+    }
+
+    Ok(Json(
+        get_webhook_deliveries(&mut connection, &link, MAX_LOGGED_DELIVERIES).await?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{create_test_link, pool};
+
+    async fn queued_events(connection: &mut AsyncPgConnection, link: &Link) -> Vec<LinkEvent> {
+        get_webhook_deliveries(connection, link, MAX_LOGGED_DELIVERIES)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|delivery| delivery.event)
+            .collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn redirects_only_queue_events_for_links_with_webhooks() {
+        let pool = pool().await;
+        let (link, targets, _) = create_test_link(
+            &pool,
+            json!({ "targets": [{ "targetUrl": "https://game.example/1", "capacity": 2 }] }),
+            None,
+        )
+        .await;
+        let mut connection = pool.get().await.unwrap();
+
+        assert!(!link.has_webhooks);
+        redirected(&mut connection, &link, &targets[0], 2, 2)
+            .await
+            .unwrap();
+        assert!(queued_events(&mut connection, &link).await.is_empty());
+
+        create_webhook_subscription(
+            &mut connection,
+            &link,
+            &CreateWebhookDto {
+                url: "https://bot.example/events".to_string(),
+                events: vec![LinkEvent::TargetFull, LinkEvent::ClickThreshold],
+                click_thresholds: vec![3],
+            },
+        )
+        .await
+        .unwrap();
+        let (link, targets) = get_link_and_targets(&mut connection, None, &link.url)
+            .await
+            .unwrap();
+        assert!(link.has_webhooks);
+
+        redirected(&mut connection, &link, &targets[0], 2, 2)
+            .await
+            .unwrap();
+        redirected(&mut connection, &link, &targets[0], 3, 3)
+            .await
+            .unwrap();
+        // Newest first
+        assert_eq!(
+            queued_events(&mut connection, &link).await,
+            [LinkEvent::ClickThreshold, LinkEvent::TargetFull]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tracing::{error, info, instrument, warn};
+
+use shared::PaymentStatus;
+
+use crate::db::Pool;
+use crate::error::{Error, Result};
+use crate::events::{self, Event};
+use crate::metrics::{GC_ERRORS, GC_LINKS_DELETED, GC_LINKS_PAID, GC_RUNS};
+use crate::payment::Payments;
+use crate::service::{delete_link, get_expired_unpaid_links, set_link_payment_status};
+
+/// Periodically deletes links whose checkout expired without being paid,
+/// so they don't hold on to their slug forever.
+pub fn spawn(pool: Pool, payments: Payments, interval: Duration, grace_period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            GC_RUNS.inc();
+            match collect(&pool, &payments, grace_period).await {
+                Ok((0, 0)) => {}
+                Ok((deleted, paid)) => {
+                    info!("Deleted {deleted} abandoned links, {paid} pending links were paid")
+                }
+                Err(err) => {
+                    GC_ERRORS.inc();
+                    error!("Failed to collect abandoned links: {:?}", err);
+                }
+            }
+        }
+    });
+}
+
+/// Returns the number of deleted links and of links that turned out to be paid.
+#[instrument(skip_all)]
+async fn collect(pool: &Pool, payments: &Payments, grace_period: Duration) -> Result<(u64, u64)> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let cutoff = Utc::now()
+        - chrono::Duration::from_std(grace_period).unwrap_or_else(|_| chrono::Duration::zero());
+    let expired = get_expired_unpaid_links(&mut connection, cutoff).await?;
+
+    let mut deleted = 0;
+    let mut paid = 0;
+
+    for link in expired {
+        // The user might have paid without ever coming back to the info page,
+        // so we have to ask the payment provider before throwing the link away
+        let status = match payments.check_payment(&link).await {
+            Ok(status) => status,
+            Err(err) => {
+                warn!("Failed to check payment of link {}: {:?}", link.url, err);
+                continue;
+            }
+        };
+
+        match status {
+            PaymentStatus::Succeeded => {
+                set_link_payment_status(&mut connection, &link, status).await?;
+                GC_LINKS_PAID.inc();
+                paid += 1;
+            }
+            // The checkout is somehow still open, try again next time
+            PaymentStatus::Pending => {}
+            PaymentStatus::Failed => {
+                events::emit(&mut connection, &link, Event::Expired).await?;
+                delete_link(&mut connection, &link).await?;
+                GC_LINKS_DELETED.inc();
+                deleted += 1;
+            }
+        }
+    }
+
+    Ok((deleted, paid))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nanoid::nanoid;
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::{CreateLinkDto, Link};
+    use crate::service::{create_link, get_link_and_targets, LinkOwner, LinkPayment};
+    use crate::test_support::{pool, SessionPayments};
+
+    /// A pending link whose checkout expired an hour ago.
+    async fn expired_link(pool: &Pool, session: &str) -> Link {
+        let create: CreateLinkDto =
+            serde_json::from_value(json!({ "targets": [{ "targetUrl": "https://example.com" }] }))
+                .unwrap();
+        let session = format!("{session}-{}", nanoid!());
+
+        let mut connection = pool.get().await.unwrap();
+        let (link, _) = create_link(
+            &mut connection,
+            &create,
+            &nanoid!(12),
+            "127.0.0.1/32".parse().unwrap(),
+            LinkOwner {
+                account: None,
+                domain: None,
+                management_token_hash: "unused",
+            },
+            LinkPayment {
+                stripe_session_id: Some(&session),
+                status: Some(PaymentStatus::Pending),
+                expires_at: Some(Utc::now() - chrono::Duration::hours(1)),
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        link
+    }
+
+    async fn reload(pool: &Pool, link: &Link) -> Option<Link> {
+        let mut connection = pool.get().await.unwrap();
+        get_link_and_targets(&mut connection, None, &link.url)
+            .await
+            .ok()
+            .map(|(link, _)| link)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn only_unpaid_links_past_the_grace_period_are_deleted() {
+        let pool = pool().await;
+        let payments: Payments = Arc::new(SessionPayments);
+
+        let paid = expired_link(&pool, "paid").await;
+        let failed = expired_link(&pool, "failed").await;
+        let open = expired_link(&pool, "open").await;
+        let broken = expired_link(&pool, "broken").await;
+
+        // Nothing expired more than two hours ago
+        collect(&pool, &payments, Duration::from_secs(2 * 60 * 60))
+            .await
+            .unwrap();
+        assert!(reload(&pool, &failed).await.is_some());
+
+        let (deleted, now_paid) = collect(&pool, &payments, Duration::ZERO).await.unwrap();
+        assert!(deleted >= 1 && now_paid >= 1);
+
+        assert_eq!(
+            reload(&pool, &paid).await.unwrap().payment_status,
+            Some(PaymentStatus::Succeeded)
+        );
+        assert!(reload(&pool, &failed).await.is_none());
+        assert_eq!(
+            reload(&pool, &open).await.unwrap().payment_status,
+            Some(PaymentStatus::Pending)
+        );
+        // Links whose payment can't be checked are kept for the next run
+        assert!(reload(&pool, &broken).await.is_some());
+
+        let mut connection = pool.get().await.unwrap();
+        for link in [paid, open, broken] {
+            delete_link(&mut connection, &link).await.unwrap();
+        }
+    }
+}
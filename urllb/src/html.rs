@@ -0,0 +1,67 @@
+use axum::http::{header, HeaderMap};
+
+use crate::domains::Namespace;
+use crate::Config;
+
+/// Escapes text for use in html content and attribute values.
+pub fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The path together with the query of the original request, so e.g. the signature of a
+/// signed url survives the detour through a page.
+pub fn with_query(path: &str, query: Option<&str>) -> String {
+    match query {
+        Some(query) if !query.is_empty() => format!("{path}?{query}"),
+        _ => path.to_string(),
+    }
+}
+
+/// The values of the cookies with the given name, browsers send several if their paths differ.
+pub fn cookies<'h: 'n, 'n>(
+    headers: &'h HeaderMap,
+    name: &'n str,
+) -> impl Iterator<Item = &'h str> + 'n {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .filter(move |(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+}
+
+/// The `Secure` attribute for cookies, if the request came in over https. Custom domains are
+/// always served over https.
+pub fn secure_attribute(config: &Config, namespace: &Namespace) -> &'static str {
+    if namespace.0.is_some() || config.base_url().starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    }
+}
+
+/// A complete page, the title is escaped but head and body have to be escaped by the caller.
+pub fn page(title: &str, head: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <meta name="robots" content="noindex">
+    <title>{} - hurlurl</title>
+{head}
+</head>
+<body>
+{body}
+</body>
+</html>"#,
+        escape(title)
+    )
+}
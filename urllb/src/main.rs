@@ -1,50 +1,93 @@
 #[macro_use]
 extern crate diesel;
 
-use crate::db::Pool;
-use crate::error::Error;
-use crate::models::{CreateLinkDto, LinkDto};
+use crate::access::{can_view, denied, require_manage, ManagementToken, VisitorAccess};
+use crate::account::{hash_token, settle_credit_purchases, AccountAuth, CreditBundle};
+use crate::db::{Connection, Pool};
+use crate::domains::{get_verified_domain, is_valid_slug, DomainVerifier, Namespace};
+use crate::error::{Api, ApiError, Error};
+use crate::metrics::{FRAUD_PAGE_HITS, LINKS_CREATED, REDIRECTS, VISIT_DURATION};
+use crate::models::{CreateLinkDto, Domain, Link, LinkDto, ResolveDto, Target};
+use crate::password::{hash_password, is_unlocked, prompt_page};
+use crate::payment::{
+    DisabledPayments, ManualApprovalPayments, PaymentStart, Payments, StripePayments,
+};
+use crate::picker::{picked_target, picker_page};
+use crate::provisioning::provisioning_info;
+use crate::proxy::{Proxy, Visit};
+use crate::selection::{group_code, normalize_group_code, target_order};
 use crate::service::{
-    create_link, get_link_and_targets, increase_redirect_count, set_link_payment_status,
+    create_link, create_link_with_credit, delete_link, get_link_and_targets,
+    get_provisioning_events, get_recent_clicks, get_target_groups, increase_redirect_count,
+    record_click, set_link_payment_status, set_link_stripe_session, LinkOwner, LinkPayment,
 };
-use axum::body::{Empty, Full};
-use axum::extract::{Path, State};
-use axum::http::{header, HeaderValue};
+use crate::signing::SignedQuery;
+use crate::target_sources::{source_info, TargetSources};
+use crate::waiting_room::Admission;
+use crate::webhooks::Webhooks;
+use crate::workspace::member_role;
+use axum::async_trait;
+use axum::body::{Body, Empty, Full};
+use axum::extract::{DefaultBodyLimit, FromRequest, Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, Request};
 use axum::response::{Redirect, Response};
 use axum::routing::get_service;
 use axum::{
     body,
     http::StatusCode,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{any, delete, get, post, put},
     Extension, Json, Router,
 };
 use axum_client_ip::{SecureClientIp, SecureClientIpSource};
+use hyper::body::HttpBody;
 use include_dir::{include_dir, Dir};
 use lazy_static::lazy_static;
 use nanoid::nanoid;
-use rand::seq::SliceRandom;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use shared::{CreateResult, PaymentStatus};
+use shared::template::TemplateError;
+use shared::{
+    CreateResult, LinkVisibility, PaymentProviderKind, PaymentStatus, PublicConfig, QuoteDto,
+};
 use std::fmt::Debug;
-use std::net::SocketAddr;
-use std::str::FromStr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use stripe::{
-    CheckoutSession, CheckoutSessionId, CheckoutSessionMode, CreateCheckoutSession,
-    CreateCheckoutSessionLineItems,
-};
+use std::time::Duration;
 use tokio::io;
 use tower_http::services::ServeDir;
-use tracing_subscriber::EnvFilter;
+use tower_http::trace::TraceLayer;
 use validator::Validate;
 
+mod access;
+mod account;
+mod admin;
 mod db;
+mod domains;
 mod error;
+mod events;
+mod gc;
+mod html;
+mod metrics;
 mod models;
+mod password;
+mod payment;
+mod picker;
+mod provisioning;
+mod proxy;
+mod qr;
 mod schema;
+mod selection;
 mod service;
+mod signing;
 mod stats;
+mod target_sources;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+mod waiting_room;
+mod webhooks;
+mod workspace;
 
 static STATIC_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../web/dist");
 
@@ -52,26 +95,262 @@ static STATIC_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../web/dist");
 struct Config {
     ip_source: SecureClientIpSource,
     database_url: String,
-    stripe_secret_key: String,
-    stripe_price_id: String,
+    #[serde(default)]
+    payment_provider: PaymentProviderKind,
+    stripe_secret_key: Option<String>,
+    stripe_price_id: Option<String>,
+    /// Token for the admin api, which is disabled if this isn't set
+    admin_token: Option<String>,
+    /// How often abandoned unpaid links are cleaned up
+    #[serde(default = "default_gc_interval_secs")]
+    gc_interval_secs: u64,
+    /// How long after its checkout expired an unpaid link can still be paid
+    #[serde(default = "default_gc_grace_period_secs")]
+    gc_grace_period_secs: u64,
+    /// Credit bundles that can be bought, as a list of `credits:stripe_price_id`
+    #[serde(default)]
+    credit_bundles: Vec<CreditBundle>,
+    /// The url under which this instance is reachable, used to build all generated links
+    #[serde(default = "default_public_base_url")]
+    public_base_url: String,
+    #[serde(default = "default_bind_address")]
+    bind_address: IpAddr,
+    #[serde(default = "default_port")]
+    port: u16,
+    /// Nameserver used to verify custom domains, the system resolver is used if this isn't set
+    domain_verification_nameserver: Option<SocketAddr>,
+    /// Port the http challenge file of custom domains is fetched from
+    #[serde(default = "default_domain_verification_http_port")]
+    domain_verification_http_port: u16,
+    /// Secret the cookies of unlocked password protected links are signed with. It has to be the
+    /// same on every instance and across restarts, or visitors have to unlock links again.
+    cookie_secret: String,
+    /// How long connecting to the target of a link in proxy mode may take before the next one is tried
+    #[serde(default = "default_proxy_connect_timeout_secs")]
+    proxy_connect_timeout_secs: u64,
+    /// How long a proxied request may take in total, including streaming the response
+    #[serde(default = "default_proxy_timeout_secs")]
+    proxy_timeout_secs: u64,
+    /// How many targets a proxied request is tried on before giving up
+    #[serde(default = "default_proxy_max_attempts")]
+    proxy_max_attempts: usize,
+    /// Largest request body that is forwarded to proxy targets
+    #[serde(default = "default_proxy_max_body_bytes")]
+    proxy_max_body_bytes: usize,
+    /// Allow proxying to loopback and private addresses, e.g. to test against local upstreams
+    #[serde(default)]
+    proxy_allow_private_targets: bool,
+    /// How often the target sources of links are fetched again
+    #[serde(default = "default_target_source_interval_secs")]
+    target_source_interval_secs: u64,
+    #[serde(default = "default_target_source_timeout_secs")]
+    target_source_timeout_secs: u64,
+    /// Allow target sources on loopback and private addresses, e.g. to test against a local stand-in
+    #[serde(default)]
+    target_source_allow_private: bool,
+    /// How long a webhook call may take before it counts as failed
+    #[serde(default = "default_webhook_timeout_secs")]
+    webhook_timeout_secs: u64,
+    /// How often a webhook call is tried before giving up, with exponential backoff in between
+    #[serde(default = "default_webhook_max_attempts")]
+    webhook_max_attempts: u32,
+    /// Allow webhooks on loopback and private addresses, e.g. to test against a local stand-in
+    #[serde(default)]
+    webhook_allow_private: bool,
+    /// How often links are checked for running out of capacity
+    #[serde(default = "default_provisioning_interval_secs")]
+    provisioning_interval_secs: u64,
+    /// How long after a call the provisioning webhook of a link isn't called again
+    #[serde(default = "default_provisioning_cooldown_secs")]
+    provisioning_cooldown_secs: u64,
+    /// How often queued link events are sent to their webhooks
+    #[serde(default = "default_event_delivery_interval_secs")]
+    event_delivery_interval_secs: u64,
+    /// How often sending a link event is tried before giving up, the backoff starts at 30 seconds
+    #[serde(default = "default_event_max_attempts")]
+    event_max_attempts: u32,
+    /// Bearer token for `/metrics`, the admin token works as well
+    metrics_token: Option<String>,
+    /// Serve `/metrics` on this port instead of the main one, without a token unless one is set
+    metrics_port: Option<u16>,
+    /// OTLP gRPC endpoint traces are exported to, e.g. `http://localhost:4317`. Traces are only
+    /// logged if this isn't set.
+    otlp_endpoint: Option<String>,
+    #[serde(default = "default_otlp_service_name")]
+    otlp_service_name: String,
+}
+
+impl Config {
+    /// The public base url without a trailing slash
+    fn base_url(&self) -> &str {
+        self.public_base_url.trim_end_matches('/')
+    }
+
+    /// The host of the public base url, requests for other hosts are looked up as custom domains
+    fn base_host(&self) -> &str {
+        let base_url = self.base_url();
+        let host = base_url
+            .split_once("://")
+            .map(|(_, host)| host)
+            .unwrap_or(base_url);
+        host.split(['/', ':']).next().unwrap_or(host)
+    }
+
+    /// The public url of a link, on its custom domain if it has one
+    fn link_url(&self, domain: Option<&Domain>, link: &str) -> String {
+        match domain {
+            Some(domain) => format!("https://{}/{link}", domain.host),
+            None => format!("{}/{link}", self.base_url()),
+        }
+    }
+
+    /// The url of the info page of a link
+    fn info_url(&self, domain: Option<&Domain>, link: &str) -> String {
+        self.link_url(domain, &format!("info/{link}"))
+    }
+}
+
+fn default_public_base_url() -> String {
+    "https://hurlurl.com".to_string()
+}
+
+fn default_bind_address() -> IpAddr {
+    IpAddr::from([0, 0, 0, 0])
+}
+
+fn default_port() -> u16 {
+    3000
+}
+
+fn default_domain_verification_http_port() -> u16 {
+    80
+}
+
+fn default_proxy_connect_timeout_secs() -> u64 {
+    5
+}
+
+fn default_proxy_timeout_secs() -> u64 {
+    60
+}
+
+fn default_proxy_max_attempts() -> usize {
+    3
+}
+
+fn default_proxy_max_body_bytes() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_target_source_interval_secs() -> u64 {
+    60
+}
+
+fn default_target_source_timeout_secs() -> u64 {
+    10
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    3
+}
+
+fn default_provisioning_interval_secs() -> u64 {
+    10
+}
+
+fn default_provisioning_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_event_delivery_interval_secs() -> u64 {
+    5
+}
+
+fn default_event_max_attempts() -> u32 {
+    10
+}
+
+fn default_otlp_service_name() -> String {
+    "urllb".to_string()
+}
+
+fn default_gc_interval_secs() -> u64 {
+    10 * 60
+}
+
+fn default_gc_grace_period_secs() -> u64 {
+    24 * 60 * 60
 }
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
 
     let config: Arc<Config> = Arc::new(envy::from_env().unwrap());
 
+    telemetry::init(&config);
+
     let pool = db::connect_and_migrate(&config.database_url)
         .await
         .expect("Failed to connect to database");
 
-    let stripe_client = stripe::Client::new(config.stripe_secret_key.clone());
+    let payments: Payments = match config.payment_provider {
+        PaymentProviderKind::Stripe => Arc::new(StripePayments::new(
+            config
+                .stripe_secret_key
+                .clone()
+                .expect("STRIPE_SECRET_KEY is required for the stripe payment provider"),
+            config
+                .stripe_price_id
+                .clone()
+                .expect("STRIPE_PRICE_ID is required for the stripe payment provider"),
+            config.base_url().to_string(),
+        )),
+        PaymentProviderKind::Disabled => Arc::new(DisabledPayments),
+        PaymentProviderKind::ManualApproval => Arc::new(ManualApprovalPayments),
+    };
+
+    let proxy = Arc::new(Proxy::new(&config));
+
+    let target_sources = Arc::new(TargetSources::new(&config));
+
+    let webhooks = Arc::new(Webhooks::new(&config));
+
+    let domain_verifier = Arc::new(DomainVerifier::new(
+        config.domain_verification_nameserver,
+        config.domain_verification_http_port,
+    ));
+
+    gc::spawn(
+        pool.clone(),
+        payments.clone(),
+        Duration::from_secs(config.gc_interval_secs),
+        Duration::from_secs(config.gc_grace_period_secs),
+    );
+
+    target_sources::spawn(
+        pool.clone(),
+        target_sources.clone(),
+        Duration::from_secs(config.target_source_interval_secs),
+    );
+
+    provisioning::spawn(
+        pool.clone(),
+        webhooks.clone(),
+        Duration::from_secs(config.provisioning_interval_secs),
+        Duration::from_secs(config.provisioning_cooldown_secs),
+    );
+
+    events::spawn(
+        pool.clone(),
+        webhooks.clone(),
+        Duration::from_secs(config.event_delivery_interval_secs),
+        config.event_max_attempts,
+    );
 
     let serve_dir_service = get_service(
         ServeDir::new(option_env!("STATIC_DIR").unwrap_or("../web/dist"))
@@ -87,25 +366,144 @@ async fn main() {
 
     let static_router = Router::new().route("/*path", serve_dir_service.clone());
 
-    let app = Router::new()
+    // The metrics are served on the main port unless they get their own
+    let app = match config.metrics_port {
+        Some(port) => {
+            let metrics_app = Router::new()
+                .route("/metrics", get(metrics::metrics))
+                .with_state(pool.clone())
+                .layer(Extension(config.clone()));
+            let addr = SocketAddr::new(config.bind_address, port);
+            tracing::info!("serving metrics on {}", addr);
+            tokio::spawn(axum::Server::bind(&addr).serve(metrics_app.into_make_service()));
+
+            Router::new()
+        }
+        None => Router::new().route("/metrics", get(metrics::metrics)),
+    };
+
+    let app = app
         .route("/", serve_dir_service.clone())
         .route("/api/stats", get(total_stats))
+        .route("/api/config", get(public_config))
         .route("/info/*path", get(root))
         .route("/api/links", post(post_link))
-        .route("/api/links/:link", get(link_info))
+        .route("/api/links/quote", post(quote_link))
+        .route("/api/links/:link", get(link_info).delete(remove_link))
+        .route("/api/links/:link/checkout", post(retry_checkout))
+        .route("/api/links/:link/qr", get(qr::link_qr))
+        .route("/api/links/:link/queue", get(waiting_room::queue_status))
+        .route("/api/links/:link/resolve", post(resolve_link))
+        .route(
+            "/api/links/:link/visibility",
+            put(access::update_visibility),
+        )
+        .route("/api/links/:link/clicks", get(link_clicks))
+        .route(
+            "/api/links/:link/signing-secret",
+            post(signing::rotate_signing_secret).delete(signing::remove_signing_secret),
+        )
+        .route(
+            "/api/links/:link/signed-urls",
+            post(signing::mint_signed_urls),
+        )
+        .route("/api/links/:link/password", put(password::update_password))
+        .route(
+            "/api/links/:link/webhooks",
+            get(events::list_webhooks).post(events::create_webhook),
+        )
+        .route(
+            "/api/links/:link/webhooks/:subscription",
+            delete(events::delete_webhook),
+        )
+        .route(
+            "/api/links/:link/webhook-deliveries",
+            get(events::webhook_deliveries),
+        )
+        .route("/api/accounts", post(account::create_account))
+        .route("/api/account/credits", get(account::credits))
+        .route("/api/account/credits/checkout", post(account::buy_credits))
+        .route(
+            "/api/account/domains",
+            get(domains::list_domains).post(domains::create_domain),
+        )
+        .route(
+            "/api/account/domains/:host/verify",
+            post(domains::verify_domain),
+        )
+        .route(
+            "/api/workspaces",
+            get(workspace::list_workspaces).post(workspace::create_workspace),
+        )
+        .route(
+            "/api/workspaces/:workspace/links",
+            get(workspace::workspace_links),
+        )
+        .route(
+            "/api/workspaces/:workspace/stats",
+            get(workspace::workspace_stats),
+        )
+        .route(
+            "/api/workspaces/:workspace/members",
+            get(workspace::members),
+        )
+        .route(
+            "/api/workspaces/:workspace/members/:account",
+            put(workspace::update_member).delete(workspace::remove_member),
+        )
+        .route(
+            "/api/workspaces/:workspace/invites",
+            post(workspace::create_invite),
+        )
+        .route("/api/invites/:code/accept", post(workspace::accept_invite))
+        .route("/api/admin/links/pending", get(admin::pending_links))
+        .route("/api/admin/links/:link/approve", post(admin::approve_link))
+        .route("/api/admin/links/:link/reject", post(admin::reject_link))
+        .route("/api/admin/links/:link/fraud", post(admin::flag_fraud))
         .nest("/static", static_router)
         .route("/:link", get(link).post(post_link))
+        .route("/:link/unlock", post(password::unlock))
+        .route(
+            "/:link/*path",
+            any(proxy::proxy_link).layer(DefaultBodyLimit::max(config.proxy_max_body_bytes)),
+        )
         .with_state(pool)
-        .layer(Extension(stripe_client))
+        .layer(Extension(payments))
+        .layer(Extension(domain_verifier))
+        .layer(Extension(proxy))
+        .layer(Extension(target_sources))
         .layer(config.ip_source.clone().into_extension())
-        .layer(Extension(config.clone()));
+        .layer(Extension(config.clone()))
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::request_span::<Body>));
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let addr = SocketAddr::new(config.bind_address, config.port);
     tracing::info!("listening on {}", addr);
     axum::Server::bind(&addr)
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    telemetry::shutdown();
+}
+
+/// Resolves on ctrl-c, or on SIGTERM when running in a container.
+async fn shutdown_signal() {
+    let terminate = async {
+        #[cfg(unix)]
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutting down");
 }
 
 async fn root() -> impl IntoResponse {
@@ -115,26 +513,55 @@ async fn root() -> impl IntoResponse {
 async fn link(
     Path(params): Path<Params>,
     State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(proxy): Extension<Arc<Proxy>>,
+    namespace: Namespace,
+    visit: Visit,
 ) -> Result<Response, StatusCode> {
+    visit_link(
+        &pool,
+        &config,
+        &proxy,
+        &namespace,
+        &params.link,
+        None,
+        visit,
+    )
+    .await
+}
+
+/// Sends a visitor to one of the targets of a link, either with a redirect or, for links in
+/// proxy mode, by forwarding the request. The path is the part of the request below the link.
+#[tracing::instrument(skip_all, fields(link = slug))]
+async fn visit_link(
+    pool: &Pool,
+    config: &Config,
+    proxy: &Proxy,
+    namespace: &Namespace,
+    slug: &str,
+    path: Option<&str>,
+    visit: Visit,
+) -> Result<Response, StatusCode> {
+    let _timer = VISIT_DURATION.with_label_values(&["visit"]).start_timer();
+
     let mut connection = pool
         .get()
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let (link, target_results) = get_link_and_targets(&mut connection, &params.link)
+    let (link, target_results) = get_link_and_targets(&mut connection, namespace.domain_id(), slug)
         .await
         .map_err(|_| StatusCode::NOT_FOUND)?;
 
-    if let Some(payment_status) = &link.payment_status {
-        match payment_status {
-            PaymentStatus::Pending | PaymentStatus::Failed => {
-                return Err(StatusCode::NOT_FOUND);
-            }
-            PaymentStatus::Succeeded => {}
-        }
+    // Only links in proxy mode have anything below them
+    if path.is_some() && !link.proxy {
+        return Err(StatusCode::NOT_FOUND);
     }
 
+    check_paid(&link)?;
+
     if link.fraud {
+        FRAUD_PAGE_HITS.inc();
         return Ok(Response::builder()
             .status(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS)
             .body(body::boxed(format!(
@@ -148,22 +575,217 @@ The following reason was given: {}",
             .unwrap());
     }
 
-    // select random target from results
-    let target = target_results.choose(&mut rand::thread_rng());
+    let Query(signed_query) =
+        Query::<SignedQuery>::try_from_uri(&visit.uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let participant = match signing::verify(&link, &signed_query) {
+        Ok(participant) => participant,
+        Err(reason) => return Ok((StatusCode::FORBIDDEN, reason).into_response()),
+    };
+
+    if !is_unlocked(&visit.headers, &config.cookie_secret, &link) {
+        return Ok(prompt_page(
+            &link.url,
+            visit.uri.query(),
+            StatusCode::OK,
+            None,
+        ));
+    }
+
+    let group = if link.group_affinity {
+        group_code(&visit.uri)
+    } else {
+        None
+    };
+
+    // Redirect links whose targets are all full keep their visitors in a waiting room, picker
+    // links show the full targets on the landing page instead
+    if !link.proxy && !link.picker {
+        let slots = waiting_room::free_slots(&target_results);
+        match waiting_room::admit(&link, slots, waiting_room::ticket(&visit.headers, &link)) {
+            Admission::Admitted => {}
+            Admission::Waiting { ticket, position } => {
+                let secure = html::secure_attribute(config, namespace);
+                return Ok(waiting_room::waiting_page(&link, &ticket, position, secure));
+            }
+            Admission::Rejected => return Err(StatusCode::SERVICE_UNAVAILABLE),
+        }
+    }
+
+    // Visitors of picker links first get the landing page, its entries link back with a pick
+    let order = if link.picker && !link.proxy {
+        match picked_target(&visit.uri, &target_results) {
+            Some(target) => vec![target],
+            None => return Ok(picker_page(&link, &target_results, visit.uri.query())),
+        }
+    } else {
+        target_order(&target_results, group.as_deref())
+    };
+
+    let (target, response) = if link.proxy {
+        if order.is_empty() {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        proxy
+            .forward(&order, path.unwrap_or_default(), &visit)
+            .await?
+    } else {
+        let target = order.first().copied().ok_or(StatusCode::NOT_FOUND)?;
+
+        let redirect = if link.permanent_redirect {
+            Redirect::permanent(&target.target_url).into_response()
+        } else {
+            Redirect::temporary(&target.target_url).into_response()
+        };
+
+        (target, redirect)
+    };
+
+    let mode = if link.proxy {
+        "proxy"
+    } else if link.picker {
+        "picker"
+    } else {
+        "redirect"
+    };
+    count_visit(
+        &mut connection,
+        &link,
+        target,
+        participant,
+        group.as_deref(),
+        mode,
+    )
+    .await;
+
+    Ok(response)
+}
+
+/// Links that are waiting for their payment or approval don't exist for visitors.
+fn check_paid(link: &Link) -> Result<(), StatusCode> {
+    match link.payment_status {
+        Some(PaymentStatus::Pending) | Some(PaymentStatus::Failed) => Err(StatusCode::NOT_FOUND),
+        Some(PaymentStatus::Succeeded) | None => Ok(()),
+    }
+}
+
+/// Counts a visit that was sent to the target. Failing to count doesn't fail the visit.
+async fn count_visit(
+    connection: &mut Connection<'_>,
+    link: &Link,
+    target: &Target,
+    participant: Option<&str>,
+    group: Option<&str>,
+    mode: &str,
+) {
+    REDIRECTS.with_label_values(&[mode]).inc();
 
-    if let Some(target) = target {
-        increase_redirect_count(&mut connection, &link, target)
+    if let Ok((link_redirects, target_redirects)) =
+        increase_redirect_count(connection, link, target).await
+    {
+        events::redirected(connection, link, target, link_redirects, target_redirects)
             .await
             .ok();
+    }
+    record_click(connection, link, target, participant, group)
+        .await
+        .ok();
+}
 
-        if link.permanent_redirect {
-            Ok(Redirect::permanent(&target.target_url).into_response())
-        } else {
-            Ok(Redirect::temporary(&target.target_url).into_response())
-        }
+/// Picks and counts a target the same way a visit of the link does, but answers with the
+/// target instead of redirecting, e.g. for bots that hand out targets directly. Signed and
+/// password protected links need the same signature query or unlock cookie as a visit.
+#[tracing::instrument(skip_all, fields(link = %params.link))]
+async fn resolve_link(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    namespace: Namespace,
+    Api(Query(signed_query)): Api<Query<SignedQuery>>,
+    headers: HeaderMap,
+    OptionalJson(body): OptionalJson<ResolveDto>,
+) -> Result<impl IntoResponse, ApiError> {
+    let _timer = VISIT_DURATION.with_label_values(&["resolve"]).start_timer();
+
+    let body = body.unwrap_or_default();
+    body.validate()?;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, target_results) =
+        get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+            .await
+            .map_err(ApiError::not_found("link"))?;
+
+    check_paid(&link)?;
+
+    if link.fraud {
+        return Err(ApiError::new(
+            StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            "fraud",
+            "The link was flagged as fraudulent",
+        ));
+    }
+
+    // The targets of proxy links are upstream servers, not something to hand out
+    if link.proxy {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "proxy_link",
+            "The targets of proxy links can't be resolved",
+        ));
+    }
+
+    let participant = signing::verify(&link, &signed_query)
+        .map_err(|reason| ApiError::new(StatusCode::FORBIDDEN, "invalid_signature", reason))?;
+
+    if !is_unlocked(&headers, &config.cookie_secret, &link) {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "password_required",
+            "The link is password protected",
+        ));
+    }
+
+    let group = if link.group_affinity {
+        body.visitor.as_deref().and_then(normalize_group_code)
     } else {
-        Err(StatusCode::NOT_FOUND)
+        None
+    };
+
+    // Resolving doesn't wait in the waiting room, but it doesn't skip the line either
+    if !waiting_room::has_room(&link, waiting_room::free_slots(&target_results)) {
+        return Err(targets_full());
     }
+
+    let target = target_order(&target_results, group.as_deref())
+        .first()
+        .copied()
+        .ok_or_else(targets_full)?;
+
+    count_visit(
+        &mut connection,
+        &link,
+        target,
+        participant,
+        group.as_deref(),
+        "resolve",
+    )
+    .await;
+
+    Ok(Json(target.clone()))
+}
+
+fn targets_full() -> ApiError {
+    ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "targets_full",
+        "Every target of the link is full",
+    )
+}
+
+fn invalid_template(err: TemplateError) -> ApiError {
+    ApiError::bad_request("invalid_template", err.to_string()).with_field("targets", "template")
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -171,6 +793,24 @@ struct Params {
     link: String,
 }
 
+/// A json body that may be left out. Unlike with `Option<Json<T>>`, a body that was sent but
+/// isn't valid is rejected instead of being ignored.
+struct OptionalJson<T>(Option<T>);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S, Body> for OptionalJson<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request<Body>, state: &S) -> Result<Self, ApiError> {
+        if request.body().is_end_stream() {
+            return Ok(OptionalJson(None));
+        }
+
+        let Json(value) = Json::<T>::from_request(request, state).await?;
+        Ok(OptionalJson(Some(value)))
+    }
+}
+
 lazy_static! {
     static ref BLACKLIST: Vec<String> = include_str!("blacklist.txt")
         .lines()
@@ -185,23 +825,19 @@ lazy_static! {
         .collect();
 }
 
-async fn post_link(
-    State(pool): State<Pool>,
-    Extension(stripe): Extension<stripe::Client>,
-    Extension(config): Extension<Arc<Config>>,
-    SecureClientIp(ip): SecureClientIp,
-    Json(body): Json<CreateLinkDto>,
-) -> Result<impl IntoResponse, StatusCode> {
-    body.validate().map_err(|_| StatusCode::BAD_REQUEST)?;
+fn is_blocked(target_url: &str) -> bool {
+    BLACKLIST.iter().any(|b| target_url.contains(b))
+}
 
-    if BLACKLIST.iter().any(|b| {
-        body.targets
-            .iter()
-            .map(|t| &t.target_url)
-            .any(|t| t.contains(b))
-    }) {
-        return Err(StatusCode::FORBIDDEN);
-    }
+/// Returns the targets that are on the blacklist and whether the link is whitelisted.
+fn check_targets(body: &CreateLinkDto) -> (Vec<String>, bool) {
+    let blocked_targets = body
+        .targets
+        .iter()
+        .map(|t| &t.target_url)
+        .filter(|t| is_blocked(t))
+        .cloned()
+        .collect();
 
     let whitelisted = WHITELIST.iter().any(|w| {
         body.targets
@@ -210,120 +846,475 @@ async fn post_link(
             .any(|t| t.starts_with(w))
     });
 
-    let url = nanoid!(5);
+    (blocked_targets, whitelisted)
+}
 
-    let success_url = format!("https://hurlurl.com/info/{url}");
+async fn quote_link(
+    Extension(payments): Extension<Payments>,
+    account: Option<AccountAuth>,
+    Api(Json(mut body)): Api<Json<CreateLinkDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    body.expand_targets().map_err(invalid_template)?;
+    body.validate()?;
 
-    let session = if !whitelisted {
-        let create_session = CreateCheckoutSession {
-            line_items: Some(vec![CreateCheckoutSessionLineItems {
-                price: Some(config.stripe_price_id.clone()),
-                quantity: Some(1),
-                ..Default::default()
-            }]),
-            mode: Some(CheckoutSessionMode::Payment),
-            success_url: Some(&success_url),
-            cancel_url: Some("https://hurlurl.com"),
-            ..Default::default()
-        };
+    let (blocked_targets, whitelisted) = check_targets(&body);
 
-        let session = CheckoutSession::create(&stripe, create_session)
-            .await
-            .map_err(error::Error::StripeError)?;
-        Some(session)
-    } else {
+    let pays_with_credit = !whitelisted
+        && account
+            .as_ref()
+            .map(|AccountAuth(account)| account.credits > 0)
+            .unwrap_or(false);
+
+    let price = if whitelisted || pays_with_credit {
         None
+    } else {
+        payments.price().await?
     };
 
+    Ok(Json(QuoteDto {
+        whitelisted,
+        pays_with_credit,
+        payment_required: price.is_some(),
+        approval_required: !whitelisted && payments.kind() == PaymentProviderKind::ManualApproval,
+        price,
+        blocked_targets,
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+async fn post_link(
+    State(pool): State<Pool>,
+    Extension(payments): Extension<Payments>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(target_sources): Extension<Arc<TargetSources>>,
+    SecureClientIp(ip): SecureClientIp,
+    account: Option<AccountAuth>,
+    Api(Json(mut body)): Api<Json<CreateLinkDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    body.expand_targets().map_err(invalid_template)?;
+    body.validate()?;
+
+    // Fetching the source right away tells the creator whether it works
+    if let Some(source) = &body.target_source {
+        body.targets = target_sources.fetch(source).await.map_err(|err| {
+            tracing::debug!("Target source {} can't be used: {}", source, err);
+            ApiError::bad_request(
+                "target_source_unavailable",
+                format!("The target source can't be used: {err}"),
+            )
+            .with_field("targetSource", "unavailable")
+        })?;
+    }
+
+    let (blocked_targets, whitelisted) = check_targets(&body);
+
+    if !blocked_targets.is_empty() {
+        let error = ApiError::new(
+            StatusCode::FORBIDDEN,
+            "blocked_targets",
+            format!("These targets are blocked: {}", blocked_targets.join(", ")),
+        );
+        return Err(body
+            .targets
+            .iter()
+            .enumerate()
+            .filter(|(_, target)| blocked_targets.contains(&target.target_url))
+            .fold(error, |error, (index, _)| {
+                error.with_field(&format!("targets[{index}].targetUrl"), "blocked")
+            }));
+    }
+
+    // Proxied requests can't stop at a landing page
+    if body.proxy && body.picker {
+        return Err(ApiError::bad_request(
+            "proxy_picker",
+            "Proxy links can't show a picker",
+        ));
+    }
+
+    // Provisioned targets would be dropped again on the next update from the source
+    if body.provisioning_webhook.is_some() && body.target_source.is_some() {
+        return Err(ApiError::bad_request(
+            "provisioning_target_source",
+            "Links with a target source can't have a provisioning webhook",
+        ));
+    }
+
+    let owner = account.as_ref().map(|AccountAuth(account)| account.id);
+
     let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    // Links can only be created on custom domains that were verified by the same account
+    let domain = match &body.domain {
+        Some(host) => {
+            let domain = get_verified_domain(&mut connection, &host.to_lowercase())
+                .await?
+                .filter(|domain| Some(domain.account_id) == owner)
+                .ok_or_else(|| {
+                    ApiError::new(
+                        StatusCode::FORBIDDEN,
+                        "domain_not_verified",
+                        "The domain isn't verified by this account",
+                    )
+                })?;
+            Some(domain)
+        }
+        None => None,
+    };
+    let domain_id = domain.as_ref().map(|domain| domain.id);
+    let domain_host = domain.as_ref().map(|domain| domain.host.clone());
+
+    // Only owners and editors can add links to a workspace
+    if let Some(workspace) = body.workspace {
+        let owner = owner.ok_or(StatusCode::UNAUTHORIZED)?;
+        let role = member_role(&mut connection, workspace, owner).await?;
+
+        if !role.map(|role| role.can_edit()).unwrap_or(false) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "workspace_forbidden",
+                "Only owners and editors can add links to the workspace",
+            ));
+        }
+    }
+
+    // Slugs can only be chosen on custom domains, the public namespace is first come first serve
+    let url = match (&domain, &body.url) {
+        (Some(_), Some(slug)) => {
+            if !is_valid_slug(slug) {
+                return Err(ApiError::bad_request("invalid_slug", "The slug is invalid")
+                    .with_field("url", "slug"));
+            }
+            if get_link_and_targets(&mut connection, domain_id, slug)
+                .await
+                .is_ok()
+            {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "slug_taken",
+                    "The slug is already taken on this domain",
+                ));
+            }
+            slug.clone()
+        }
+        _ => nanoid!(5),
+    };
+
+    let password_hash = match &body.password {
+        Some(password) => Some(hash_password(password.clone()).await?),
+        None => None,
+    };
+
+    let management_token = nanoid!(32);
+    let management_token_hash = hash_token(&management_token);
+    let link_owner = LinkOwner {
+        account: owner,
+        domain: domain_id,
+        management_token_hash: &management_token_hash,
+    };
+
+    // Account holders with credits left don't have to go through the checkout
+    if let (false, Some(AccountAuth(account))) = (whitelisted, &account) {
+        settle_credit_purchases(&mut connection, &payments, account.id).await?;
+
+        if let Some((link, target_results)) = create_link_with_credit(
+            &mut connection,
+            &body,
+            &url,
+            ip.into(),
+            account.id,
+            link_owner,
+            password_hash.as_deref(),
+        )
+        .await?
+        {
+            LINKS_CREATED.with_label_values(&["false", "credit"]).inc();
+            return Ok(Json(CreateResult::Link(Box::new(LinkDto {
+                signed: link.signing_secret.is_some(),
+                target_groups: vec![],
+                target_source: source_info(&link),
+                provisioning: provisioning_info(&link, &target_results, vec![]),
+                provisioning_secret: link.provisioning_secret.clone(),
+                link,
+                targets: target_results,
+                domain: domain_host,
+                password_protected: password_hash.is_some(),
+                management_token: Some(management_token),
+            }))));
+        }
+    }
+
+    let payment = if !whitelisted {
+        // The token is passed along in the fragment, so the info page can keep it after the checkout
+        payments
+            .start_payment(&format!(
+                "{}#token={management_token}",
+                config.info_url(domain.as_ref(), &url)
+            ))
+            .await?
+    } else {
+        PaymentStart::NotRequired
+    };
+
+    let link_payment = match &payment {
+        PaymentStart::NotRequired => LinkPayment::default(),
+        PaymentStart::Checkout(checkout) => LinkPayment {
+            stripe_session_id: Some(&checkout.session_id),
+            status: Some(PaymentStatus::Pending),
+            expires_at: Some(checkout.expires_at),
+        },
+        PaymentStart::AwaitingApproval => LinkPayment {
+            status: Some(PaymentStatus::Pending),
+            ..Default::default()
+        },
+    };
+
     let (link, target_results) = create_link(
         &mut connection,
         &body,
         &url,
         ip.into(),
-        session.as_ref().map(|s| s.id.to_string()),
+        link_owner,
+        link_payment,
+        password_hash.as_deref(),
     )
     .await?;
 
-    match session {
-        None => Ok(Json(CreateResult::Link(LinkDto {
-            link,
-            targets: target_results,
-        }))),
-        Some(session) => Ok(Json(CreateResult::StripeRedirect(
-            session.url.ok_or(StatusCode::INTERNAL_SERVER_ERROR)?,
-        ))),
+    let payment_label = match &payment {
+        PaymentStart::NotRequired => "none",
+        PaymentStart::Checkout(_) => "checkout",
+        PaymentStart::AwaitingApproval => "approval",
+    };
+    LINKS_CREATED
+        .with_label_values(&[&whitelisted.to_string(), payment_label])
+        .inc();
+
+    match payment {
+        PaymentStart::Checkout(checkout) => Ok(Json(CreateResult::StripeRedirect(checkout.url))),
+        PaymentStart::NotRequired | PaymentStart::AwaitingApproval => {
+            Ok(Json(CreateResult::Link(Box::new(LinkDto {
+                signed: link.signing_secret.is_some(),
+                target_groups: vec![],
+                target_source: source_info(&link),
+                provisioning: provisioning_info(&link, &target_results, vec![]),
+                provisioning_secret: link.provisioning_secret.clone(),
+                link,
+                targets: target_results,
+                domain: domain_host,
+                password_protected: password_hash.is_some(),
+                management_token: Some(management_token),
+            }))))
+        }
     }
 }
 
+#[tracing::instrument(skip_all, fields(link = %params.link))]
 async fn link_info(
     Path(params): Path<Params>,
     State(pool): State<Pool>,
-    Extension(stripe): Extension<stripe::Client>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let mut connection = pool
-        .get()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Extension(payments): Extension<Payments>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+    access: VisitorAccess,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
 
-    let (mut link, results) = get_link_and_targets(&mut connection, &params.link)
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let (mut link, mut results) =
+        get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+            .await
+            .map_err(ApiError::not_found("link"))?;
 
-    if let Some(status) = &link.payment_status {
-        match status {
-            PaymentStatus::Pending => {
-                if let Some(id) = &link.stripe_session_id {
-                    let session = CheckoutSession::retrieve(
-                        &stripe,
-                        &CheckoutSessionId::from_str(id).unwrap(),
-                        &[],
-                    )
-                    .await
-                    .map_err(Error::StripeError)?;
-
-                    if session.status == Some(stripe::CheckoutSessionStatus::Complete) {
-                        set_link_payment_status(
-                            &mut connection,
-                            &params.link,
-                            PaymentStatus::Succeeded,
-                        )
-                        .await?;
-                    } else {
-                        set_link_payment_status(
-                            &mut connection,
-                            &params.link,
-                            PaymentStatus::Failed,
-                        )
-                        .await?;
-                        return Err(StatusCode::NOT_FOUND);
-                    }
-                }
-            }
-            PaymentStatus::Failed => {
-                return Err(StatusCode::NOT_FOUND);
-            }
-            PaymentStatus::Succeeded => {}
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    let viewer = can_view(&mut connection, &link, account, &token).await?;
+    if !viewer {
+        match link.visibility {
+            LinkVisibility::Public => {}
+            LinkVisibility::StatsOnly => results.clear(),
+            LinkVisibility::Private => return Err(denied(account, &token).into()),
+        }
+
+        // The targets are what the password and the signature protect
+        if !access.allows(&link) {
+            results.clear();
+        }
+    }
+
+    if link.payment_status == Some(PaymentStatus::Pending) {
+        let status = payments.check_payment(&link).await?;
+
+        if status != PaymentStatus::Pending {
+            set_link_payment_status(&mut connection, &link, status.clone()).await?;
+            link.payment_status = Some(status);
         }
     }
 
+    // Groups are counted per target, so they are hidden together with the targets
+    let target_groups = if link.group_affinity && !results.is_empty() {
+        get_target_groups(&mut connection, &link).await?
+    } else {
+        vec![]
+    };
+
+    // The source and webhook urls may contain credentials, so only the owners get to see them
+    let target_source = if viewer { source_info(&link) } else { None };
+    let provisioning = if viewer && link.provisioning_webhook.is_some() {
+        let events = get_provisioning_events(&mut connection, &link, 20).await?;
+        provisioning_info(&link, &results, events)
+    } else {
+        None
+    };
+
     link.stripe_session_id = None;
 
     Ok(Json(LinkDto {
+        password_protected: link.password_hash.is_some(),
+        signed: link.signing_secret.is_some(),
+        target_groups,
+        target_source,
+        provisioning,
         link,
         targets: results,
+        domain: namespace.host(),
+        management_token: None,
+        provisioning_secret: None,
     }))
 }
 
-async fn total_stats(State(pool): State<Pool>) -> Result<impl IntoResponse, StatusCode> {
-    let mut connection = pool
-        .get()
+/// The most recent clicks of a link. They include the participants of signed urls, so only
+/// the owners of a link can see them.
+#[tracing::instrument(skip_all, fields(link = %params.link))]
+async fn link_clicks(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    if !can_view(&mut connection, &link, account, &token).await? {
+        return Err(denied(account, &token).into());
+    }
+
+    Ok(Json(get_recent_clicks(&mut connection, &link, 1000).await?))
+}
+
+/// Creates a fresh checkout session for a link that hasn't been paid yet, e.g. because the
+/// user closed the checkout page or the previous session expired.
+#[tracing::instrument(skip_all, fields(link = %params.link))]
+async fn retry_checkout(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    Extension(payments): Extension<Payments>,
+    Extension(config): Extension<Arc<Config>>,
+    Namespace(domain): Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(
+        &mut connection,
+        domain.as_ref().map(|domain| domain.id),
+        &params.link,
+    )
+    .await
+    .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    match link.payment_status {
+        Some(PaymentStatus::Pending) | Some(PaymentStatus::Failed) => {}
+        Some(PaymentStatus::Succeeded) | None => {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "already_paid",
+                "The link doesn't need to be paid anymore",
+            ))
+        }
+    }
+
+    // Like on creation, the token comes back in the fragment, so the info page can keep it
+    let info_url = config.info_url(domain.as_ref(), &link.url);
+    let return_url = match &token.0 {
+        Some(management_token) => format!("{info_url}#token={management_token}"),
+        None => info_url,
+    };
+
+    match payments.start_payment(&return_url).await? {
+        PaymentStart::Checkout(checkout) => {
+            set_link_stripe_session(
+                &mut connection,
+                &link,
+                &checkout.session_id,
+                checkout.expires_at,
+            )
+            .await?;
+            Ok(Json(CreateResult::StripeRedirect(checkout.url)))
+        }
+        // There is nothing the user can pay for with the other providers
+        PaymentStart::NotRequired | PaymentStart::AwaitingApproval => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "checkout_unavailable",
+            "Links can't be paid for with the configured payment provider",
+        )),
+    }
+}
+
+/// Deletes a link, using its management token or as its owner.
+#[tracing::instrument(skip_all, fields(link = %params.link))]
+async fn remove_link(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    delete_link(&mut connection, &link).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn public_config(
+    Extension(payments): Extension<Payments>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(PublicConfig {
+        public_base_url: config.base_url().to_string(),
+        payment_provider: payments.kind(),
+        price: payments.price().await?,
+        credit_bundles: config
+            .credit_bundles
+            .iter()
+            .filter(|_| payments.sells_credits())
+            .map(|bundle| bundle.credits)
+            .collect(),
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+async fn total_stats(State(pool): State<Pool>) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
 
     let stats = stats::total_stats(&mut connection)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(Error::Stats)?;
 
     Ok(Json(stats))
 }
@@ -356,3 +1347,225 @@ async fn static_path(Path(path): Path<String>) -> impl IntoResponse {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::FromRequestParts;
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::ErrorDto;
+    use crate::password::{cookie_name, unlock_signature};
+    use crate::test_support::{config, create_test_link, json_response, pool, request_parts};
+
+    async fn resolve_body(
+        content_type: Option<&str>,
+        body: &'static str,
+    ) -> Result<Option<ResolveDto>, (StatusCode, ErrorDto)> {
+        let mut request = Request::builder().method("POST");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+
+        match OptionalJson::from_request(request.body(Body::from(body)).unwrap(), &()).await {
+            Ok(OptionalJson(body)) => Ok(body),
+            Err(err) => Err(json_response(err).await),
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_bodies_can_be_left_out_but_not_malformed() {
+        assert!(resolve_body(None, "").await.unwrap().is_none());
+        assert!(resolve_body(Some("application/json"), "")
+            .await
+            .unwrap()
+            .is_none());
+
+        let body = resolve_body(Some("application/json"), r#"{"visitor": "alice"}"#).await;
+        assert_eq!(body.unwrap().unwrap().visitor.as_deref(), Some("alice"));
+
+        let (status, error) = resolve_body(Some("application/json"), r#"{"visitor": "#)
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, "invalid_json");
+
+        let (status, error) = resolve_body(Some("application/json"), r#"{"visitor": 1}"#)
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, "invalid_json");
+
+        let (status, error) = resolve_body(None, r#"{"visitor": "alice"}"#)
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(error.code, "unsupported_media_type");
+    }
+
+    async fn info(
+        pool: &Pool,
+        link: &Link,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> LinkDto {
+        info_at(
+            pool,
+            link,
+            &format!("/api/links/{}", link.url),
+            token,
+            headers,
+        )
+        .await
+    }
+
+    async fn info_at(
+        pool: &Pool,
+        link: &Link,
+        uri: &str,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> LinkDto {
+        let mut parts = request_parts(uri, headers);
+        let access = VisitorAccess::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+
+        let response = link_info(
+            Path(Params {
+                link: link.url.clone(),
+            }),
+            State(pool.clone()),
+            Extension(Arc::new(DisabledPayments) as Payments),
+            Namespace(None),
+            None,
+            ManagementToken(token.map(str::to_string)),
+            access,
+        )
+        .await;
+
+        let (status, dto) = json_response(response.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        dto
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn locked_link_hides_its_targets() {
+        let pool = pool().await;
+        let password_hash = hash_password("secret".to_string()).await.unwrap();
+        let (link, _, token) = create_test_link(
+            &pool,
+            json!({ "targets": [{ "targetUrl": "https://example.com/room" }] }),
+            Some(&password_hash),
+        )
+        .await;
+
+        let locked = info(&pool, &link, None, &[]).await;
+        assert!(locked.password_protected);
+        assert!(locked.targets.is_empty());
+        assert_eq!(
+            serde_json::to_value(&locked).unwrap()["passwordProtected"],
+            true
+        );
+
+        let cookie = format!(
+            "{}={}",
+            cookie_name(&link),
+            unlock_signature(&config().cookie_secret, &link).unwrap()
+        );
+        let unlocked = info(&pool, &link, None, &[("cookie", &cookie)]).await;
+        assert_eq!(unlocked.targets.len(), 1);
+
+        let wrong_cookie = format!("{}=00", cookie_name(&link));
+        let wrong = info(&pool, &link, None, &[("cookie", &wrong_cookie)]).await;
+        assert!(wrong.targets.is_empty());
+
+        let owner = info(&pool, &link, Some(&token), &[]).await;
+        assert_eq!(owner.targets.len(), 1);
+    }
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn signed_link_hides_its_targets() {
+        let pool = pool().await;
+        let (link, _, token) = create_test_link(
+            &pool,
+            json!({ "signed": true, "targets": [{ "targetUrl": "https://example.com/room" }] }),
+            None,
+        )
+        .await;
+        let secret = link.signing_secret.clone().unwrap();
+        let info_url = format!("/api/links/{}", link.url);
+
+        let unsigned = info(&pool, &link, None, &[]).await;
+        assert!(unsigned.signed);
+        assert!(unsigned.targets.is_empty());
+
+        let signed_url = signing::signed_url(&info_url, &secret, &link.url, Some("alice"), None);
+        let signed = info_at(&pool, &link, &signed_url, None, &[]).await;
+        assert_eq!(signed.targets.len(), 1);
+
+        let other_secret = signing::signed_url(&info_url, "guess", &link.url, None, None);
+        let forged = info_at(&pool, &link, &other_secret, None, &[]).await;
+        assert!(forged.targets.is_empty());
+
+        let expired_url = signing::signed_url(&info_url, &secret, &link.url, None, Some(1));
+        let expired = info_at(&pool, &link, &expired_url, None, &[]).await;
+        assert!(expired.targets.is_empty());
+
+        let owner = info(&pool, &link, Some(&token), &[]).await;
+        assert_eq!(owner.targets.len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn missing_links_are_answered_with_an_error_body() {
+        let pool = pool().await;
+        let mut parts = request_parts("/api/links/missing", &[]);
+        let access = VisitorAccess::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+
+        let response = link_info(
+            Path(Params { link: nanoid!(12) }),
+            State(pool),
+            Extension(Arc::new(DisabledPayments) as Payments),
+            Namespace(None),
+            None,
+            ManagementToken(None),
+            access,
+        )
+        .await;
+
+        let (status, error) = json_response::<ErrorDto>(response.err().unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error.code, "link_not_found");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn clicks_carry_their_target() {
+        let pool = pool().await;
+        let (link, targets, _) = create_test_link(
+            &pool,
+            json!({ "targets": [
+                { "targetUrl": "https://example.com/room", "label": "Room", "color": "#16a34a" },
+            ] }),
+            None,
+        )
+        .await;
+
+        let mut connection = pool.get().await.unwrap();
+        record_click(&mut connection, &link, &targets[0], Some("alice"), None)
+            .await
+            .unwrap();
+
+        let clicks = get_recent_clicks(&mut connection, &link, 10).await.unwrap();
+        let click = serde_json::to_value(&clicks).unwrap()[0].clone();
+        assert_eq!(click["targetId"], targets[0].id);
+        assert_eq!(click["participant"], "alice");
+        assert_eq!(click["targetUrl"], "https://example.com/room");
+        assert_eq!(click["label"], "Room");
+        assert_eq!(click["color"], "#16a34a");
+    }
+}
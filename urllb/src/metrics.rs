@@ -0,0 +1,294 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Extension;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Counter, Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use tracing::Instrument;
+
+use crate::admin::bearer_token;
+use crate::db::Pool;
+use crate::Config;
+
+lazy_static! {
+    pub static ref GC_RUNS: IntCounter = register_int_counter!(
+        "hurlurl_gc_runs_total",
+        "Number of times the pending link garbage collection ran"
+    )
+    .unwrap();
+    pub static ref GC_ERRORS: IntCounter = register_int_counter!(
+        "hurlurl_gc_errors_total",
+        "Number of failed pending link garbage collection runs"
+    )
+    .unwrap();
+    pub static ref GC_LINKS_DELETED: IntCounter = register_int_counter!(
+        "hurlurl_gc_links_deleted_total",
+        "Number of abandoned unpaid links that were deleted"
+    )
+    .unwrap();
+    pub static ref GC_LINKS_PAID: IntCounter = register_int_counter!(
+        "hurlurl_gc_links_paid_total",
+        "Number of pending links the garbage collection found to be paid after all"
+    )
+    .unwrap();
+    pub static ref TARGET_SOURCE_REFRESHES: IntCounter = register_int_counter!(
+        "hurlurl_target_source_refreshes_total",
+        "Number of times the target source of a link was fetched"
+    )
+    .unwrap();
+    pub static ref TARGET_SOURCE_ERRORS: IntCounter = register_int_counter!(
+        "hurlurl_target_source_errors_total",
+        "Number of target source fetches that failed and kept the previous targets"
+    )
+    .unwrap();
+    pub static ref REDIRECTS: IntCounterVec = register_int_counter_vec!(
+        "hurlurl_redirects_total",
+        "Number of visitors sent to a target, by how they were sent",
+        &["mode"]
+    )
+    .unwrap();
+    pub static ref VISIT_DURATION: HistogramVec = register_histogram_vec!(
+        "hurlurl_visit_duration_seconds",
+        "Time it took to answer a visit of a link, by handler",
+        &["handler"]
+    )
+    .unwrap();
+    pub static ref LINKS_CREATED: IntCounterVec = register_int_counter_vec!(
+        "hurlurl_links_created_total",
+        "Number of created links, by whether they were whitelisted and how they are paid",
+        &["whitelisted", "payment"]
+    )
+    .unwrap();
+    pub static ref STRIPE_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "hurlurl_stripe_request_duration_seconds",
+        "Time calls to the stripe api took, by operation",
+        &["operation"]
+    )
+    .unwrap();
+    pub static ref STRIPE_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "hurlurl_stripe_errors_total",
+        "Number of failed calls to the stripe api, by operation",
+        &["operation"]
+    )
+    .unwrap();
+    pub static ref CACHE_HITS: IntCounterVec = register_int_counter_vec!(
+        "hurlurl_cache_hits_total",
+        "Number of lookups that were answered from a cache",
+        &["cache"]
+    )
+    .unwrap();
+    pub static ref CACHE_MISSES: IntCounterVec = register_int_counter_vec!(
+        "hurlurl_cache_misses_total",
+        "Number of lookups that weren't in a cache",
+        &["cache"]
+    )
+    .unwrap();
+    pub static ref FRAUD_PAGE_HITS: IntCounter = register_int_counter!(
+        "hurlurl_fraud_page_hits_total",
+        "Number of visits of links that were flagged as fraudulent"
+    )
+    .unwrap();
+    static ref POOL_CONNECTIONS: IntGauge = register_int_gauge!(
+        "hurlurl_db_pool_connections",
+        "Number of database connections the pool currently holds"
+    )
+    .unwrap();
+    static ref POOL_IDLE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "hurlurl_db_pool_idle_connections",
+        "Number of database connections that aren't in use"
+    )
+    .unwrap();
+    static ref POOL_GETS_WAITED: IntCounter = register_int_counter!(
+        "hurlurl_db_pool_gets_waited_total",
+        "Number of times getting a connection had to wait for one to become available"
+    )
+    .unwrap();
+    static ref POOL_GETS_TIMED_OUT: IntCounter = register_int_counter!(
+        "hurlurl_db_pool_gets_timed_out_total",
+        "Number of times getting a connection timed out"
+    )
+    .unwrap();
+    static ref POOL_WAIT_SECONDS: Counter = register_counter!(
+        "hurlurl_db_pool_wait_seconds_total",
+        "Time spent waiting for a connection from the pool"
+    )
+    .unwrap();
+}
+
+/// Times a call to the stripe api in a span of its own and counts it as failed if it returns
+/// an error.
+pub async fn observe_stripe<T, E>(
+    operation: &str,
+    call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let timer = STRIPE_REQUEST_DURATION
+        .with_label_values(&[operation])
+        .start_timer();
+    let result = call
+        .instrument(tracing::info_span!("stripe", operation))
+        .await;
+    timer.observe_duration();
+
+    if result.is_err() {
+        STRIPE_ERRORS.with_label_values(&[operation]).inc();
+    }
+
+    result
+}
+
+/// The pool keeps its own statistics, they are copied over right before they are scraped.
+fn observe_pool(pool: &Pool) {
+    let state = pool.state();
+    POOL_CONNECTIONS.set(i64::from(state.connections));
+    POOL_IDLE_CONNECTIONS.set(i64::from(state.idle_connections));
+
+    let statistics = state.statistics;
+    POOL_GETS_WAITED.inc_by(statistics.get_waited.saturating_sub(POOL_GETS_WAITED.get()));
+    POOL_GETS_TIMED_OUT.inc_by(
+        statistics
+            .get_timed_out
+            .saturating_sub(POOL_GETS_TIMED_OUT.get()),
+    );
+    POOL_WAIT_SECONDS
+        .inc_by((statistics.get_wait_time.as_secs_f64() - POOL_WAIT_SECONDS.get()).max(0.0));
+}
+
+/// Metrics need the metrics or the admin token. Without a metrics token they are served to
+/// everyone on the separate metrics port, which then shouldn't be exposed publicly.
+fn check_token(config: &Config, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let token = bearer_token(headers);
+    let matches = |expected: &Option<String>| expected.is_some() && token == expected.as_deref();
+
+    match (&config.metrics_token, config.metrics_port) {
+        (None, Some(_)) => Ok(()),
+        (None, None) if config.admin_token.is_none() => Err(StatusCode::NOT_FOUND),
+        _ if matches(&config.metrics_token) || matches(&config.admin_token) => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+pub async fn metrics(
+    State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_token(&config, &headers)?;
+    observe_pool(&pool);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+
+    use super::*;
+    use crate::test_support::config_with;
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        headers
+    }
+
+    /// A pool that never connects, the metrics only read its statistics.
+    fn unconnected_pool() -> Pool {
+        Pool::builder().build_unchecked(AsyncDieselConnectionManager::new(
+            "postgres://localhost/unused",
+        ))
+    }
+
+    #[test]
+    fn metrics_need_a_token_unless_they_have_their_own_port() {
+        let none = HeaderMap::new();
+
+        let closed = config_with(&[]);
+        assert_eq!(check_token(&closed, &none), Err(StatusCode::NOT_FOUND));
+
+        let own_port = config_with(&[("METRICS_PORT", "9100")]);
+        assert_eq!(check_token(&own_port, &none), Ok(()));
+
+        let admin = config_with(&[("ADMIN_TOKEN", "admin")]);
+        assert_eq!(check_token(&admin, &bearer("admin")), Ok(()));
+        assert_eq!(check_token(&admin, &none), Err(StatusCode::UNAUTHORIZED));
+
+        let token = config_with(&[
+            ("METRICS_TOKEN", "metrics"),
+            ("METRICS_PORT", "9100"),
+            ("ADMIN_TOKEN", "admin"),
+        ]);
+        assert_eq!(check_token(&token, &bearer("metrics")), Ok(()));
+        assert_eq!(check_token(&token, &bearer("admin")), Ok(()));
+        assert_eq!(
+            check_token(&token, &bearer("guess")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(check_token(&token, &none), Err(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn failed_stripe_calls_are_counted() {
+        let errors = || STRIPE_ERRORS.with_label_values(&["test_call"]).get();
+        let calls = || {
+            STRIPE_REQUEST_DURATION
+                .with_label_values(&["test_call"])
+                .get_sample_count()
+        };
+
+        observe_stripe("test_call", async { Ok::<_, ()>(()) })
+            .await
+            .unwrap();
+        assert_eq!((calls(), errors()), (1, 0));
+
+        observe_stripe("test_call", async { Err::<(), _>(()) })
+            .await
+            .unwrap_err();
+        assert_eq!((calls(), errors()), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_in_the_text_format() {
+        REDIRECTS.with_label_values(&["redirect"]).inc();
+        let config = config_with(&[("ADMIN_TOKEN", "admin")]);
+
+        let response = metrics(
+            State(unconnected_pool()),
+            Extension(config),
+            bearer("admin"),
+        )
+        .await
+        .unwrap()
+        .into_response();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# TYPE hurlurl_redirects_total counter"));
+        assert!(body.contains(r#"hurlurl_redirects_total{mode="redirect"}"#));
+        assert!(body.contains("hurlurl_db_pool_connections 0"));
+    }
+}
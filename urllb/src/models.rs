@@ -1,5 +1,18 @@
+use chrono::{DateTime, Utc};
+
+use super::schema::accounts;
+use super::schema::clicks;
+use super::schema::credit_ledger;
+use super::schema::credit_purchases;
+use super::schema::domains;
 use super::schema::links;
+use super::schema::provisioning_events;
 use super::schema::targets;
+use super::schema::webhook_deliveries;
+use super::schema::webhook_subscriptions;
+use super::schema::workspace_invites;
+use super::schema::workspace_members;
+use super::schema::workspaces;
 
 pub use shared::*;
 
@@ -11,6 +24,22 @@ pub struct NewLink<'a> {
     pub created_by_ip: Option<ipnet::IpNet>,
     pub stripe_session_id: Option<&'a str>,
     pub payment_status: Option<PaymentStatus>,
+    pub payment_expires_at: Option<DateTime<Utc>>,
+    pub account_id: Option<i32>,
+    pub domain_id: Option<i32>,
+    pub workspace_id: Option<i32>,
+    pub management_token_hash: Option<&'a str>,
+    pub visibility: LinkVisibility,
+    pub password_hash: Option<&'a str>,
+    pub signing_secret: Option<&'a str>,
+    pub proxy: bool,
+    pub group_affinity: bool,
+    pub picker: bool,
+    pub target_source: Option<&'a str>,
+    pub target_source_checked_at: Option<DateTime<Utc>>,
+    pub provisioning_webhook: Option<&'a str>,
+    pub provisioning_secret: Option<&'a str>,
+    pub provisioning_threshold: i32,
 }
 
 #[derive(Insertable)]
@@ -18,4 +47,130 @@ pub struct NewLink<'a> {
 pub struct NewTarget<'a> {
     pub link_id: i32,
     pub target_url: &'a str,
+    pub label: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub color: Option<&'a str>,
+    pub weight: i32,
+    pub capacity: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "clicks"]
+pub struct NewClick<'a> {
+    pub link_id: i32,
+    pub target_id: i32,
+    pub participant: Option<&'a str>,
+    pub group_code: Option<&'a str>,
+}
+
+#[derive(Insertable)]
+#[table_name = "provisioning_events"]
+pub struct NewProvisioningEvent<'a> {
+    pub link_id: i32,
+    pub remaining_capacity: i32,
+    pub attempts: i32,
+    pub added_targets: i32,
+    pub error: Option<&'a str>,
+}
+
+#[derive(Insertable)]
+#[table_name = "webhook_subscriptions"]
+pub struct NewWebhookSubscription<'a> {
+    pub link_id: i32,
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub events: &'a [LinkEvent],
+    pub click_thresholds: &'a [i32],
+}
+
+#[derive(Insertable)]
+#[table_name = "webhook_deliveries"]
+pub struct NewWebhookDelivery<'a> {
+    pub link_id: i32,
+    pub subscription_id: i32,
+    pub event: LinkEvent,
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub payload: &'a str,
+}
+
+#[derive(Queryable, Identifiable, Clone, Debug)]
+pub struct Account {
+    pub id: i32,
+    pub token_hash: String,
+    pub credits: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "accounts"]
+pub struct NewAccount<'a> {
+    pub token_hash: &'a str,
+}
+
+#[derive(Queryable, Identifiable, Clone, Debug)]
+pub struct CreditPurchase {
+    pub id: i32,
+    pub account_id: i32,
+    pub credits: i32,
+    pub stripe_session_id: String,
+    pub payment_status: PaymentStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "credit_purchases"]
+pub struct NewCreditPurchase<'a> {
+    pub account_id: i32,
+    pub credits: i32,
+    pub stripe_session_id: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "credit_ledger"]
+pub struct NewCreditLedgerEntry {
+    pub account_id: i32,
+    pub amount: i32,
+    pub reason: CreditReason,
+    pub purchase_id: Option<i32>,
+    pub link_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "domains"]
+pub struct NewDomain<'a> {
+    pub host: &'a str,
+    pub account_id: i32,
+    pub verification_token: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "workspaces"]
+pub struct NewWorkspace<'a> {
+    pub name: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "workspace_members"]
+pub struct NewWorkspaceMember {
+    pub workspace_id: i32,
+    pub account_id: i32,
+    pub role: WorkspaceRole,
+}
+
+#[derive(Queryable, Identifiable, Clone, Debug)]
+pub struct WorkspaceInvite {
+    pub id: i32,
+    pub workspace_id: i32,
+    pub code_hash: String,
+    pub role: WorkspaceRole,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "workspace_invites"]
+pub struct NewWorkspaceInvite<'a> {
+    pub workspace_id: i32,
+    pub code_hash: &'a str,
+    pub role: WorkspaceRole,
 }
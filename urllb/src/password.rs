@@ -0,0 +1,327 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use axum::extract::{Path, RawQuery, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::{Extension, Form, Json};
+use axum_client_ip::SecureClientIp;
+use cached::{Cached, TimedSizedCache};
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use sha2::Sha256;
+use validator::Validate;
+
+use shared::schema::links;
+
+use crate::access::{require_manage, ManagementToken};
+use crate::account::AccountAuth;
+use crate::db::Pool;
+use crate::domains::Namespace;
+use crate::error::{self, Api, ApiError, Error};
+use crate::html;
+use crate::models::{Link, UpdatePasswordDto};
+use crate::service::get_link_and_targets;
+use crate::{Config, Params};
+
+/// Guesses a single visitor gets per link before having to wait. IPv6 visitors usually get a
+/// whole /64, so they are counted by that instead of by address.
+const MAX_ATTEMPTS_PER_VISITOR: u32 = 5;
+/// Wrong guesses per link from all visitors together, this stops guessing from many addresses.
+/// It locks everyone out until the window passes, so it's far above what visitors that mistype
+/// the password need.
+const MAX_FAILURES_PER_LINK: u32 = 100;
+/// Attempts are forgotten this long after the last one.
+const ATTEMPT_WINDOW_SECS: u64 = 15 * 60;
+const UNLOCK_COOKIE_MAX_AGE_SECS: u64 = 30 * 24 * 60 * 60;
+
+lazy_static! {
+    /// Attempts per visitor and link
+    static ref ATTEMPTS: Mutex<TimedSizedCache<(IpNet, i32), u32>> = Mutex::new(
+        TimedSizedCache::with_size_and_lifespan(100_000, ATTEMPT_WINDOW_SECS)
+    );
+    /// Wrong guesses per link. Kept apart from the attempts, so visitors with fresh addresses
+    /// can't push the count of a link out of the cache.
+    static ref FAILURES: Mutex<TimedSizedCache<i32, u32>> = Mutex::new(
+        TimedSizedCache::with_size_and_lifespan(10_000, ATTEMPT_WINDOW_SECS)
+    );
+}
+
+pub async fn hash_password(password: String) -> error::Result<String> {
+    Ok(tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await??)
+}
+
+async fn verify_password(password: String, password_hash: String) -> error::Result<bool> {
+    Ok(tokio::task::spawn_blocking(move || {
+        let parsed = PasswordHash::new(&password_hash)?;
+        Ok::<_, argon2::password_hash::Error>(
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+        )
+    })
+    .await??)
+}
+
+/// The network a visitor is counted by, the address itself for IPv4 and its /64 for IPv6.
+fn visitor_network(ip: IpAddr) -> IpNet {
+    let prefix = match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 64,
+    };
+    IpNet::new(ip, prefix)
+        .expect("the prefix fits the address")
+        .trunc()
+}
+
+/// Counts an attempt to unlock the link, returns false if there were too many recently.
+fn register_attempt(ip: IpAddr, link: i32) -> bool {
+    let failures = FAILURES.lock().unwrap().cache_get(&link).copied();
+    if failures.unwrap_or(0) >= MAX_FAILURES_PER_LINK {
+        return false;
+    }
+
+    let mut attempts = ATTEMPTS.lock().unwrap();
+    let key = (visitor_network(ip), link);
+
+    let visitor = attempts.cache_get(&key).copied().unwrap_or(0);
+    if visitor >= MAX_ATTEMPTS_PER_VISITOR {
+        return false;
+    }
+
+    attempts.cache_set(key, visitor + 1);
+    true
+}
+
+/// Counts a wrong guess towards the limit of the link.
+fn register_failure(link: i32) {
+    let mut failures = FAILURES.lock().unwrap();
+
+    let count = failures.cache_get(&link).copied().unwrap_or(0);
+    failures.cache_set(link, count + 1);
+}
+
+pub fn cookie_name(link: &Link) -> String {
+    format!("hurlurl-unlock-{}", link.id)
+}
+
+/// The mac of the unlock cookie. It covers the password hash, so changing the password
+/// locks out everyone that unlocked the link before.
+fn unlock_mac(secret: &str, link: &Link) -> Option<Hmac<Sha256>> {
+    let password_hash = link.password_hash.as_ref()?;
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{password_hash}", link.id).as_bytes());
+    Some(mac)
+}
+
+/// The value of the cookie that unlocks the link, none if it has no password.
+pub fn unlock_signature(secret: &str, link: &Link) -> Option<String> {
+    unlock_mac(secret, link).map(|mac| hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether the visitor may be redirected, either because the link has no password
+/// or because the request carries a valid unlock cookie.
+pub fn is_unlocked(headers: &HeaderMap, secret: &str, link: &Link) -> bool {
+    let Some(mac) = unlock_mac(secret, link) else {
+        return true;
+    };
+
+    html::cookies(headers, &cookie_name(link))
+        .filter_map(|value| hex::decode(value).ok())
+        .any(|signature| mac.clone().verify_slice(&signature).is_ok())
+}
+
+/// The page visitors of a password protected link see instead of the redirect.
+pub fn prompt_page(
+    slug: &str,
+    query: Option<&str>,
+    status: StatusCode,
+    error: Option<&str>,
+) -> Response {
+    let error = error
+        .map(|error| format!(r#"<p class="error">{error}</p>"#))
+        .unwrap_or_default();
+    let action = html::escape(&html::with_query(&format!("/{slug}/unlock"), query));
+
+    let page = html::page(
+        "Password required",
+        r#"    <style>
+        body { font-family: sans-serif; display: flex; justify-content: center; margin-top: 15vh; }
+        form { display: flex; flex-direction: column; gap: 0.75rem; width: 20rem; }
+        input, button { font-size: 1rem; padding: 0.5rem; }
+        .error { color: #b91c1c; margin: 0; }
+    </style>"#,
+        &format!(
+            r#"    <form method="post" action="{action}">
+        <h1>Password required</h1>
+        <p>This hurlurl is protected with a password.</p>
+        {error}
+        <input type="password" name="password" placeholder="Password" autofocus required>
+        <button type="submit">Continue</button>
+    </form>"#
+        ),
+    );
+
+    (status, [(header::CACHE_CONTROL, "no-store")], Html(page)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct UnlockForm {
+    password: String,
+}
+
+/// Checks the password entered on the prompt page and remembers it in a signed cookie.
+pub async fn unlock(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    namespace: Namespace,
+    SecureClientIp(ip): SecureClientIp,
+    RawQuery(query): RawQuery,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response, StatusCode> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    crate::check_paid(&link)?;
+
+    let link_path = format!("/{}", link.url);
+    let redirect_path = html::with_query(&link_path, query.as_deref());
+
+    // Fraudulent links don't take guesses, the link itself tells the visitor why
+    if link.fraud {
+        return Ok(Redirect::to(&redirect_path).into_response());
+    }
+
+    let Some(password_hash) = link.password_hash.clone() else {
+        return Ok(Redirect::to(&redirect_path).into_response());
+    };
+
+    if !register_attempt(ip, link.id) {
+        return Ok(prompt_page(
+            &link.url,
+            query.as_deref(),
+            StatusCode::TOO_MANY_REQUESTS,
+            Some("Too many attempts, please try again later."),
+        ));
+    }
+
+    if !verify_password(form.password, password_hash).await? {
+        register_failure(link.id);
+        return Ok(prompt_page(
+            &link.url,
+            query.as_deref(),
+            StatusCode::UNAUTHORIZED,
+            Some("Wrong password."),
+        ));
+    }
+
+    let signature = unlock_signature(&config.cookie_secret, &link).unwrap_or_default();
+
+    let secure = html::secure_attribute(&config, &namespace);
+
+    // The info api needs the cookie as well to show the targets
+    let cookie = format!(
+        "{}={signature}; Path=/; Max-Age={UNLOCK_COOKIE_MAX_AGE_SECS}; HttpOnly; SameSite=Lax{secure}",
+        cookie_name(&link),
+    );
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&redirect_path)).into_response())
+}
+
+/// Sets or removes the password of a link.
+#[tracing::instrument(skip_all)]
+pub async fn update_password(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+    Api(Json(body)): Api<Json<UpdatePasswordDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    body.validate()?;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    let password_hash = match body.password {
+        Some(password) => Some(hash_password(password).await?),
+        None => None,
+    };
+
+    diesel::update(&link)
+        .set(links::password_hash.eq(password_hash))
+        .execute(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv6_visitors_are_counted_by_their_network() {
+        let link = -1;
+        for attempt in 0..MAX_ATTEMPTS_PER_VISITOR {
+            let ip = format!("2001:db8:1:2::{attempt}").parse().unwrap();
+            assert!(register_attempt(ip, link));
+        }
+
+        assert!(!register_attempt(
+            "2001:db8:1:2:ffff::1".parse().unwrap(),
+            link
+        ));
+        assert!(register_attempt("2001:db8:1:3::1".parse().unwrap(), link));
+    }
+
+    #[test]
+    fn ipv4_visitors_are_counted_by_their_address() {
+        let link = -2;
+        for _ in 0..MAX_ATTEMPTS_PER_VISITOR {
+            assert!(register_attempt("192.0.2.1".parse().unwrap(), link));
+        }
+
+        assert!(!register_attempt("192.0.2.1".parse().unwrap(), link));
+        assert!(register_attempt("192.0.2.2".parse().unwrap(), link));
+        assert!(register_attempt("192.0.2.1".parse().unwrap(), -3));
+    }
+
+    #[test]
+    fn links_stop_taking_guesses_after_too_many_failures() {
+        let link = -4;
+        for _ in 0..MAX_FAILURES_PER_LINK {
+            register_failure(link);
+        }
+
+        assert!(!register_attempt("192.0.2.10".parse().unwrap(), link));
+        assert!(!register_attempt("2001:db8:4::1".parse().unwrap(), link));
+        assert!(register_attempt("192.0.2.10".parse().unwrap(), -5));
+    }
+}
@@ -0,0 +1,229 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use stripe::{
+    CheckoutSession, CheckoutSessionId, CheckoutSessionMode, CheckoutSessionStatus,
+    CreateCheckoutSession, CreateCheckoutSessionLineItems, PriceId,
+};
+use tokio::sync::OnceCell;
+
+use crate::error::{Error, Result};
+use crate::metrics::observe_stripe;
+use crate::models::{Link, PaymentProviderKind, PaymentStatus, Price};
+
+pub type Payments = Arc<dyn PaymentProvider>;
+
+/// What needs to happen before a newly created link can be used.
+pub enum PaymentStart {
+    /// The link can be used right away.
+    NotRequired,
+    /// The user needs to be sent to a checkout page.
+    Checkout(Checkout),
+    /// The link needs to be approved by an admin.
+    AwaitingApproval,
+}
+
+pub struct Checkout {
+    pub session_id: String,
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    fn kind(&self) -> PaymentProviderKind;
+
+    /// The price of a link that isn't whitelisted, or none if links are free.
+    async fn price(&self) -> Result<Option<Price>>;
+
+    /// Starts the payment for a new link, the user is sent back to its info page afterwards.
+    async fn start_payment(&self, info_url: &str) -> Result<PaymentStart>;
+
+    /// Checks the payment of a pending link and returns its current status.
+    async fn check_payment(&self, link: &Link) -> Result<PaymentStatus>;
+
+    fn sells_credits(&self) -> bool {
+        false
+    }
+
+    /// Starts the checkout for a credit bundle, returns none if the provider can't sell credits.
+    async fn start_credit_payment(&self, _price_id: &str) -> Result<Option<Checkout>> {
+        Ok(None)
+    }
+
+    /// Checks the checkout of a credit bundle and returns its current status.
+    async fn check_credit_payment(&self, _session_id: &str) -> Result<PaymentStatus> {
+        Ok(PaymentStatus::Failed)
+    }
+}
+
+/// Charges a fixed price per link through a stripe checkout session.
+pub struct StripePayments {
+    client: stripe::Client,
+    price_id: String,
+    /// Public base url of this instance, the user is sent back there after buying credits
+    base_url: String,
+    /// The price is fetched from stripe once and then kept for the lifetime of the process
+    price: OnceCell<Price>,
+}
+
+impl StripePayments {
+    pub fn new(secret_key: String, price_id: String, base_url: String) -> Self {
+        Self {
+            client: stripe::Client::new(secret_key),
+            price_id,
+            base_url,
+            price: OnceCell::new(),
+        }
+    }
+
+    async fn create_checkout(&self, price_id: &str, return_url: &str) -> Result<Checkout> {
+        let create_session = CreateCheckoutSession {
+            line_items: Some(vec![CreateCheckoutSessionLineItems {
+                price: Some(price_id.to_string()),
+                quantity: Some(1),
+                ..Default::default()
+            }]),
+            mode: Some(CheckoutSessionMode::Payment),
+            success_url: Some(return_url),
+            // Send the user back to where they came from, so they can retry the payment
+            cancel_url: Some(return_url),
+            ..Default::default()
+        };
+
+        let session = observe_stripe(
+            "create_checkout_session",
+            CheckoutSession::create(&self.client, create_session),
+        )
+        .await?;
+
+        Ok(Checkout {
+            session_id: session.id.to_string(),
+            url: session.url.ok_or(Error::MissingCheckoutUrl)?,
+            expires_at: Utc
+                .timestamp_opt(session.expires_at, 0)
+                .single()
+                .ok_or(Error::InvalidCheckoutSession)?,
+        })
+    }
+
+    async fn checkout_status(&self, session_id: &str) -> Result<PaymentStatus> {
+        let id =
+            CheckoutSessionId::from_str(session_id).map_err(|_| Error::InvalidCheckoutSession)?;
+        let session = observe_stripe(
+            "retrieve_checkout_session",
+            CheckoutSession::retrieve(&self.client, &id, &[]),
+        )
+        .await?;
+
+        // The session stays open while the user is still on the checkout page,
+        // so we only give up on it once stripe reports it as expired.
+        Ok(match session.status {
+            Some(CheckoutSessionStatus::Complete) => PaymentStatus::Succeeded,
+            Some(CheckoutSessionStatus::Expired) => PaymentStatus::Failed,
+            _ => PaymentStatus::Pending,
+        })
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for StripePayments {
+    fn kind(&self) -> PaymentProviderKind {
+        PaymentProviderKind::Stripe
+    }
+
+    async fn price(&self) -> Result<Option<Price>> {
+        let price = self
+            .price
+            .get_or_try_init(|| async {
+                let id = PriceId::from_str(&self.price_id).map_err(|_| Error::InvalidPrice)?;
+                let price = observe_stripe(
+                    "retrieve_price",
+                    stripe::Price::retrieve(&self.client, &id, &[]),
+                )
+                .await?;
+
+                Ok::<_, Error>(Price {
+                    amount: price.unit_amount.ok_or(Error::InvalidPrice)?,
+                    currency: price.currency.ok_or(Error::InvalidPrice)?.to_string(),
+                })
+            })
+            .await?;
+
+        Ok(Some(price.clone()))
+    }
+
+    async fn start_payment(&self, info_url: &str) -> Result<PaymentStart> {
+        Ok(PaymentStart::Checkout(
+            self.create_checkout(&self.price_id, info_url).await?,
+        ))
+    }
+
+    async fn check_payment(&self, link: &Link) -> Result<PaymentStatus> {
+        match &link.stripe_session_id {
+            Some(id) => self.checkout_status(id).await,
+            None => Ok(PaymentStatus::Pending),
+        }
+    }
+
+    fn sells_credits(&self) -> bool {
+        true
+    }
+
+    async fn start_credit_payment(&self, price_id: &str) -> Result<Option<Checkout>> {
+        let return_url = format!("{}/", self.base_url);
+
+        Ok(Some(self.create_checkout(price_id, &return_url).await?))
+    }
+
+    async fn check_credit_payment(&self, session_id: &str) -> Result<PaymentStatus> {
+        self.checkout_status(session_id).await
+    }
+}
+
+/// Every link is free, for self hosted instances that don't want to charge anything.
+pub struct DisabledPayments;
+
+#[async_trait]
+impl PaymentProvider for DisabledPayments {
+    fn kind(&self) -> PaymentProviderKind {
+        PaymentProviderKind::Disabled
+    }
+
+    async fn price(&self) -> Result<Option<Price>> {
+        Ok(None)
+    }
+
+    async fn start_payment(&self, _info_url: &str) -> Result<PaymentStart> {
+        Ok(PaymentStart::NotRequired)
+    }
+
+    async fn check_payment(&self, _link: &Link) -> Result<PaymentStatus> {
+        // Links that were still pending when payments were turned off are now free
+        Ok(PaymentStatus::Succeeded)
+    }
+}
+
+/// Links stay pending until an admin approves or rejects them through the admin api.
+pub struct ManualApprovalPayments;
+
+#[async_trait]
+impl PaymentProvider for ManualApprovalPayments {
+    fn kind(&self) -> PaymentProviderKind {
+        PaymentProviderKind::ManualApproval
+    }
+
+    async fn price(&self) -> Result<Option<Price>> {
+        Ok(None)
+    }
+
+    async fn start_payment(&self, _info_url: &str) -> Result<PaymentStart> {
+        Ok(PaymentStart::AwaitingApproval)
+    }
+
+    async fn check_payment(&self, _link: &Link) -> Result<PaymentStatus> {
+        Ok(PaymentStatus::Pending)
+    }
+}
@@ -0,0 +1,138 @@
+use axum::extract::Query;
+use axum::http::{header, StatusCode, Uri};
+use axum::response::{Html, IntoResponse, Response};
+use serde::Deserialize;
+
+use crate::html;
+use crate::models::{Link, Target};
+
+/// How often the picker page reloads to show the current fill levels.
+const REFRESH_SECS: u32 = 10;
+/// The color of the fill bars of targets without a color of their own.
+const DEFAULT_COLOR: &str = "#570df8";
+
+#[derive(Deserialize)]
+struct PickQuery {
+    pick: Option<i32>,
+}
+
+/// The target a visitor picked on the picker page of the link.
+pub fn picked_target<'t>(uri: &Uri, targets: &'t [Target]) -> Option<&'t Target> {
+    let Query(query) = Query::<PickQuery>::try_from_uri(uri).ok()?;
+    let pick = query.pick?;
+
+    targets
+        .iter()
+        .find(|target| target.id == pick && !target.is_full())
+}
+
+/// The query of the entries on the picker page, everything but a previous pick is kept,
+/// e.g. the signature of a signed url.
+fn pick_query(query: Option<&str>, target: &Target) -> String {
+    let mut params = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("pick="))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    params.push(format!("pick={}", target.id));
+    params.join("&")
+}
+
+/// The landing page of links in picker mode, where visitors choose a target themselves.
+/// It is rendered here instead of in the frontend, so it loads fast without the wasm bundle.
+pub fn picker_page(link: &Link, targets: &[Target], query: Option<&str>) -> Response {
+    let most_redirects = targets
+        .iter()
+        .map(|target| target.redirects)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let entries = targets
+        .iter()
+        .map(|target| {
+            // Targets with a capacity fill up towards it, the others relative to the busiest one
+            let fill = match target.capacity {
+                Some(capacity) => {
+                    (i64::from(target.redirects) * 100 / i64::from(capacity.max(1))).min(100)
+                }
+                None => i64::from(target.redirects) * 100 / i64::from(most_redirects),
+            };
+            let count = match target.capacity {
+                Some(capacity) => format!("{} of {capacity}", target.redirects),
+                None => target.redirects.to_string(),
+            };
+            let description = target
+                .description
+                .as_deref()
+                .map(|description| {
+                    format!(
+                        r#"
+            <span class="description">{}</span>"#,
+                        html::escape(description)
+                    )
+                })
+                .unwrap_or_default();
+            // Full targets are still listed, but can't be picked anymore
+            let (open, close) = if target.is_full() {
+                (r#"<div class="entry full">"#.to_string(), "</div>")
+            } else {
+                let href = format!("/{}?{}", link.url, pick_query(query, target));
+                (
+                    format!(r#"<a class="entry" href="{}">"#, html::escape(&href)),
+                    "</a>",
+                )
+            };
+            format!(
+                r#"        {open}
+            <span class="label">{}</span>
+            <span class="count">{count} {}</span>{description}
+            <span class="bar"><span style="width: {fill}%; background: {}"></span></span>
+        {close}"#,
+                html::escape(target.display_name()),
+                if target.is_full() {
+                    "visitors, full"
+                } else if target.redirects == 1 {
+                    "visitor"
+                } else {
+                    "visitors"
+                },
+                html::escape(target.color.as_deref().unwrap_or(DEFAULT_COLOR)),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let page = html::page(
+        "Choose where to go",
+        &format!(
+            r#"    <meta http-equiv="refresh" content="{REFRESH_SECS}">
+    <style>
+        body {{ font-family: sans-serif; max-width: 32rem; margin: 0 auto; padding: 1rem; }}
+        .entry {{ display: grid; grid-template-columns: 1fr auto; gap: 0.25rem 1rem; padding: 0.75rem;
+            margin-bottom: 0.5rem; border: 1px solid #d1d5db; border-radius: 0.5rem; color: inherit; text-decoration: none; }}
+        a.entry:hover {{ border-color: #570df8; }}
+        .entry.full {{ opacity: 0.5; }}
+        .label {{ overflow-wrap: anywhere; }}
+        .count {{ opacity: 0.6; white-space: nowrap; }}
+        .description {{ grid-column: 1 / -1; opacity: 0.8; font-size: 0.875rem; }}
+        .bar {{ grid-column: 1 / -1; height: 0.375rem; background: #e5e7eb; border-radius: 0.25rem; overflow: hidden; }}
+        .bar span {{ display: block; height: 100%; }}
+    </style>"#
+        ),
+        &format!(
+            r#"    <h1>Choose where to go</h1>
+    <nav>
+{entries}
+    </nav>"#
+        ),
+    );
+
+    (
+        StatusCode::OK,
+        [(header::CACHE_CONTROL, "no-store")],
+        Html(page),
+    )
+        .into_response()
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::db::Pool;
+use crate::error::{Error, Result};
+use crate::models::{
+    remaining_capacity, Link, NewProvisioningEvent, ProvisioningDto, ProvisioningEvent, Target,
+};
+use crate::service::{
+    add_targets, get_provisioned_links, get_provisioning_events, record_provisioning_event,
+};
+use crate::target_sources::parse_targets;
+use crate::webhooks::Webhooks;
+
+/// The request a provisioning webhook gets, it answers with a json list of new targets in
+/// the same format as target sources.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProvisioningRequest<'a> {
+    event: &'static str,
+    link: &'a str,
+    remaining_capacity: i64,
+    threshold: i32,
+    targets: Vec<&'a str>,
+}
+
+pub fn provisioning_info(
+    link: &Link,
+    target_results: &[Target],
+    events: Vec<ProvisioningEvent>,
+) -> Option<ProvisioningDto> {
+    link.provisioning_webhook
+        .as_ref()
+        .map(|url| ProvisioningDto {
+            url: url.clone(),
+            threshold: link.provisioning_threshold,
+            remaining_capacity: remaining_capacity(target_results),
+            events,
+        })
+}
+
+/// Periodically asks the provisioning webhooks of links that run out of capacity for new targets.
+/// After a call a link isn't provisioned again for the cooldown, whether the call worked or not.
+pub fn spawn(pool: Pool, webhooks: Arc<Webhooks>, interval: Duration, cooldown: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            match provision_due(&pool, &webhooks, cooldown).await {
+                Ok(0) => {}
+                Ok(provisioned) => info!("Called {provisioned} provisioning webhooks"),
+                Err(err) => error!("Failed to provision links: {:?}", err),
+            }
+        }
+    });
+}
+
+/// Returns the number of links whose webhook was called.
+async fn provision_due(pool: &Pool, webhooks: &Webhooks, cooldown: Duration) -> Result<usize> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+    let cutoff = Utc::now()
+        - chrono::Duration::from_std(cooldown).unwrap_or_else(|_| chrono::Duration::zero());
+
+    let mut due = vec![];
+    for (link, target_results) in get_provisioned_links(&mut connection).await? {
+        // Targets without a capacity never run out
+        let Some(remaining) = remaining_capacity(&target_results) else {
+            continue;
+        };
+        if remaining >= i64::from(link.provisioning_threshold) {
+            continue;
+        }
+
+        let last_event = get_provisioning_events(&mut connection, &link, 1).await?;
+        if last_event.iter().any(|event| event.created_at > cutoff) {
+            continue;
+        }
+
+        due.push((link, target_results, remaining));
+    }
+    drop(connection);
+
+    let count = due.len();
+    for result in join_all(due.iter().map(|(link, target_results, remaining)| {
+        provision(pool, webhooks, link, target_results, *remaining)
+    }))
+    .await
+    {
+        result?;
+    }
+
+    Ok(count)
+}
+
+async fn provision(
+    pool: &Pool,
+    webhooks: &Webhooks,
+    link: &Link,
+    target_results: &[Target],
+    remaining: i64,
+) -> Result<()> {
+    let (Some(url), Some(secret)) = (&link.provisioning_webhook, &link.provisioning_secret) else {
+        return Ok(());
+    };
+
+    let body = serde_json::to_vec(&ProvisioningRequest {
+        event: "capacity_low",
+        link: &link.url,
+        remaining_capacity: remaining,
+        threshold: link.provisioning_threshold,
+        targets: target_results
+            .iter()
+            .map(|target| target.target_url.as_str())
+            .collect(),
+    })
+    .expect("Provisioning requests can be serialized");
+
+    let delivery = webhooks.post(url, secret, &body).await;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (added, error) = match delivery.result.map(|response| parse_targets(&response)) {
+        Ok(Ok(new_targets)) => (
+            add_targets(&mut connection, link, &new_targets).await?,
+            None,
+        ),
+        Ok(Err(err)) => (0, Some(err.to_string())),
+        Err(err) => (0, Some(err.to_string())),
+    };
+
+    match &error {
+        Some(error) => warn!("Provisioning link {} failed: {}", link.url, error),
+        None => info!("Provisioning added {added} targets to link {}", link.url),
+    }
+
+    record_provisioning_event(
+        &mut connection,
+        NewProvisioningEvent {
+            link_id: link.id,
+            remaining_capacity: i32::try_from(remaining).unwrap_or(i32::MAX),
+            attempts: delivery.attempts as i32,
+            added_targets: added as i32,
+            error: error.as_deref(),
+        },
+    )
+    .await
+}
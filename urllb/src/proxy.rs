@@ -0,0 +1,512 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use axum::body::{self, Body, Bytes, StreamBody};
+use axum::extract::{FromRequest, FromRequestParts, Path, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use axum_client_ip::SecureClientIp;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::db::Pool;
+use crate::domains::Namespace;
+use crate::models::Target;
+use crate::Config;
+
+/// Headers that only apply to a single connection and must not be forwarded.
+fn hop_by_hop_headers() -> [HeaderName; 7] {
+    [
+        header::CONNECTION,
+        header::PROXY_AUTHENTICATE,
+        header::PROXY_AUTHORIZATION,
+        header::TE,
+        header::TRAILER,
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+    ]
+}
+
+/// A request to a link, with everything needed to redirect it or to forward it to a target.
+pub struct Visit {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub client_ip: IpAddr,
+    pub body: Bytes,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S, Body> for Visit {
+    type Rejection = Response;
+
+    async fn from_request(request: Request<Body>, state: &S) -> Result<Self, Response> {
+        let (mut parts, request_body) = request.into_parts();
+
+        let SecureClientIp(client_ip) = SecureClientIp::from_request_parts(&mut parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let method = parts.method.clone();
+        let uri = parts.uri.clone();
+        let headers = parts.headers.clone();
+
+        let body = Bytes::from_request(Request::from_parts(parts, request_body), state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        Ok(Visit {
+            method,
+            uri,
+            headers,
+            client_ip,
+            body,
+        })
+    }
+}
+
+/// Forwards requests of links in proxy mode to their targets and streams the responses back.
+pub struct Proxy {
+    client: reqwest::Client,
+    max_attempts: usize,
+    allow_private_targets: bool,
+}
+
+impl Proxy {
+    pub fn new(config: &Config) -> Self {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config.proxy_connect_timeout_secs))
+            .timeout(Duration::from_secs(config.proxy_timeout_secs))
+            .redirect(reqwest::redirect::Policy::none());
+
+        if !config.proxy_allow_private_targets {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Self {
+            client: builder.build().expect("Failed to build proxy client"),
+            max_attempts: config.proxy_max_attempts.max(1),
+            allow_private_targets: config.proxy_allow_private_targets,
+        }
+    }
+
+    /// Sends the request to the targets in the given order, moving on to the next one as long as
+    /// the connection fails. Returns the target that answered together with its response.
+    pub async fn forward<'t>(
+        &self,
+        order: &[&'t Target],
+        path: &str,
+        visit: &Visit,
+    ) -> Result<(&'t Target, Response), StatusCode> {
+        for target in order.iter().take(self.max_attempts) {
+            let Some(url) = upstream_url(&target.target_url, path, visit.uri.query()) else {
+                tracing::debug!("Target {} can't be proxied to", target.target_url);
+                continue;
+            };
+
+            if !self.allow_private_targets && !is_public_host(&url) {
+                tracing::debug!("Refusing to proxy to private target {}", url);
+                continue;
+            }
+
+            let mut request = self
+                .client
+                .request(visit.method.clone(), url)
+                .headers(forwarded_headers(visit))
+                .body(visit.body.clone());
+
+            if let Some(host) = visit.headers.get(header::HOST) {
+                request = request.header("x-forwarded-host", host);
+            }
+
+            match request.send().await {
+                Ok(response) => return Ok((target, into_response(response))),
+                Err(err) if err.is_connect() => {
+                    tracing::debug!("Connecting to {} failed: {}", target.target_url, err);
+                }
+                Err(err) if err.is_timeout() => return Err(StatusCode::GATEWAY_TIMEOUT),
+                Err(err) => {
+                    tracing::debug!("Proxying to {} failed: {}", target.target_url, err);
+                    return Err(StatusCode::BAD_GATEWAY);
+                }
+            }
+        }
+
+        Err(StatusCode::BAD_GATEWAY)
+    }
+}
+
+/// Whether the path has `.` or `..` segments, which would be resolved when joining it to the
+/// target url and could leave its path. Encoded dots and backslashes count as well, since the
+/// url parser treats them the same.
+fn has_dot_segment(path: &str) -> bool {
+    path.split(['/', '\\']).any(|segment| {
+        let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+        segment == "." || segment == ".."
+    })
+}
+
+/// The url of the request path below the target url, e.g. `/abcde/api/users` on the target
+/// `http://10.0.0.1:8080/v1` goes to `http://10.0.0.1:8080/v1/api/users`.
+fn upstream_url(target: &str, path: &str, query: Option<&str>) -> Option<Url> {
+    let mut url = Url::parse(target).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || has_dot_segment(path) {
+        return None;
+    }
+
+    let upstream_path = format!("{}/{}", url.path().trim_end_matches('/'), path);
+    url.set_path(&upstream_path);
+
+    let upstream_query = match (url.query(), query) {
+        (Some(target_query), Some(query)) => Some(format!("{target_query}&{query}")),
+        (target_query, query) => target_query.or(query).map(str::to_string),
+    };
+    url.set_query(upstream_query.as_deref());
+
+    Some(url)
+}
+
+/// Removes the hop-by-hop headers, including the ones the `Connection` header lists.
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+
+    for name in hop_by_hop_headers().into_iter().chain(listed) {
+        headers.remove(name);
+    }
+}
+
+/// The headers sent to the target. Cookies stay behind, they belong to this origin and not to
+/// the target.
+fn forwarded_headers(visit: &Visit) -> HeaderMap {
+    let mut headers = visit.headers.clone();
+    remove_hop_by_hop_headers(&mut headers);
+    headers.remove(header::HOST);
+    headers.remove(header::COOKIE);
+
+    let forwarded_for = match headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(forwarded_for) => format!("{forwarded_for}, {}", visit.client_ip),
+        None => visit.client_ip.to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+        headers.insert("x-forwarded-for", value);
+    }
+
+    headers
+}
+
+/// The response of the target as it is sent to the visitor. Targets are served from the origin
+/// of the app, so they can't set cookies on it and their pages are sandboxed, keeping their
+/// scripts away from the tokens the web client stores.
+fn into_response(response: reqwest::Response) -> Response {
+    let status = response.status();
+    let mut headers = response.headers().clone();
+    remove_hop_by_hop_headers(&mut headers);
+    headers.remove(header::SET_COOKIE);
+    headers.append(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox"),
+    );
+
+    let mut response = body::boxed(StreamBody::new(response.bytes_stream())).into_response();
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+/// Whether an address is reachable from the internet, everything else is off limits for
+/// proxied requests unless private targets are allowed.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Carrier grade nat, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local, fc00::/7
+                    || first & 0xfe00 == 0xfc00
+                    // Link local, fe80::/10
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Hosts given by name are checked by the resolver, ip addresses in the url have to be checked here.
+pub fn is_public_host(url: &Url) -> bool {
+    match url.host_str() {
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(is_public_ip)
+            .unwrap_or(true),
+        None => false,
+    }
+}
+
+/// Resolves hosts with the system resolver, but only returns public addresses.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr: &SocketAddr| is_public_ip(addr.ip()))
+                .collect::<Vec<_>>();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ProxyParams {
+    link: String,
+    path: String,
+}
+
+/// Requests below a link in proxy mode, e.g. `/abcde/api/users`, are forwarded to its targets.
+pub async fn proxy_link(
+    Path(params): Path<ProxyParams>,
+    State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(proxy): Extension<Arc<Proxy>>,
+    namespace: Namespace,
+    visit: Visit,
+) -> Result<Response, StatusCode> {
+    if has_dot_segment(&params.path) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    crate::visit_link(
+        &pool,
+        &config,
+        &proxy,
+        &namespace,
+        &params.link,
+        Some(&params.path),
+        visit,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use axum::routing::any;
+    use axum::Router;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{config_with, create_test_link, pool};
+
+    /// A local upstream server that answers with the path and query it was asked for.
+    fn spawn_upstream(name: &'static str) -> SocketAddr {
+        let app = Router::new().fallback(any(move |uri: Uri| async move {
+            format!("{name} {}", uri.path_and_query().unwrap())
+        }));
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    /// An address nothing listens on, connecting to it fails right away.
+    fn closed_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn visit(uri: &str) -> Visit {
+        Visit {
+            method: Method::GET,
+            uri: uri.parse().unwrap(),
+            headers: HeaderMap::new(),
+            client_ip: "127.0.0.1".parse().unwrap(),
+            body: Bytes::new(),
+        }
+    }
+
+    async fn proxy(pool: &Pool, slug: &str, path: &str, query: &str) -> Response {
+        let config = config_with(&[("PROXY_ALLOW_PRIVATE_TARGETS", "true")]);
+        let proxy = Arc::new(Proxy::new(&config));
+
+        match proxy_link(
+            Path(ProxyParams {
+                link: slug.to_string(),
+                path: path.to_string(),
+            }),
+            State(pool.clone()),
+            Extension(config),
+            Extension(proxy),
+            Namespace(None),
+            visit(&format!("/{slug}/{path}{query}")),
+        )
+        .await
+        {
+            Ok(response) => response,
+            Err(status) => status.into_response(),
+        }
+    }
+
+    async fn text(response: Response) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn dot_segments_are_detected() {
+        for path in [
+            "..",
+            "api/../admin",
+            "./api",
+            "api/%2e%2e/admin",
+            "api/%2E./x",
+            "api\\..\\x",
+        ] {
+            assert!(has_dot_segment(path), "{path}");
+        }
+        for path in ["api/users", "api/..hidden", "api/v1.2/users", ""] {
+            assert!(!has_dot_segment(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn upstream_urls_stay_below_the_target() {
+        let url = upstream_url("http://10.0.0.1:8080/v1/", "api/users", Some("page=2")).unwrap();
+        assert_eq!(url.as_str(), "http://10.0.0.1:8080/v1/api/users?page=2");
+
+        let url = upstream_url("http://10.0.0.1/v1?key=a", "users", Some("page=2")).unwrap();
+        assert_eq!(url.as_str(), "http://10.0.0.1/v1/users?key=a&page=2");
+
+        assert!(upstream_url("http://10.0.0.1/v1", "../admin", None).is_none());
+        assert!(upstream_url("http://10.0.0.1/v1", "%2e%2e/admin", None).is_none());
+        assert!(upstream_url("ftp://10.0.0.1/v1", "users", None).is_none());
+    }
+
+    #[test]
+    fn cookies_and_connection_headers_are_not_forwarded() {
+        let mut visit = visit("/abcde/api");
+        for (name, value) in [
+            ("connection", "keep-alive, x-hop"),
+            ("x-hop", "1"),
+            ("cookie", "session=secret"),
+            ("host", "urllb.example"),
+            ("accept", "text/html"),
+        ] {
+            visit.headers.insert(name, HeaderValue::from_static(value));
+        }
+
+        let headers = forwarded_headers(&visit);
+        assert_eq!(headers.get(header::ACCEPT).unwrap(), "text/html");
+        assert_eq!(headers.get("x-forwarded-for").unwrap(), "127.0.0.1");
+        for name in ["connection", "x-hop", "cookie", "host"] {
+            assert!(!headers.contains_key(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn responses_are_sandboxed_and_set_no_cookies() {
+        let upstream = hyper::Response::builder()
+            .status(StatusCode::CREATED)
+            .header("connection", "x-hop")
+            .header("x-hop", "1")
+            .header("set-cookie", "session=upstream")
+            .header("content-security-policy", "default-src 'self'")
+            .header("content-type", "text/html")
+            .body("")
+            .unwrap();
+
+        let response = into_response(reqwest::Response::from(upstream));
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "text/html");
+        for name in ["connection", "x-hop", "set-cookie"] {
+            assert!(!response.headers().contains_key(name), "{name}");
+        }
+        let policies = response
+            .headers()
+            .get_all(header::CONTENT_SECURITY_POLICY)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(policies, ["default-src 'self'", "sandbox"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn requests_are_forwarded_to_a_local_upstream() {
+        let pool = pool().await;
+        let upstream = spawn_upstream("upstream");
+        let (link, _, _) = create_test_link(
+            &pool,
+            json!({ "proxy": true, "targets": [{ "targetUrl": format!("http://{upstream}/v1") }] }),
+            None,
+        )
+        .await;
+
+        let response = proxy(&pool, &link.url, "api/users", "?page=2").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, "upstream /v1/api/users?page=2");
+
+        let escape = proxy(&pool, &link.url, "api/../../admin", "").await;
+        assert_eq!(escape.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn failed_connections_are_retried_on_another_target() {
+        let pool = pool().await;
+        let upstream = spawn_upstream("alive");
+        let (link, _, _) = create_test_link(
+            &pool,
+            json!({ "proxy": true, "targets": [
+                { "targetUrl": format!("http://{}", closed_addr()) },
+                { "targetUrl": format!("http://{upstream}") },
+            ] }),
+            None,
+        )
+        .await;
+
+        // Whichever target is picked first, the request ends up at the one that is up
+        for _ in 0..5 {
+            let response = proxy(&pool, &link.url, "health", "").await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(text(response).await, "alive /health");
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Extension;
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+
+use crate::db::Pool;
+use crate::domains::Namespace;
+use crate::error::{Api, ApiError, Error, Result};
+use crate::service::get_link_and_targets;
+use crate::{Config, Params};
+
+const MAX_SIZE: u32 = 4096;
+const MAX_MARGIN: u32 = 32;
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+#[derive(Deserialize)]
+pub struct QrOptions {
+    #[serde(default)]
+    format: QrFormat,
+    /// Width and height of the image in pixels, the svg can be scaled freely anyway
+    #[serde(default = "default_size")]
+    size: u32,
+    /// Width of the quiet zone around the code, in modules
+    #[serde(default = "default_margin")]
+    margin: u32,
+    /// Error correction level, one of `L`, `M`, `Q` or `H`
+    #[serde(default = "default_ec")]
+    ec: char,
+}
+
+fn default_size() -> u32 {
+    512
+}
+
+fn default_margin() -> u32 {
+    4
+}
+
+fn default_ec() -> char {
+    'M'
+}
+
+/// Checks the options against the limits and returns the error correction level they ask for.
+fn check_options(options: &QrOptions) -> std::result::Result<EcLevel, ApiError> {
+    let ec_level = match options.ec.to_ascii_uppercase() {
+        'L' => EcLevel::L,
+        'M' => EcLevel::M,
+        'Q' => EcLevel::Q,
+        'H' => EcLevel::H,
+        _ => {
+            return Err(ApiError::bad_request(
+                "invalid_error_correction",
+                "The error correction level has to be L, M, Q or H",
+            )
+            .with_field("ec", "invalid_error_correction"))
+        }
+    };
+
+    if options.size == 0 || options.size > MAX_SIZE {
+        return Err(ApiError::bad_request(
+            "invalid_size",
+            format!("The size has to be between 1 and {MAX_SIZE}"),
+        )
+        .with_field("size", "invalid_size"));
+    }
+
+    if options.margin > MAX_MARGIN {
+        return Err(ApiError::bad_request(
+            "invalid_margin",
+            format!("The margin can be at most {MAX_MARGIN}"),
+        )
+        .with_field("margin", "invalid_margin"));
+    }
+
+    Ok(ec_level)
+}
+
+/// Renders a qr code of the link as svg or png.
+pub async fn link_qr(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    Namespace(domain): Namespace,
+    Api(Query(options)): Api<Query<QrOptions>>,
+) -> std::result::Result<impl IntoResponse, ApiError> {
+    let ec_level = check_options(&options)?;
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(
+        &mut connection,
+        domain.as_ref().map(|domain| domain.id),
+        &params.link,
+    )
+    .await
+    .map_err(ApiError::not_found("link"))?;
+
+    let code =
+        QrCode::with_error_correction_level(config.link_url(domain.as_ref(), &link.url), ec_level)
+            .map_err(Error::Qr)?;
+
+    let (content_type, body) = match options.format {
+        QrFormat::Svg => (
+            "image/svg+xml",
+            render_svg(&code, options.size, options.margin).into_bytes(),
+        ),
+        QrFormat::Png => (
+            "image/png",
+            render_png(&code, options.size, options.margin)?,
+        ),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            // The code only changes if the link is deleted and its slug reused
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        body,
+    ))
+}
+
+/// Renders the code as a single path, using one unit of the view box per module.
+fn render_svg(code: &QrCode, size: u32, margin: u32) -> String {
+    let width = code.width() as u32;
+    let total = width + 2 * margin;
+
+    let mut path = String::new();
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let x = i as u32 % width + margin;
+            let y = i as u32 / width + margin;
+            write!(path, "M{x} {y}h1v1h-1z").unwrap();
+        }
+    }
+
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {total} {total}" "#,
+            r#"width="{size}" height="{size}" shape-rendering="crispEdges">"#,
+            r##"<rect width="{total}" height="{total}" fill="#fff"/>"##,
+            r##"<path d="{path}" fill="#000"/></svg>"##,
+        ),
+        total = total,
+        size = size,
+        path = path,
+    )
+}
+
+/// Renders the code as a grayscale png. Modules are whole pixels to keep the edges sharp,
+/// so the image is as large as possible without exceeding the requested size.
+fn render_png(code: &QrCode, size: u32, margin: u32) -> Result<Vec<u8>> {
+    let width = code.width() as u32;
+    let total = width + 2 * margin;
+    let module = (size / total).max(1);
+    let pixels = total * module;
+
+    let modules = code.to_colors();
+    let mut image = vec![255u8; (pixels * pixels) as usize];
+    for y in 0..pixels {
+        for x in 0..pixels {
+            let (module_x, module_y) = (x / module, y / module);
+            let inside = (margin..margin + width).contains(&module_x)
+                && (margin..margin + width).contains(&module_y);
+
+            if inside
+                && modules[((module_y - margin) * width + module_x - margin) as usize]
+                    == Color::Dark
+            {
+                image[(y * pixels + x) as usize] = 0;
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, pixels, pixels);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&image)?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::models::ErrorDto;
+    use crate::test_support::json_response;
+
+    fn code() -> QrCode {
+        QrCode::new("https://hurlurl.com/abcde").unwrap()
+    }
+
+    fn options(size: u32, margin: u32, ec: char) -> QrOptions {
+        QrOptions {
+            format: QrFormat::Png,
+            size,
+            margin,
+            ec,
+        }
+    }
+
+    /// The width of the image and its pixels, one byte per pixel.
+    fn decode_png(png: &[u8]) -> (u32, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(info.width, info.height);
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        (info.width, pixels)
+    }
+
+    async fn rejected_field(options: QrOptions) -> String {
+        let error = check_options(&options).err().unwrap();
+        let (status, error) = json_response::<ErrorDto>(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        error.fields.into_keys().next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn options_are_limited() {
+        assert!(matches!(
+            check_options(&options(512, 4, 'h')),
+            Ok(EcLevel::H)
+        ));
+        assert!(check_options(&options(MAX_SIZE, MAX_MARGIN, 'L')).is_ok());
+
+        assert_eq!(rejected_field(options(0, 4, 'M')).await, "size");
+        assert_eq!(rejected_field(options(MAX_SIZE + 1, 4, 'M')).await, "size");
+        assert_eq!(
+            rejected_field(options(512, MAX_MARGIN + 1, 'M')).await,
+            "margin"
+        );
+        assert_eq!(rejected_field(options(512, 4, 'x')).await, "ec");
+    }
+
+    #[test]
+    fn svgs_have_one_square_per_dark_module() {
+        let code = code();
+        let total = code.width() + 8;
+
+        let svg = render_svg(&code, 300, 4);
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains(&format!(r#"viewBox="0 0 {total} {total}""#)));
+        assert!(svg.contains(r#"width="300" height="300""#));
+        let dark = code
+            .to_colors()
+            .into_iter()
+            .filter(|color| *color == Color::Dark)
+            .count();
+        assert_eq!(svg.matches("h1v1h-1z").count(), dark);
+        // The top left module belongs to a finder pattern, so it's dark
+        assert!(svg.contains("M4 4h1v1h-1z"));
+    }
+
+    #[test]
+    fn pngs_fit_the_size_with_whole_modules() {
+        let code = code();
+        let total = code.width() as u32 + 8;
+
+        for size in [total, 512, 1000, MAX_SIZE] {
+            let (width, _) = decode_png(&render_png(&code, size, 4).unwrap());
+            assert!(width <= size, "{width} > {size}");
+            assert_eq!(width % total, 0);
+            assert!(width + total > size, "{width} could be larger for {size}");
+        }
+
+        // Modules can't be smaller than a pixel
+        let (width, _) = decode_png(&render_png(&code, 1, 4).unwrap());
+        assert_eq!(width, total);
+    }
+
+    #[test]
+    fn pngs_show_the_code_inside_the_margin() {
+        let code = code();
+        let total = code.width() as u32 + 2;
+        let module = 10;
+
+        let (width, pixels) = decode_png(&render_png(&code, total * module, 1).unwrap());
+        assert_eq!(width, total * module);
+
+        let pixel = |x: u32, y: u32| pixels[(y * width + x) as usize];
+        assert_eq!(pixel(0, 0), 255, "the margin is white");
+        assert_eq!(pixel(module, module), 0, "the finder pattern is dark");
+        assert_eq!(pixel(2 * module - 1, 2 * module - 1), 0);
+        assert_eq!(pixel(2 * module, 2 * module), 255);
+    }
+}
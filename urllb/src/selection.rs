@@ -0,0 +1,189 @@
+use axum::extract::Query;
+use axum::http::Uri;
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::models::Target;
+
+const MAX_GROUP_CODE_LENGTH: usize = 64;
+
+#[derive(Deserialize)]
+struct GroupQuery {
+    group: Option<String>,
+}
+
+/// The group code a visitor came with.
+pub fn group_code(uri: &Uri) -> Option<String> {
+    let Query(query) = Query::<GroupQuery>::try_from_uri(uri).ok()?;
+    normalize_group_code(&query.group?)
+}
+
+/// Codes that only differ in case end up in the same group.
+pub fn normalize_group_code(code: &str) -> Option<String> {
+    let code = code.trim().to_lowercase();
+
+    (!code.is_empty() && code.len() <= MAX_GROUP_CODE_LENGTH).then_some(code)
+}
+
+/// The order in which the targets of a link are tried. Visitors are sent to the first one,
+/// the others are fallbacks for proxied requests whose target can't be reached. Full targets
+/// are left out, targets with a higher weight end up in front more often.
+///
+/// Visitors with a group code are ordered with weighted rendezvous hashing: every target gets a
+/// score for the group and the highest one wins. Adding or removing a target only moves the
+/// groups that win or lose it, all others stay where they are.
+pub fn target_order<'t>(targets: &'t [Target], group: Option<&str>) -> Vec<&'t Target> {
+    let mut rng = rand::thread_rng();
+
+    let mut scored = targets
+        .iter()
+        .filter(|target| !target.is_full())
+        .map(|target| {
+            let uniform = match group {
+                Some(group) => group_hash(group, target),
+                None => rng.gen(),
+            };
+            (weighted_score(target.weight, uniform), target)
+        })
+        .collect::<Vec<_>>();
+
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    scored.into_iter().map(|(_, target)| target).collect()
+}
+
+/// Sorting by this score picks every target with a probability proportional to its weight,
+/// for a uniformly distributed value between 0 and 1.
+fn weighted_score(weight: i32, uniform: f64) -> f64 {
+    -f64::from(weight.max(1)) / uniform.ln()
+}
+
+/// A value between 0 and 1 that is the same for every visitor of the group.
+fn group_hash(group: &str, target: &Target) -> f64 {
+    let digest = Sha256::new()
+        .chain_update(target.id.to_be_bytes())
+        .chain_update(group.as_bytes())
+        .finalize();
+    let hash = u64::from_be_bytes(digest[..8].try_into().expect("sha256 digests are 32 bytes"));
+
+    // The 53 bits a f64 can hold exactly, shifted away from 0 so the logarithm stays finite
+    ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 20_000;
+
+    fn target(id: i32, weight: i32, capacity: Option<i32>) -> Target {
+        Target {
+            id,
+            link_id: 1,
+            target_url: format!("https://example.com/{id}"),
+            redirects: 10,
+            label: None,
+            description: None,
+            color: None,
+            weight,
+            capacity,
+            active: true,
+        }
+    }
+
+    fn first_ids(targets: &[Target], group: Option<&str>) -> Vec<i32> {
+        target_order(targets, group)
+            .into_iter()
+            .map(|target| target.id)
+            .collect()
+    }
+
+    /// How often the target with the id comes first, out of [`SAMPLES`] orders.
+    fn share_of(id: i32, targets: &[Target], groups: bool) -> f64 {
+        let wins = (0..SAMPLES)
+            .filter(|sample| {
+                let group = groups.then(|| format!("group-{sample}"));
+                target_order(targets, group.as_deref())[0].id == id
+            })
+            .count();
+        wins as f64 / SAMPLES as f64
+    }
+
+    #[test]
+    fn groups_always_get_the_same_order() {
+        let targets = [target(1, 1, None), target(2, 2, None), target(3, 5, None)];
+
+        let order = first_ids(&targets, Some("team-a"));
+        for _ in 0..100 {
+            assert_eq!(first_ids(&targets, Some("team-a")), order);
+        }
+        assert_eq!(
+            group_hash("team-a", &targets[0]),
+            group_hash("team-a", &targets[0])
+        );
+        assert_ne!(
+            group_hash("team-a", &targets[0]),
+            group_hash("team-b", &targets[0])
+        );
+    }
+
+    #[test]
+    fn targets_are_chosen_proportionally_to_their_weight() {
+        let targets = [target(1, 1, None), target(2, 3, None)];
+
+        let random = share_of(2, &targets, false);
+        assert!((random - 0.75).abs() < 0.02, "random share was {random}");
+
+        let grouped = share_of(2, &targets, true);
+        assert!((grouped - 0.75).abs() < 0.02, "grouped share was {grouped}");
+    }
+
+    #[test]
+    fn weights_below_one_count_as_one() {
+        let targets = [target(1, 0, None), target(2, 1, None)];
+
+        let share = share_of(1, &targets, true);
+        assert!((share - 0.5).abs() < 0.02, "share was {share}");
+    }
+
+    #[test]
+    fn full_targets_are_skipped() {
+        let targets = [
+            target(1, 100, Some(10)),
+            target(2, 1, Some(11)),
+            target(3, 1, None),
+        ];
+
+        for sample in 0..1000 {
+            let group = format!("group-{sample}");
+            assert_eq!(first_ids(&targets, Some(&group)).len(), 2);
+            assert!(!first_ids(&targets, None).contains(&1));
+        }
+    }
+
+    #[test]
+    fn removing_a_target_only_moves_its_groups() {
+        let targets = [target(1, 1, None), target(2, 2, None), target(3, 1, None)];
+
+        for sample in 0..1000 {
+            let group = format!("group-{sample}");
+            let before = first_ids(&targets, Some(&group))[0];
+            let after = first_ids(&targets[..2], Some(&group))[0];
+            if before != 3 {
+                assert_eq!(before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn group_codes_are_normalized() {
+        assert_eq!(normalize_group_code(" Team-A "), Some("team-a".to_string()));
+        assert_eq!(normalize_group_code("  "), None);
+        assert_eq!(normalize_group_code(&"a".repeat(65)), None);
+        assert_eq!(
+            group_code(&"/abc?group=TEAM".parse().unwrap()),
+            Some("team".to_string())
+        );
+        assert_eq!(group_code(&"/abc".parse().unwrap()), None);
+    }
+}
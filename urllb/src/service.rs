@@ -1,101 +1,845 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-use crate::error::Result;
+use crate::error::{Error, Result};
+use chrono::{DateTime, Utc};
 use diesel::associations::HasTable;
+use diesel::dsl::count_distinct;
 use diesel::expression_methods::ExpressionMethods;
+use diesel::BoolExpressionMethods;
 use diesel::QueryDsl;
-use diesel_async::RunQueryDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use ipnet::IpNet;
+use nanoid::nanoid;
+use tracing::instrument;
 
 use shared::{schema, PaymentStatus};
 
 use crate::db::Connection;
-use crate::models::{CreateLinkDto, Link, NewLink, NewTarget, Target};
+use crate::events::{self, Event};
+use crate::models::{
+    Click, ClickDto, CreateLinkDto, CreateTargetDto, CreateWebhookDto, CreditReason, Domain, Link,
+    LinkDto, NewClick, NewCreditLedgerEntry, NewLink, NewProvisioningEvent, NewTarget,
+    NewWebhookDelivery, NewWebhookSubscription, ProvisioningEvent, Target, TargetGroupsDto,
+    WebhookDelivery, WebhookSubscription,
+};
 use crate::schema::links::dsl::*;
 use crate::schema::links::url;
 use crate::schema::targets::dsl::targets;
 use crate::schema::targets::link_id;
 
+/// Looks up a link by its slug, either on a custom domain or on the public base url.
+#[instrument(skip_all)]
 pub async fn get_link_and_targets<'c>(
     connection: &mut Connection<'c>,
+    domain: Option<i32>,
     link: &str,
 ) -> Result<(Link, Vec<Target>)> {
-    let link = links.filter(url.eq(link)).first::<Link>(connection).await?;
+    let query = links.filter(url.eq(link)).into_boxed();
+    let query = match domain {
+        Some(domain) => query.filter(domain_id.eq(domain)),
+        None => query.filter(domain_id.is_null()),
+    };
+
+    let link = query.first::<Link>(connection).await?;
 
     let target_results: Vec<Target> = targets
         .filter(link_id.eq(link.id))
+        .filter(schema::targets::active.eq(true))
         .load::<Target>(connection)
         .await?;
 
     Ok((link, target_results))
 }
 
+#[instrument(skip_all)]
+pub async fn get_links_with_payment_status<'c>(
+    connection: &mut Connection<'c>,
+    status: PaymentStatus,
+) -> Result<Vec<(Link, Vec<Target>)>> {
+    let link_results = links
+        .filter(payment_status.eq(status))
+        .order(id.asc())
+        .load::<Link>(connection)
+        .await?;
+
+    with_targets(connection, link_results).await
+}
+
+#[instrument(skip_all)]
+pub async fn get_workspace_links<'c>(
+    connection: &mut Connection<'c>,
+    workspace: i32,
+) -> Result<Vec<(Link, Vec<Target>)>> {
+    let link_results = links
+        .filter(workspace_id.eq(workspace))
+        .order(id.desc())
+        .load::<Link>(connection)
+        .await?;
+
+    with_targets(connection, link_results).await
+}
+
+#[instrument(skip_all)]
+async fn with_targets<'c>(
+    connection: &mut Connection<'c>,
+    link_results: Vec<Link>,
+) -> Result<Vec<(Link, Vec<Target>)>> {
+    let link_ids: Vec<i32> = link_results.iter().map(|link| link.id).collect();
+
+    let target_results: Vec<Target> = targets
+        .filter(link_id.eq_any(link_ids))
+        .filter(schema::targets::active.eq(true))
+        .load::<Target>(connection)
+        .await?;
+
+    Ok(link_results
+        .into_iter()
+        .map(|link| {
+            let link_targets = target_results
+                .iter()
+                .filter(|target| target.link_id == link.id)
+                .cloned()
+                .collect();
+            (link, link_targets)
+        })
+        .collect())
+}
+
+/// Builds the dtos for a list of links, looking up the hosts of their custom domains.
+/// Stripe sessions are left out, they are only needed internally.
+#[instrument(skip_all)]
+pub async fn into_link_dtos<'c>(
+    connection: &mut Connection<'c>,
+    link_results: Vec<(Link, Vec<Target>)>,
+) -> Result<Vec<LinkDto>> {
+    let domain_ids: Vec<i32> = link_results
+        .iter()
+        .filter_map(|(link, _)| link.domain_id)
+        .collect();
+
+    let link_domains = schema::domains::table
+        .filter(schema::domains::id.eq_any(domain_ids))
+        .load::<Domain>(connection)
+        .await?;
+
+    Ok(link_results
+        .into_iter()
+        .map(|(mut link, target_results)| {
+            link.stripe_session_id = None;
+            let password_protected = link.password_hash.is_some();
+            let signed = link.signing_secret.is_some();
+            let domain = link_domains
+                .iter()
+                .find(|domain| Some(domain.id) == link.domain_id)
+                .map(|domain| domain.host.clone());
+            LinkDto {
+                link,
+                targets: target_results,
+                domain,
+                password_protected,
+                signed,
+                target_groups: vec![],
+                target_source: None,
+                provisioning: None,
+                management_token: None,
+                provisioning_secret: None,
+            }
+        })
+        .collect())
+}
+
+/// Returns all unpaid links whose checkout expired before the given time.
+#[instrument(skip_all)]
+pub async fn get_expired_unpaid_links<'c>(
+    connection: &mut Connection<'c>,
+    before: DateTime<Utc>,
+) -> Result<Vec<Link>> {
+    Ok(links
+        .filter(payment_status.eq_any(vec![PaymentStatus::Pending, PaymentStatus::Failed]))
+        .filter(payment_expires_at.lt(before))
+        .load::<Link>(connection)
+        .await?)
+}
+
+/// Deletes a link together with its targets.
+#[instrument(skip_all)]
+pub async fn delete_link<'c>(connection: &mut Connection<'c>, link: &Link) -> Result<()> {
+    let deleted_id = link.id;
+
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                diesel::delete(targets.filter(link_id.eq(deleted_id)))
+                    .execute(connection)
+                    .await?;
+
+                diesel::delete(links.filter(id.eq(deleted_id)))
+                    .execute(connection)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// Returns the new redirect counts of the link and of the target.
+#[instrument(skip_all)]
 pub async fn increase_redirect_count<'c>(
     connection: &mut Connection<'c>,
     link: &Link,
     target: &Target,
-) -> Result<()> {
-    diesel::update(link)
+) -> Result<(i32, i32)> {
+    let link_redirects = diesel::update(link)
         .set(schema::links::redirects.eq(schema::links::redirects + 1))
-        .execute(connection)
+        .returning(schema::links::redirects)
+        .get_result::<i32>(connection)
         .await?;
 
-    diesel::update(target)
+    let target_redirects = diesel::update(target)
         .set(schema::targets::redirects.eq(schema::targets::redirects + 1))
+        .returning(schema::targets::redirects)
+        .get_result::<i32>(connection)
+        .await?;
+
+    Ok((link_redirects, target_redirects))
+}
+
+/// Records a redirect, together with the participant of the signed url it came through
+/// and the group of the visitor.
+#[instrument(skip_all)]
+pub async fn record_click<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    target: &Target,
+    participant: Option<&str>,
+    group: Option<&str>,
+) -> Result<()> {
+    diesel::insert_into(schema::clicks::table)
+        .values(NewClick {
+            link_id: link.id,
+            target_id: target.id,
+            participant,
+            group_code: group,
+        })
         .execute(connection)
         .await?;
 
     Ok(())
 }
 
+#[instrument(skip_all)]
+pub async fn get_recent_clicks<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    limit: i64,
+) -> Result<Vec<ClickDto>> {
+    let results = schema::clicks::table
+        .inner_join(schema::targets::table)
+        .filter(schema::clicks::link_id.eq(link.id))
+        .order(schema::clicks::id.desc())
+        .limit(limit)
+        .select((
+            schema::clicks::all_columns,
+            (
+                schema::targets::target_url,
+                schema::targets::label,
+                schema::targets::color,
+            ),
+        ))
+        .load::<(Click, (String, Option<String>, Option<String>))>(connection)
+        .await?;
+
+    Ok(results
+        .into_iter()
+        .map(|(click, (target_url, label, color))| ClickDto {
+            click,
+            target_url,
+            label,
+            color,
+        })
+        .collect())
+}
+
+/// How many distinct groups were sent to each target of the link.
+#[instrument(skip_all)]
+pub async fn get_target_groups<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+) -> Result<Vec<TargetGroupsDto>> {
+    let counts = schema::clicks::table
+        .filter(schema::clicks::link_id.eq(link.id))
+        .filter(schema::clicks::group_code.is_not_null())
+        .group_by(schema::clicks::target_id)
+        .select((
+            schema::clicks::target_id,
+            count_distinct(schema::clicks::group_code),
+        ))
+        .load::<(i32, i64)>(connection)
+        .await?;
+
+    Ok(counts
+        .into_iter()
+        .map(|(target_id, groups)| TargetGroupsDto { target_id, groups })
+        .collect())
+}
+
+#[instrument(skip_all)]
 pub async fn set_link_payment_status<'c>(
     connection: &mut Connection<'c>,
-    link: &str,
+    link: &Link,
     status: PaymentStatus,
 ) -> Result<()> {
-    diesel::update(links.filter(url.eq(link)))
+    let paid =
+        status == PaymentStatus::Succeeded && link.payment_status != Some(PaymentStatus::Succeeded);
+
+    diesel::update(link)
         .set(schema::links::payment_status.eq(status))
         .execute(connection)
         .await?;
 
+    // Payments noticed by the info page or the garbage collector and approvals all end up here
+    if paid {
+        events::emit(connection, link, Event::Paid).await?;
+    }
+
     Ok(())
 }
 
-pub async fn create_link<'c>(
+/// Attaches a new checkout session to a link and resets its payment status to pending.
+#[instrument(skip_all)]
+pub async fn set_link_stripe_session<'c>(
     connection: &mut Connection<'c>,
+    link: &Link,
+    session_id: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<()> {
+    diesel::update(link)
+        .set((
+            schema::links::stripe_session_id.eq(session_id),
+            schema::links::payment_status.eq(PaymentStatus::Pending),
+            schema::links::payment_expires_at.eq(expires_at),
+        ))
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+/// The payment state a link is created with, links that don't need to be paid use the default.
+#[derive(Default)]
+pub struct LinkPayment<'a> {
+    pub stripe_session_id: Option<&'a str>,
+    pub status: Option<PaymentStatus>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Who a new link belongs to and who can manage it.
+#[derive(Clone, Copy)]
+pub struct LinkOwner<'a> {
+    pub account: Option<i32>,
+    pub domain: Option<i32>,
+    /// Hash of the token that allows managing the link without an account
+    pub management_token_hash: &'a str,
+}
+
+#[instrument(skip_all)]
+pub async fn create_link(
+    connection: &mut AsyncPgConnection,
     create: &CreateLinkDto,
     path: &str,
     user_ip: IpNet,
-    stripe_session: Option<String>,
+    owner: LinkOwner<'_>,
+    payment: LinkPayment<'_>,
+    password: Option<&str>,
 ) -> Result<(Link, Vec<Target>)> {
-    let link = NewLink {
-        url: path,
-        permanent_redirect: create.permanent_redirect,
-        created_by_ip: Some(anonymize_ip(user_ip)),
-        payment_status: stripe_session.as_ref().map(|_| PaymentStatus::Pending),
-        stripe_session_id: stripe_session.as_deref(),
-    };
+    // A link without its targets, subscriptions or created event must not be left behind
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                let secret = create.signed.then(|| nanoid!(32));
+                let webhook_secret = create.provisioning_webhook.as_ref().map(|_| nanoid!(32));
+
+                let link = NewLink {
+                    url: path,
+                    permanent_redirect: create.permanent_redirect,
+                    created_by_ip: Some(anonymize_ip(user_ip)),
+                    payment_status: payment.status,
+                    stripe_session_id: payment.stripe_session_id,
+                    payment_expires_at: payment.expires_at,
+                    account_id: owner.account,
+                    domain_id: owner.domain,
+                    workspace_id: create.workspace,
+                    management_token_hash: Some(owner.management_token_hash),
+                    visibility: create.visibility,
+                    password_hash: password,
+                    signing_secret: secret.as_deref(),
+                    proxy: create.proxy,
+                    group_affinity: create.group_affinity,
+                    picker: create.picker,
+                    target_source: create.target_source.as_deref(),
+                    // The targets of links with a source were just fetched from it
+                    target_source_checked_at: create.target_source.as_ref().map(|_| Utc::now()),
+                    provisioning_webhook: create.provisioning_webhook.as_deref(),
+                    provisioning_secret: webhook_secret.as_deref(),
+                    provisioning_threshold: create.provisioning_threshold.unwrap_or(1),
+                };
+
+                let link = diesel::insert_into(links::table())
+                    .values(link)
+                    .get_result::<Link>(connection)
+                    .await?;
+
+                let target_results = diesel::insert_into(targets::table())
+                    .values(
+                        &create
+                            .targets
+                            .iter()
+                            .map(|target| new_target(&link, target))
+                            .collect::<Vec<_>>(),
+                    )
+                    .get_results::<Target>(connection)
+                    .await?;
+
+                for webhook in &create.webhooks {
+                    create_webhook_subscription(connection, &link, webhook).await?;
+                }
+                events::emit(connection, &link, Event::Created).await?;
+
+                Ok((link, target_results))
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+fn new_target<'a>(link: &Link, target: &'a CreateTargetDto) -> NewTarget<'a> {
+    NewTarget {
+        link_id: link.id,
+        target_url: &target.target_url,
+        label: target.label.as_deref(),
+        description: target.description.as_deref(),
+        color: target.color.as_deref(),
+        weight: target.weight.unwrap_or(1),
+        capacity: target.capacity,
+    }
+}
+
+/// Creates a link that is paid for with one of the account's credits.
+/// Returns none without creating anything if the account has no credits left.
+#[instrument(skip_all)]
+pub async fn create_link_with_credit(
+    connection: &mut AsyncPgConnection,
+    create: &CreateLinkDto,
+    path: &str,
+    user_ip: IpNet,
+    account: i32,
+    owner: LinkOwner<'_>,
+    password: Option<&str>,
+) -> Result<Option<(Link, Vec<Target>)>> {
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                // Decrementing only if there are credits left makes this safe against
+                // concurrent requests without having to lock the account
+                let updated = diesel::update(
+                    schema::accounts::table
+                        .filter(schema::accounts::id.eq(account))
+                        .filter(schema::accounts::credits.gt(0)),
+                )
+                .set(schema::accounts::credits.eq(schema::accounts::credits - 1))
+                .execute(connection)
+                .await?;
+
+                if updated == 0 {
+                    return Ok(None);
+                }
+
+                let (link, target_results) = create_link(
+                    connection,
+                    create,
+                    path,
+                    user_ip,
+                    LinkOwner {
+                        account: Some(account),
+                        ..owner
+                    },
+                    LinkPayment::default(),
+                    password,
+                )
+                .await?;
+
+                diesel::insert_into(schema::credit_ledger::table)
+                    .values(NewCreditLedgerEntry {
+                        account_id: account,
+                        amount: -1,
+                        reason: CreditReason::LinkCreation,
+                        purchase_id: None,
+                        link_id: Some(link.id),
+                    })
+                    .execute(connection)
+                    .await?;
 
-    let link = diesel::insert_into(links::table())
-        .values(link)
-        .get_result::<Link>(connection)
+                Ok(Some((link, target_results)))
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// Returns the links with a target source that wasn't checked since the given time.
+#[instrument(skip_all)]
+pub async fn get_due_target_sources<'c>(
+    connection: &mut Connection<'c>,
+    before: DateTime<Utc>,
+) -> Result<Vec<Link>> {
+    Ok(links
+        .filter(target_source.is_not_null())
+        .filter(
+            target_source_checked_at
+                .is_null()
+                .or(target_source_checked_at.lt(before)),
+        )
+        .filter(fraud.eq(false))
+        .load::<Link>(connection)
+        .await?)
+}
+
+/// Adds targets to a link, or updates them if the link already has a target with the same url.
+/// Returns how many targets the link didn't have or had deactivated before.
+#[instrument(skip_all)]
+async fn upsert_targets(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+    entries: &[CreateTargetDto],
+) -> Result<usize> {
+    let existing = targets
+        .filter(link_id.eq(link.id))
+        .load::<Target>(connection)
         .await?;
 
-    let target_results = diesel::insert_into(targets::table())
-        .values(
-            &create
-                .targets
-                .iter()
-                .map(|target| NewTarget {
-                    link_id: link.id,
-                    target_url: &target.target_url,
-                })
-                .collect::<Vec<_>>(),
+    let mut added = 0;
+    for entry in entries {
+        match existing
+            .iter()
+            .find(|target| target.target_url == entry.target_url)
+        {
+            Some(target) => {
+                diesel::update(target)
+                    .set((
+                        schema::targets::label.eq(&entry.label),
+                        schema::targets::description.eq(&entry.description),
+                        schema::targets::color.eq(&entry.color),
+                        schema::targets::weight.eq(entry.weight.unwrap_or(1)),
+                        schema::targets::capacity.eq(entry.capacity),
+                        schema::targets::active.eq(true),
+                    ))
+                    .execute(connection)
+                    .await?;
+
+                if !target.active {
+                    added += 1;
+                }
+            }
+            None => {
+                diesel::insert_into(targets::table())
+                    .values(new_target(link, entry))
+                    .execute(connection)
+                    .await?;
+                added += 1;
+            }
+        }
+    }
+
+    Ok(added)
+}
+
+/// Replaces the targets of a link with those of its target source. Targets that are still
+/// in the source keep their redirects, the others are deactivated but kept for their stats.
+#[instrument(skip_all)]
+pub async fn sync_source_targets(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+    entries: &[CreateTargetDto],
+) -> Result<()> {
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                upsert_targets(connection, link, entries).await?;
+
+                let source_urls = entries
+                    .iter()
+                    .map(|entry| entry.target_url.as_str())
+                    .collect::<Vec<_>>();
+                diesel::update(
+                    targets
+                        .filter(link_id.eq(link.id))
+                        .filter(schema::targets::target_url.ne_all(source_urls)),
+                )
+                .set(schema::targets::active.eq(false))
+                .execute(connection)
+                .await?;
+
+                diesel::update(link)
+                    .set((
+                        target_source_checked_at.eq(Utc::now()),
+                        target_source_error.eq(None::<String>),
+                    ))
+                    .execute(connection)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// Adds the targets a provisioning webhook returned to a link, see [upsert_targets].
+#[instrument(skip_all)]
+pub async fn add_targets(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+    entries: &[CreateTargetDto],
+) -> Result<usize> {
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move { upsert_targets(connection, link, entries).await }.scope_boxed()
+        })
+        .await
+}
+
+/// Returns the paid links with a provisioning webhook, together with their targets.
+#[instrument(skip_all)]
+pub async fn get_provisioned_links<'c>(
+    connection: &mut Connection<'c>,
+) -> Result<Vec<(Link, Vec<Target>)>> {
+    let link_results = links
+        .filter(provisioning_webhook.is_not_null())
+        .filter(fraud.eq(false))
+        .filter(
+            payment_status
+                .is_null()
+                .or(payment_status.eq(PaymentStatus::Succeeded)),
         )
-        .get_results::<Target>(connection)
+        .load::<Link>(connection)
         .await?;
 
-    Ok((link, target_results))
+    with_targets(connection, link_results).await
+}
+
+#[instrument(skip_all)]
+pub async fn get_provisioning_events<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    limit: i64,
+) -> Result<Vec<ProvisioningEvent>> {
+    Ok(schema::provisioning_events::table
+        .filter(schema::provisioning_events::link_id.eq(link.id))
+        .order(schema::provisioning_events::id.desc())
+        .limit(limit)
+        .load::<ProvisioningEvent>(connection)
+        .await?)
+}
+
+#[instrument(skip_all)]
+pub async fn record_provisioning_event<'c>(
+    connection: &mut Connection<'c>,
+    event: NewProvisioningEvent<'_>,
+) -> Result<()> {
+    diesel::insert_into(schema::provisioning_events::table)
+        .values(event)
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+/// Remembers why the target source of a link couldn't be loaded, its targets stay as they are.
+#[instrument(skip_all)]
+pub async fn set_target_source_error<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    error: &str,
+) -> Result<()> {
+    diesel::update(link)
+        .set((
+            target_source_checked_at.eq(Utc::now()),
+            target_source_error.eq(error),
+        ))
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+/// Marks a link as fraudulent, from then on visitors get a warning instead of a redirect.
+#[instrument(skip_all)]
+pub async fn flag_link_fraud<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    reason: Option<&str>,
+) -> Result<()> {
+    diesel::update(link)
+        .set((fraud.eq(true), fraud_reason.eq(reason)))
+        .execute(connection)
+        .await?;
+
+    if !link.fraud {
+        events::emit(connection, link, Event::Fraud { reason }).await?;
+    }
+
+    Ok(())
+}
+
+#[instrument(skip_all)]
+pub async fn create_webhook_subscription(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+    create: &CreateWebhookDto,
+) -> Result<WebhookSubscription> {
+    let secret = nanoid!(32);
+
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                diesel::update(link)
+                    .set(schema::links::has_webhooks.eq(true))
+                    .execute(connection)
+                    .await?;
+
+                Ok(diesel::insert_into(schema::webhook_subscriptions::table)
+                    .values(NewWebhookSubscription {
+                        link_id: link.id,
+                        url: &create.url,
+                        secret: &secret,
+                        events: &create.events,
+                        click_thresholds: &create.click_thresholds,
+                    })
+                    .get_result::<WebhookSubscription>(connection)
+                    .await?)
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+#[instrument(skip_all)]
+pub async fn get_webhook_subscriptions(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+) -> Result<Vec<WebhookSubscription>> {
+    Ok(schema::webhook_subscriptions::table
+        .filter(schema::webhook_subscriptions::link_id.eq(link.id))
+        .order(schema::webhook_subscriptions::id)
+        .load::<WebhookSubscription>(connection)
+        .await?)
+}
+
+/// Returns whether the link had the subscription. Its past deliveries stay in the log.
+#[instrument(skip_all)]
+pub async fn delete_webhook_subscription(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+    subscription: i32,
+) -> Result<bool> {
+    let deleted = diesel::delete(
+        schema::webhook_subscriptions::table
+            .filter(schema::webhook_subscriptions::id.eq(subscription))
+            .filter(schema::webhook_subscriptions::link_id.eq(link.id)),
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(deleted > 0)
+}
+
+#[instrument(skip_all)]
+pub async fn enqueue_webhook_deliveries(
+    connection: &mut AsyncPgConnection,
+    deliveries: &[NewWebhookDelivery<'_>],
+) -> Result<()> {
+    diesel::insert_into(schema::webhook_deliveries::table)
+        .values(deliveries)
+        .execute(connection)
+        .await?;
+
+    Ok(())
+}
+
+#[instrument(skip_all)]
+pub async fn get_webhook_deliveries(
+    connection: &mut AsyncPgConnection,
+    link: &Link,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>> {
+    Ok(schema::webhook_deliveries::table
+        .filter(schema::webhook_deliveries::link_id.eq(link.id))
+        .order(schema::webhook_deliveries::id.desc())
+        .limit(limit)
+        .load::<WebhookDelivery>(connection)
+        .await?)
+}
+
+/// Takes deliveries that are due off the queue until the lease ends, so other instances
+/// don't send them at the same time. If the instance dies, they are picked up again after it.
+#[instrument(skip_all)]
+pub async fn claim_due_webhook_deliveries(
+    connection: &mut AsyncPgConnection,
+    limit: i64,
+    lease_until: DateTime<Utc>,
+) -> Result<Vec<WebhookDelivery>> {
+    connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                let due = schema::webhook_deliveries::table
+                    .filter(schema::webhook_deliveries::next_attempt_at.le(Utc::now()))
+                    .order(schema::webhook_deliveries::next_attempt_at)
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .load::<WebhookDelivery>(connection)
+                    .await?;
+
+                diesel::update(
+                    schema::webhook_deliveries::table.filter(
+                        schema::webhook_deliveries::id
+                            .eq_any(due.iter().map(|delivery| delivery.id).collect::<Vec<_>>()),
+                    ),
+                )
+                .set(schema::webhook_deliveries::next_attempt_at.eq(lease_until))
+                .execute(connection)
+                .await?;
+
+                Ok(due)
+            }
+            .scope_boxed()
+        })
+        .await
+}
+
+/// Records an attempt to send a delivery. Without an error it was delivered, otherwise it's
+/// tried again at the next attempt time, or never if there is none.
+#[instrument(skip_all)]
+pub async fn record_webhook_attempt(
+    connection: &mut AsyncPgConnection,
+    delivery: &WebhookDelivery,
+    error: Option<&str>,
+    next_attempt_at: Option<DateTime<Utc>>,
+) -> Result<()> {
+    diesel::update(delivery)
+        .set((
+            schema::webhook_deliveries::attempts.eq(schema::webhook_deliveries::attempts + 1),
+            schema::webhook_deliveries::next_attempt_at.eq(next_attempt_at),
+            schema::webhook_deliveries::delivered_at.eq(error.is_none().then(Utc::now)),
+            schema::webhook_deliveries::error.eq(error),
+        ))
+        .execute(connection)
+        .await?;
+
+    Ok(())
 }
 
 /// Truncates some bits of the IP address to anonymize it.
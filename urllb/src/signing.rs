@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::Utc;
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use hmac::{Hmac, Mac};
+use nanoid::nanoid;
+use serde::Deserialize;
+use sha2::Sha256;
+use validator::Validate;
+
+use shared::schema::links;
+
+use crate::access::{require_manage, ManagementToken};
+use crate::account::AccountAuth;
+use crate::db::Pool;
+use crate::domains::Namespace;
+use crate::error::{Api, ApiError, Error};
+use crate::models::{Link, MintSignedUrlsDto, SignedUrlDto};
+use crate::service::get_link_and_targets;
+use crate::{Config, Params};
+
+/// Signatures are truncated to keep the urls short, 128 bits are plenty against guessing.
+const SIGNATURE_BYTES: usize = 16;
+const MAX_MINTED_URLS: usize = 10_000;
+
+/// The query parameters of a signed url.
+#[derive(Deserialize)]
+pub struct SignedQuery {
+    sig: Option<String>,
+    /// Unix timestamp after which the url stops working
+    exp: Option<i64>,
+    participant: Option<String>,
+}
+
+fn mac(secret: &str, slug: &str, participant: Option<&str>, exp: Option<i64>) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(
+        format!(
+            "{slug}\n{}\n{}",
+            participant.unwrap_or_default(),
+            exp.map(|exp| exp.to_string()).unwrap_or_default()
+        )
+        .as_bytes(),
+    );
+    mac
+}
+
+/// Participant ids end up in urls unencoded, so they are limited to characters that are safe there.
+fn is_valid_participant(participant: &str) -> bool {
+    !participant.is_empty()
+        && participant.len() <= 128
+        && participant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'))
+}
+
+/// Checks the signature of a visit to a link that requires signed urls.
+/// Returns the participant the url was minted for, links without a secret let everyone through.
+/// Rejected visits get the returned message as reason.
+pub fn verify<'q>(link: &Link, query: &'q SignedQuery) -> Result<Option<&'q str>, &'static str> {
+    match &link.signing_secret {
+        Some(secret) => verify_signature(secret, &link.url, query),
+        None => Ok(None),
+    }
+}
+
+fn verify_signature<'q>(
+    secret: &str,
+    slug: &str,
+    query: &'q SignedQuery,
+) -> Result<Option<&'q str>, &'static str> {
+    let signature = query
+        .sig
+        .as_deref()
+        .and_then(|sig| hex::decode(sig).ok())
+        .filter(|sig| sig.len() == SIGNATURE_BYTES)
+        .ok_or("This link can only be opened with a personal, signed url.")?;
+
+    let participant = query.participant.as_deref();
+
+    mac(secret, slug, participant, query.exp)
+        .verify_truncated_left(&signature)
+        .map_err(|_| "The signature of this url is invalid.")?;
+
+    if let Some(exp) = query.exp {
+        if exp < Utc::now().timestamp() {
+            return Err("This url has expired.");
+        }
+    }
+
+    Ok(participant)
+}
+
+pub fn signed_url(
+    link_url: &str,
+    secret: &str,
+    slug: &str,
+    participant: Option<&str>,
+    exp: Option<i64>,
+) -> String {
+    let signature = mac(secret, slug, participant, exp).finalize().into_bytes();
+
+    let mut signed = format!("{link_url}?");
+    if let Some(participant) = participant {
+        signed.push_str(&format!("participant={participant}&"));
+    }
+    if let Some(exp) = exp {
+        signed.push_str(&format!("exp={exp}&"));
+    }
+    signed.push_str(&format!(
+        "sig={}",
+        hex::encode(&signature[..SIGNATURE_BYTES])
+    ));
+    signed
+}
+
+/// Mints signed urls for a link, one per participant id plus the requested anonymous ones.
+pub async fn mint_signed_urls(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+    Api(Json(body)): Api<Json<MintSignedUrlsDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    body.validate()?;
+
+    let count = body.participants.len() + body.anonymous as usize;
+    if count == 0
+        || count > MAX_MINTED_URLS
+        || !body
+            .participants
+            .iter()
+            .all(|participant| is_valid_participant(participant))
+    {
+        return Err(ApiError::bad_request(
+            "invalid_participants",
+            format!(
+                "Between 1 and {MAX_MINTED_URLS} urls with valid participant ids can be minted"
+            ),
+        ));
+    }
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    let secret = link.signing_secret.as_deref().ok_or_else(|| {
+        ApiError::new(StatusCode::CONFLICT, "not_signed", "The link isn't signed")
+    })?;
+    let link_url = config.link_url(namespace.0.as_ref(), &link.url);
+    let exp = body.expires_at.map(|expires_at| expires_at.timestamp());
+
+    let urls = body
+        .participants
+        .iter()
+        .map(|participant| Some(participant.as_str()))
+        .chain((0..body.anonymous).map(|_| None))
+        .map(|participant| SignedUrlDto {
+            participant: participant.map(str::to_string),
+            url: signed_url(&link_url, secret, &link.url, participant, exp),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(urls))
+}
+
+/// Generates a new signing secret, which requires signed urls from now on and invalidates
+/// all urls that were minted before.
+pub async fn rotate_signing_secret(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> Result<impl IntoResponse, ApiError> {
+    set_signing_secret(&pool, &params, namespace, account, token, Some(nanoid!(32))).await
+}
+
+/// Removes the signing secret, so the bare link works again.
+pub async fn remove_signing_secret(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+) -> Result<impl IntoResponse, ApiError> {
+    set_signing_secret(&pool, &params, namespace, account, token, None).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn set_signing_secret(
+    pool: &Pool,
+    params: &Params,
+    namespace: Namespace,
+    account: Option<AccountAuth>,
+    token: ManagementToken,
+    secret: Option<String>,
+) -> Result<StatusCode, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, _) = get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+        .await
+        .map_err(ApiError::not_found("link"))?;
+
+    let account = account.as_ref().map(|AccountAuth(account)| account);
+    require_manage(&mut connection, &link, account, &token).await?;
+
+    diesel::update(&link)
+        .set(links::signing_secret.eq(secret))
+        .execute(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Query;
+    use axum::http::Uri;
+
+    use super::*;
+
+    const SECRET: &str = "signing-secret";
+
+    fn query(url: &str) -> SignedQuery {
+        let uri: Uri = url.parse().unwrap();
+        Query::<SignedQuery>::try_from_uri(&uri).unwrap().0
+    }
+
+    fn sign(participant: Option<&str>, exp: Option<i64>) -> SignedQuery {
+        query(&signed_url(
+            "http://localhost/room",
+            SECRET,
+            "room",
+            participant,
+            exp,
+        ))
+    }
+
+    #[test]
+    fn signed_urls_verify() {
+        let anonymous = sign(None, None);
+        assert_eq!(verify_signature(SECRET, "room", &anonymous), Ok(None));
+
+        let exp = Utc::now().timestamp() + 60;
+        let personal = sign(Some("alice@example.com"), Some(exp));
+        assert_eq!(
+            verify_signature(SECRET, "room", &personal),
+            Ok(Some("alice@example.com"))
+        );
+    }
+
+    #[test]
+    fn signatures_are_truncated() {
+        let signed = sign(None, None);
+        assert_eq!(signed.sig.unwrap().len(), SIGNATURE_BYTES * 2);
+    }
+
+    #[test]
+    fn tampered_urls_are_rejected() {
+        let signed = signed_url("http://localhost/room", SECRET, "room", Some("alice"), None);
+
+        let other_participant = query(&signed.replace("participant=alice", "participant=bob"));
+        assert!(verify_signature(SECRET, "room", &other_participant).is_err());
+
+        let added_expiry = query(&signed.replace("sig=", "exp=99999999999&sig="));
+        assert!(verify_signature(SECRET, "room", &added_expiry).is_err());
+
+        assert!(verify_signature(SECRET, "other-room", &query(&signed)).is_err());
+        assert!(verify_signature("other-secret", "room", &query(&signed)).is_err());
+
+        let mut flipped = query(&signed);
+        let sig = flipped.sig.take().unwrap();
+        let last = if sig.ends_with('0') { "1" } else { "0" };
+        flipped.sig = Some(format!("{}{last}", &sig[..sig.len() - 1]));
+        assert!(verify_signature(SECRET, "room", &flipped).is_err());
+
+        let unsigned = query("http://localhost/room?participant=alice");
+        assert!(verify_signature(SECRET, "room", &unsigned).is_err());
+    }
+
+    #[test]
+    fn expired_urls_are_rejected() {
+        let expired = sign(Some("alice"), Some(Utc::now().timestamp() - 1));
+        assert_eq!(
+            verify_signature(SECRET, "room", &expired),
+            Err("This url has expired.")
+        );
+    }
+
+    #[test]
+    fn participants_are_limited_to_url_safe_characters() {
+        assert!(is_valid_participant("alice.smith-1_2@example.com"));
+        assert!(!is_valid_participant(""));
+        assert!(!is_valid_participant(&"a".repeat(129)));
+        for participant in [
+            "alice smith",
+            "alice&exp=1",
+            "alice#1",
+            "alice/..",
+            "älice",
+            "a=b",
+        ] {
+            assert!(!is_valid_participant(participant), "{participant}");
+        }
+    }
+}
@@ -1,14 +1,16 @@
 use cached::lazy_static::lazy_static;
 use diesel::dsl::sum;
-use diesel::QueryDsl;
+use diesel::{ExpressionMethods, QueryDsl};
 use diesel_async::RunQueryDsl;
 
 use cached::{Cached, TimedCache};
 use shared::TotalStats;
 
 use crate::db::Connection;
+use crate::metrics::{CACHE_HITS, CACHE_MISSES};
 use crate::schema::links::dsl::*;
 use crate::schema::targets::dsl::targets;
+use crate::schema::targets::link_id;
 
 use std::sync::Mutex;
 
@@ -17,10 +19,13 @@ lazy_static! {
         Mutex::new(TimedCache::with_lifespan(120));
 }
 
+#[tracing::instrument(skip_all)]
 pub async fn total_stats<'a>(connection: &mut Connection<'a>) -> anyhow::Result<TotalStats> {
     if let Some(stats) = CACHE.lock().unwrap().cache_get(&0) {
+        CACHE_HITS.with_label_values(&["total_stats"]).inc();
         return Ok(stats.clone());
     }
+    CACHE_MISSES.with_label_values(&["total_stats"]).inc();
 
     let link_count = links.count().first(connection).await?;
 
@@ -38,3 +43,34 @@ pub async fn total_stats<'a>(connection: &mut Connection<'a>) -> anyhow::Result<
 
     Ok(stats)
 }
+
+/// Stats of all links in a workspace, these aren't cached since they change with every click.
+#[tracing::instrument(skip_all)]
+pub async fn workspace_stats<'a>(
+    connection: &mut Connection<'a>,
+    workspace: i32,
+) -> anyhow::Result<TotalStats> {
+    let link_count = links
+        .filter(workspace_id.eq(workspace))
+        .count()
+        .first(connection)
+        .await?;
+
+    let target_count = targets
+        .filter(link_id.eq_any(links.filter(workspace_id.eq(workspace)).select(id)))
+        .count()
+        .first(connection)
+        .await?;
+
+    let redirect_count: Option<i64> = links
+        .filter(workspace_id.eq(workspace))
+        .select(sum(redirects))
+        .first(connection)
+        .await?;
+
+    Ok(TotalStats {
+        links: link_count,
+        targets: target_count,
+        redirects: redirect_count.unwrap_or(0),
+    })
+}
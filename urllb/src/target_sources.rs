@@ -0,0 +1,461 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use reqwest::Url;
+use serde::Deserialize;
+use tracing::{error, info, instrument, warn};
+use validator::Validate;
+
+use crate::db::Pool;
+use crate::error::{Error, Result};
+use crate::metrics::{TARGET_SOURCE_ERRORS, TARGET_SOURCE_REFRESHES};
+use crate::models::{CreateTargetDto, Link, TargetSourceDto};
+use crate::proxy::{is_public_host, PublicResolver};
+use crate::service::{get_due_target_sources, set_target_source_error, sync_source_targets};
+use crate::Config;
+
+/// Target sources can't make a link bigger than target templates can.
+const MAX_SOURCE_TARGETS: usize = shared::template::MAX_EXPANDED_TARGETS;
+const MAX_SOURCE_BYTES: usize = 1024 * 1024;
+
+/// An entry of a target source, either just the url or an object with the details of the target.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SourceEntry {
+    Url(String),
+    Target {
+        url: String,
+        #[serde(default)]
+        weight: Option<i32>,
+        #[serde(default)]
+        capacity: Option<i32>,
+        #[serde(default)]
+        label: Option<String>,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        color: Option<String>,
+    },
+}
+
+impl From<SourceEntry> for CreateTargetDto {
+    fn from(entry: SourceEntry) -> Self {
+        match entry {
+            SourceEntry::Url(url) => CreateTargetDto {
+                target_url: url,
+                ..Default::default()
+            },
+            SourceEntry::Target {
+                url,
+                weight,
+                capacity,
+                label,
+                description,
+                color,
+            } => CreateTargetDto {
+                target_url: url,
+                label,
+                description,
+                color,
+                weight,
+                capacity,
+            },
+        }
+    }
+}
+
+/// Why a target source couldn't be loaded, shown to the owner of the link on the info page.
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[error("The source url is invalid or not public")]
+    InvalidUrl,
+    #[error("Fetching the source failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("The source answered with status {0}")]
+    Status(StatusCode),
+    #[error("The source is larger than {MAX_SOURCE_BYTES} bytes")]
+    TooLarge,
+    #[error("Not a json list of targets: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The source contains no targets")]
+    Empty,
+    #[error("More than {MAX_SOURCE_TARGETS} targets")]
+    TooManyTargets,
+    #[error("The target {0} is invalid or blocked")]
+    InvalidTarget(String),
+}
+
+/// Loads the targets of links from the json endpoints given as their target source.
+pub struct TargetSources {
+    client: reqwest::Client,
+    allow_private: bool,
+}
+
+impl TargetSources {
+    pub fn new(config: &Config) -> Self {
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.target_source_timeout_secs))
+            .redirect(reqwest::redirect::Policy::none());
+
+        if !config.target_source_allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Self {
+            client: builder
+                .build()
+                .expect("Failed to build target source client"),
+            allow_private: config.target_source_allow_private,
+        }
+    }
+
+    /// Fetches and checks the targets of a source.
+    pub async fn fetch(
+        &self,
+        source: &str,
+    ) -> std::result::Result<Vec<CreateTargetDto>, SourceError> {
+        let url = Url::parse(source).map_err(|_| SourceError::InvalidUrl)?;
+        if !matches!(url.scheme(), "http" | "https")
+            || (!self.allow_private && !is_public_host(&url))
+        {
+            return Err(SourceError::InvalidUrl);
+        }
+
+        let mut response = self.client.get(url).send().await?;
+        if !response.status().is_success() {
+            return Err(SourceError::Status(response.status()));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > MAX_SOURCE_BYTES {
+                return Err(SourceError::TooLarge);
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let targets = parse_targets(&body)?;
+        if targets.is_empty() {
+            return Err(SourceError::Empty);
+        }
+
+        Ok(targets)
+    }
+
+    /// Fetches the source of a link and replaces its targets. If that fails, the error is
+    /// remembered and the link keeps the targets of the last successful fetch.
+    #[instrument(skip_all, fields(link = %link.url))]
+    async fn refresh(&self, pool: &Pool, link: &Link) -> Result<bool> {
+        let Some(source) = &link.target_source else {
+            return Ok(true);
+        };
+
+        let fetched = self.fetch(source).await;
+
+        let mut connection = pool.get().await.map_err(Error::PoolError)?;
+        match fetched {
+            Ok(targets) => {
+                sync_source_targets(&mut connection, link, &targets).await?;
+                Ok(true)
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to load the target source of link {}: {}",
+                    link.url, err
+                );
+                set_target_source_error(&mut connection, link, &err.to_string()).await?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Parses and checks a json list of targets, either all of them are fine or none are used.
+/// Provisioning webhooks answer in the same format.
+pub fn parse_targets(body: &[u8]) -> std::result::Result<Vec<CreateTargetDto>, SourceError> {
+    let entries = serde_json::from_slice::<Vec<SourceEntry>>(body)?;
+    if entries.len() > MAX_SOURCE_TARGETS {
+        return Err(SourceError::TooManyTargets);
+    }
+
+    let mut targets: Vec<CreateTargetDto> = entries.into_iter().map(Into::into).collect();
+    // The same url twice would be the same target, the first entry wins
+    let mut seen = HashSet::new();
+    targets.retain(|target| seen.insert(target.target_url.clone()));
+
+    if let Some(target) = targets
+        .iter()
+        .find(|target| target.validate().is_err() || crate::is_blocked(&target.target_url))
+    {
+        return Err(SourceError::InvalidTarget(target.target_url.clone()));
+    }
+
+    Ok(targets)
+}
+
+pub fn source_info(link: &Link) -> Option<TargetSourceDto> {
+    link.target_source.as_ref().map(|url| TargetSourceDto {
+        url: url.clone(),
+        checked_at: link.target_source_checked_at,
+        error: link.target_source_error.clone(),
+    })
+}
+
+/// Periodically reloads the targets of all links with a target source.
+pub fn spawn(pool: Pool, sources: Arc<TargetSources>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            match refresh_due(&pool, &sources, interval).await {
+                Ok((0, 0)) => {}
+                Ok((refreshed, failed)) => {
+                    info!("Refreshed {refreshed} target sources, {failed} failed")
+                }
+                Err(err) => error!("Failed to refresh target sources: {:?}", err),
+            }
+        }
+    });
+}
+
+/// Returns the number of refreshed and of failed sources.
+#[instrument(skip_all)]
+async fn refresh_due(
+    pool: &Pool,
+    sources: &TargetSources,
+    interval: Duration,
+) -> Result<(u64, u64)> {
+    let due = {
+        let mut connection = pool.get().await.map_err(Error::PoolError)?;
+        // Half an interval, so sources checked just after the previous tick aren't skipped
+        let cutoff = Utc::now()
+            - chrono::Duration::from_std(interval / 2).unwrap_or_else(|_| chrono::Duration::zero());
+        get_due_target_sources(&mut connection, cutoff).await?
+    };
+
+    let mut refreshed = 0;
+    let mut failed = 0;
+
+    for link in due {
+        TARGET_SOURCE_REFRESHES.inc();
+        match sources.refresh(pool, &link).await {
+            Ok(true) => refreshed += 1,
+            Ok(false) => {
+                TARGET_SOURCE_ERRORS.inc();
+                failed += 1;
+            }
+            // The other links may still get through, e.g. if the database only failed briefly
+            Err(err) => {
+                error!(
+                    "Failed to refresh the target source of {}: {:?}",
+                    link.url, err
+                );
+                TARGET_SOURCE_ERRORS.inc();
+                failed += 1;
+            }
+        }
+    }
+
+    Ok((refreshed, failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+
+    use axum::extract::State;
+    use axum::routing::get;
+    use axum::Router;
+    use diesel::{ExpressionMethods, QueryDsl};
+    use diesel_async::RunQueryDsl;
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::Target;
+    use crate::test_support::{config_with, create_test_link, pool};
+
+    type SourceResponse = Arc<Mutex<(StatusCode, String)>>;
+
+    /// A target source on localhost that answers with whatever the test puts in the response.
+    fn spawn_source(status: StatusCode, body: &str) -> (SocketAddr, SourceResponse) {
+        let response = Arc::new(Mutex::new((status, body.to_string())));
+        let app = Router::new()
+            .route(
+                "/targets.json",
+                get(|State(response): State<SourceResponse>| async move {
+                    response.lock().unwrap().clone()
+                }),
+            )
+            .with_state(response.clone());
+
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, response)
+    }
+
+    fn local_sources() -> TargetSources {
+        TargetSources::new(&config_with(&[("TARGET_SOURCE_ALLOW_PRIVATE", "true")]))
+    }
+
+    fn urls(targets: &[CreateTargetDto]) -> Vec<&str> {
+        targets
+            .iter()
+            .map(|target| target.target_url.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn sources_list_urls_or_targets() {
+        let targets = parse_targets(
+            br#"[
+                "https://game.example/1",
+                {"url": "https://game.example/2", "weight": 3, "capacity": 20, "label": "Two"},
+                "https://game.example/1"
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            urls(&targets),
+            ["https://game.example/1", "https://game.example/2"]
+        );
+        assert_eq!(targets[0].weight, None);
+        assert_eq!(targets[1].weight, Some(3));
+        assert_eq!(targets[1].capacity, Some(20));
+        assert_eq!(targets[1].label.as_deref(), Some("Two"));
+    }
+
+    #[test]
+    fn invalid_sources_are_rejected() {
+        assert!(matches!(
+            parse_targets(br#"{"url": "https://game.example"}"#),
+            Err(SourceError::Json(_))
+        ));
+        assert!(matches!(
+            parse_targets(br#"["not a url"]"#),
+            Err(SourceError::InvalidTarget(url)) if url == "not a url"
+        ));
+
+        let too_many = (0..=MAX_SOURCE_TARGETS)
+            .map(|index| format!("https://game.example/{index}"))
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            parse_targets(&serde_json::to_vec(&too_many).unwrap()),
+            Err(SourceError::TooManyTargets)
+        ));
+    }
+
+    #[tokio::test]
+    async fn sources_are_fetched_over_http() {
+        let (addr, response) = spawn_source(StatusCode::OK, r#"["https://game.example/1"]"#);
+        let url = format!("http://{addr}/targets.json");
+        let sources = local_sources();
+
+        assert_eq!(
+            urls(&sources.fetch(&url).await.unwrap()),
+            ["https://game.example/1"]
+        );
+
+        *response.lock().unwrap() = (StatusCode::OK, "[]".to_string());
+        assert!(matches!(sources.fetch(&url).await, Err(SourceError::Empty)));
+
+        *response.lock().unwrap() = (StatusCode::BAD_GATEWAY, "".to_string());
+        assert!(matches!(
+            sources.fetch(&url).await,
+            Err(SourceError::Status(StatusCode::BAD_GATEWAY))
+        ));
+
+        *response.lock().unwrap() = (StatusCode::OK, "x".repeat(MAX_SOURCE_BYTES + 1));
+        assert!(matches!(
+            sources.fetch(&url).await,
+            Err(SourceError::TooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn private_sources_are_rejected_by_default() {
+        let (addr, _) = spawn_source(StatusCode::OK, r#"["https://game.example/1"]"#);
+        let sources = TargetSources::new(&config_with(&[]));
+
+        assert!(matches!(
+            sources.fetch(&format!("http://{addr}/targets.json")).await,
+            Err(SourceError::InvalidUrl)
+        ));
+        assert!(matches!(
+            sources.fetch("ftp://game.example/targets.json").await,
+            Err(SourceError::InvalidUrl)
+        ));
+    }
+
+    async fn active_targets(pool: &Pool, link: &Link) -> Vec<String> {
+        use shared::schema::targets;
+
+        let mut connection = pool.get().await.unwrap();
+        targets::table
+            .filter(targets::link_id.eq(link.id))
+            .filter(targets::active.eq(true))
+            .order(targets::id.asc())
+            .load::<Target>(&mut connection)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|target| target.target_url)
+            .collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn failed_refreshes_keep_the_last_good_targets() {
+        let pool = pool().await;
+        let (addr, response) = spawn_source(
+            StatusCode::OK,
+            r#"["https://game.example/1", "https://game.example/2"]"#,
+        );
+        let (link, _, _) = create_test_link(
+            &pool,
+            json!({
+                "targets": [{"targetUrl": "https://game.example/1"}],
+                "targetSource": format!("http://{addr}/targets.json"),
+            }),
+            None,
+        )
+        .await;
+        let sources = local_sources();
+
+        assert!(sources.refresh(&pool, &link).await.unwrap());
+        assert_eq!(
+            active_targets(&pool, &link).await,
+            ["https://game.example/1", "https://game.example/2"]
+        );
+
+        *response.lock().unwrap() = (StatusCode::OK, r#"["https://game.example/2"]"#.to_string());
+        assert!(sources.refresh(&pool, &link).await.unwrap());
+        assert_eq!(
+            active_targets(&pool, &link).await,
+            ["https://game.example/2"]
+        );
+
+        *response.lock().unwrap() = (StatusCode::INTERNAL_SERVER_ERROR, "".to_string());
+        assert!(!sources.refresh(&pool, &link).await.unwrap());
+        assert_eq!(
+            active_targets(&pool, &link).await,
+            ["https://game.example/2"]
+        );
+
+        let mut connection = pool.get().await.unwrap();
+        let (link, _) = crate::service::get_link_and_targets(&mut connection, None, &link.url)
+            .await
+            .unwrap();
+        assert_eq!(
+            source_info(&link).and_then(|info| info.error).as_deref(),
+            Some("The source answered with status 500 Internal Server Error")
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use axum::http::{HeaderMap, Request};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::Tracer;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::Config;
+
+/// Reads the `traceparent` and `tracestate` headers of incoming requests.
+struct HeaderExtractor<'h>(&'h HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+fn otlp_tracer(endpoint: &str, service_name: &str) -> Tracer {
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                service_name.to_string(),
+            )])),
+        )
+        .install_batch(runtime::Tokio)
+        .expect("Failed to set up the OTLP trace exporter")
+}
+
+/// Logs to stdout and, if an OTLP endpoint is configured, exports the spans to it as well.
+pub fn init(config: &Config) {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let otlp = config.otlp_endpoint.as_deref().map(|endpoint| {
+        tracing_opentelemetry::layer().with_tracer(otlp_tracer(endpoint, &config.otlp_service_name))
+    });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(tracing_subscriber::fmt::layer())
+        .with(otlp)
+        .init();
+
+    if let Some(endpoint) = &config.otlp_endpoint {
+        tracing::info!("exporting traces to {}", endpoint);
+    }
+}
+
+/// Exports the spans that are still buffered, the batch exporter would drop them on exit.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// The span of a request, it continues the trace of the caller if the request carries a W3C
+/// trace context.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        http.method = %request.method(),
+        http.target = %request.uri().path(),
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent);
+
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+        TraceService, TraceServiceServer,
+    };
+    use opentelemetry_proto::tonic::collector::trace::v1::{
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    /// An OTLP collector on localhost that passes on everything that is exported to it.
+    struct Collector(mpsc::UnboundedSender<ExportTraceServiceRequest>);
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            let _ = self.0.send(request.into_inner());
+            Ok(tonic::Response::new(ExportTraceServiceResponse {
+                partial_success: None,
+            }))
+        }
+    }
+
+    async fn spawn_collector() -> (String, mpsc::UnboundedReceiver<ExportTraceServiceRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let connections = futures_util::stream::unfold(listener, |listener| async move {
+            let connection = listener.accept().await.map(|(stream, _)| stream);
+            Some((connection, listener))
+        });
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(Collector(sender)))
+                .serve_with_incoming(connections),
+        );
+
+        (endpoint, receiver)
+    }
+
+    /// A request from a caller that is part of the trace [TRACE_ID].
+    fn traced_request() -> Request<()> {
+        Request::builder()
+            .uri("/finals?group=a")
+            .header("traceparent", format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn requests_continue_the_trace_of_the_caller() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        // Tracers stop working once their provider is dropped
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let traced = request_span(&traced_request());
+            assert_eq!(
+                traced
+                    .context()
+                    .span()
+                    .span_context()
+                    .trace_id()
+                    .to_string(),
+                TRACE_ID
+            );
+
+            let untraced = request_span(&Request::builder().uri("/finals").body(()).unwrap());
+            assert_ne!(
+                untraced
+                    .context()
+                    .span()
+                    .span_context()
+                    .trace_id()
+                    .to_string(),
+                TRACE_ID
+            );
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_collector() {
+        let (endpoint, mut exported) = spawn_collector().await;
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(otlp_tracer(&endpoint, "urllb-test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = request_span(&traced_request());
+            span.in_scope(|| tracing::info_span!("query").in_scope(|| {}));
+        });
+
+        // Shutting down blocks until the batch exporter sent everything
+        tokio::task::spawn_blocking(shutdown).await.unwrap();
+
+        let mut requests =
+            vec![
+                tokio::time::timeout(std::time::Duration::from_secs(10), exported.recv())
+                    .await
+                    .expect("Nothing was exported")
+                    .unwrap(),
+            ];
+        while let Ok(request) = exported.try_recv() {
+            requests.push(request);
+        }
+        let resource_spans = requests
+            .iter()
+            .flat_map(|request| &request.resource_spans)
+            .collect::<Vec<_>>();
+
+        for resource_spans in &resource_spans {
+            let service_name = resource_spans
+                .resource
+                .iter()
+                .flat_map(|resource| &resource.attributes)
+                .find(|attribute| attribute.key == "service.name")
+                .and_then(|attribute| attribute.value.as_ref()?.value.clone());
+            assert_eq!(
+                service_name,
+                Some(Value::StringValue("urllb-test".to_string()))
+            );
+        }
+
+        let spans = resource_spans
+            .iter()
+            .flat_map(|resource_spans| &resource_spans.scope_spans)
+            .flat_map(|scope| &scope.spans)
+            .collect::<Vec<_>>();
+        let mut names = spans
+            .iter()
+            .map(|span| span.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["query", "request"]);
+        assert!(spans
+            .iter()
+            .all(|span| hex::encode(&span.trace_id) == TRACE_ID));
+    }
+}
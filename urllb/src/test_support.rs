@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::http::request::Parts;
+use axum::http::Request;
+use axum::response::IntoResponse;
+use diesel_async::RunQueryDsl;
+use lazy_static::lazy_static;
+use nanoid::nanoid;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use shared::schema::accounts;
+
+use crate::account::hash_token;
+use crate::db::{self, Pool};
+use crate::error::{Error, Result};
+use crate::models::{
+    Account, CreateLinkDto, Link, NewAccount, PaymentProviderKind, PaymentStatus, Price, Target,
+};
+use crate::payment::{PaymentProvider, PaymentStart};
+use crate::service::{create_link, LinkOwner, LinkPayment};
+use crate::Config;
+
+lazy_static! {
+    /// Tests run in parallel, but the migrations must only run once at a time.
+    static ref MIGRATIONS: Mutex<()> = Mutex::new(());
+}
+
+/// A pool for the database in `TEST_DATABASE_URL`. Tests that need a database are ignored by
+/// default and fail instead of passing when they're run without one.
+pub async fn pool() -> Pool {
+    let url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must be set for tests that need a database");
+
+    let _migrations = MIGRATIONS.lock().await;
+    db::connect_and_migrate(&url)
+        .await
+        .expect("Failed to connect to the test database")
+}
+
+pub fn config() -> Arc<Config> {
+    config_with(&[])
+}
+
+/// The test config with some more variables.
+pub fn config_with(variables: &[(&str, &str)]) -> Arc<Config> {
+    let defaults = [
+        ("IP_SOURCE", "ConnectInfo"),
+        ("DATABASE_URL", "postgres://localhost/hurlurl_test"),
+        ("PUBLIC_BASE_URL", "http://localhost:3000"),
+        ("COOKIE_SECRET", "test-cookie-secret"),
+    ];
+
+    Arc::new(
+        envy::from_iter(
+            defaults
+                .iter()
+                .chain(variables)
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        )
+        .expect("The test config is valid"),
+    )
+}
+
+/// Creates a link from the json of a create request, returns it with its management token.
+pub async fn create_test_link(
+    pool: &Pool,
+    create: Value,
+    password_hash: Option<&str>,
+) -> (Link, Vec<Target>, String) {
+    let create: CreateLinkDto = serde_json::from_value(create).expect("Invalid test link");
+    let token = nanoid!(32);
+    let token_hash = hash_token(&token);
+
+    let mut connection = pool.get().await.unwrap();
+    let (link, targets) = create_link(
+        &mut connection,
+        &create,
+        &nanoid!(12),
+        "127.0.0.1/32".parse().unwrap(),
+        LinkOwner {
+            account: None,
+            domain: None,
+            management_token_hash: &token_hash,
+        },
+        LinkPayment::default(),
+        password_hash,
+    )
+    .await
+    .unwrap();
+
+    (link, targets, token)
+}
+
+/// Creates an account without credits.
+pub async fn create_test_account(pool: &Pool) -> Account {
+    let mut connection = pool.get().await.unwrap();
+    diesel::insert_into(accounts::table)
+        .values(NewAccount {
+            token_hash: &hash_token(&nanoid!(32)),
+        })
+        .get_result::<Account>(&mut connection)
+        .await
+        .unwrap()
+}
+
+/// The parts of a request, with the config extension the extractors expect.
+pub fn request_parts(uri: &str, headers: &[(&str, &str)]) -> Parts {
+    let mut request = Request::builder().uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    let (mut parts, _) = request.body(()).unwrap().into_parts();
+    parts.extensions.insert(config());
+    parts
+}
+
+/// The status and json body of a handler's response.
+pub async fn json_response<T: DeserializeOwned>(
+    response: impl IntoResponse,
+) -> (axum::http::StatusCode, T) {
+    let response = response.into_response();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+/// A payment provider that answers with the status the checkout session id starts with, e.g.
+/// `paid-...` or `failed-...`. Sessions starting with `broken-` can't be checked, all others
+/// are still pending.
+pub struct SessionPayments;
+
+fn session_status(session: &str) -> Result<PaymentStatus> {
+    match session {
+        session if session.starts_with("paid-") => Ok(PaymentStatus::Succeeded),
+        session if session.starts_with("failed-") => Ok(PaymentStatus::Failed),
+        session if session.starts_with("broken-") => Err(Error::InvalidCheckoutSession),
+        _ => Ok(PaymentStatus::Pending),
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for SessionPayments {
+    fn kind(&self) -> PaymentProviderKind {
+        PaymentProviderKind::Stripe
+    }
+
+    async fn price(&self) -> Result<Option<Price>> {
+        Ok(None)
+    }
+
+    async fn start_payment(&self, _info_url: &str) -> Result<PaymentStart> {
+        Ok(PaymentStart::NotRequired)
+    }
+
+    async fn check_payment(&self, link: &Link) -> Result<PaymentStatus> {
+        session_status(link.stripe_session_id.as_deref().unwrap_or_default())
+    }
+
+    fn sells_credits(&self) -> bool {
+        true
+    }
+
+    async fn check_credit_payment(&self, session_id: &str) -> Result<PaymentStatus> {
+        session_status(session_id)
+    }
+}
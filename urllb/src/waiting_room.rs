@@ -0,0 +1,402 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::Json;
+use lazy_static::lazy_static;
+use nanoid::nanoid;
+use serde::Serialize;
+
+use crate::db::Pool;
+use crate::domains::Namespace;
+use crate::error::{ApiError, Error};
+use crate::html;
+use crate::models::{remaining_capacity, Link, Target};
+use crate::service::get_link_and_targets;
+use crate::Params;
+
+/// How often the waiting room page asks whether it's the visitor's turn.
+const POLL_INTERVAL_SECS: u64 = 3;
+/// Visitors that stopped polling, e.g. because they closed the page, lose their place after this.
+const TICKET_TIMEOUT: Duration = Duration::from_secs(10 * POLL_INTERVAL_SECS);
+/// Visitors beyond this don't get a place in the line of a link and are turned away.
+const MAX_QUEUE_LENGTH: usize = 100_000;
+/// Places handed out to admitted visitors stay taken for this long, until their visit shows up in
+/// the redirects of the targets. Otherwise every request of a burst would see the same free places.
+const RESERVATION_TIMEOUT: Duration = Duration::from_secs(10);
+const TICKET_COOKIE_MAX_AGE_SECS: u64 = 60 * 60;
+
+struct Ticket {
+    id: String,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+struct Line {
+    /// The visitors waiting for a place, in the order they arrived
+    tickets: VecDeque<Ticket>,
+    /// When the places of the recently admitted visitors were handed out
+    reservations: VecDeque<Instant>,
+}
+
+impl Line {
+    /// Drops the tickets of visitors that stopped polling and the reservations that ran out,
+    /// and returns how many places are left for the line.
+    fn available(&mut self, slots: usize) -> usize {
+        let now = Instant::now();
+        self.tickets
+            .retain(|ticket| now.duration_since(ticket.last_seen) < TICKET_TIMEOUT);
+        self.reservations
+            .retain(|reserved| now.duration_since(*reserved) < RESERVATION_TIMEOUT);
+
+        slots.saturating_sub(self.reservations.len())
+    }
+
+    fn position(&self, ticket: Option<&str>) -> Option<usize> {
+        ticket.and_then(|ticket| self.tickets.iter().position(|queued| queued.id == ticket))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tickets.is_empty() && self.reservations.is_empty()
+    }
+}
+
+lazy_static! {
+    /// The line per link. It lives in memory, so hurlurl has to run as a single instance for the
+    /// line to hold, and it's gone after a restart.
+    static ref QUEUES: Mutex<HashMap<i32, Line>> = Mutex::new(HashMap::new());
+}
+
+pub enum Admission {
+    Admitted,
+    /// The visitor has to wait, the position starts at 0 for the front of the line
+    Waiting {
+        ticket: String,
+        position: usize,
+    },
+    /// The line is too long to join
+    Rejected,
+}
+
+#[derive(Serialize)]
+pub struct QueueStatus {
+    position: Option<usize>,
+    /// The visitor can reload the link to be sent to a target
+    ready: bool,
+}
+
+/// How many more visitors the targets can take, unlimited if one of them has no capacity.
+/// Deactivated targets, e.g. the ones a target source dropped, take nobody, so a link without
+/// active targets has no free places either.
+pub fn free_slots(targets: &[Target]) -> usize {
+    let active = targets
+        .iter()
+        .filter(|target| target.active)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    remaining_capacity(&active)
+        .map(|remaining| usize::try_from(remaining).unwrap_or(usize::MAX))
+        .unwrap_or(usize::MAX)
+}
+
+fn cookie_name(link: &Link) -> String {
+    format!("hurlurl-queue-{}", link.id)
+}
+
+/// The ticket the visitor got when joining the line of the link.
+pub fn ticket<'h>(headers: &'h HeaderMap, link: &Link) -> Option<&'h str> {
+    html::cookies(headers, &cookie_name(link)).last()
+}
+
+/// Lets a visitor through if there's a free place that nobody before them in line is waiting
+/// for, otherwise the visitor keeps or gets a place in line. The place of an admitted visitor
+/// is reserved until their visit is counted.
+pub fn admit(link: &Link, slots: usize, ticket: Option<&str>) -> Admission {
+    let mut queues = QUEUES.lock().unwrap();
+    let line = queues.entry(link.id).or_default();
+
+    let available = line.available(slots);
+    let now = Instant::now();
+
+    let admission = match line.position(ticket) {
+        Some(position) if position < available => {
+            line.tickets.remove(position);
+            line.reservations.push_back(now);
+            Admission::Admitted
+        }
+        Some(position) => {
+            line.tickets[position].last_seen = now;
+            Admission::Waiting {
+                ticket: line.tickets[position].id.clone(),
+                position,
+            }
+        }
+        // Everyone in line fits, and so does the new visitor
+        None if line.tickets.len() < available => {
+            line.reservations.push_back(now);
+            Admission::Admitted
+        }
+        None if line.tickets.len() >= MAX_QUEUE_LENGTH => Admission::Rejected,
+        None => {
+            let id = nanoid!();
+            line.tickets.push_back(Ticket {
+                id: id.clone(),
+                last_seen: now,
+            });
+            Admission::Waiting {
+                ticket: id,
+                position: line.tickets.len() - 1,
+            }
+        }
+    };
+
+    if line.is_empty() {
+        queues.remove(&link.id);
+    }
+
+    admission
+}
+
+/// Whether a visitor that doesn't wait in line can be let through without taking the place of
+/// someone who does. If so, the place is reserved for the visitor like in [`admit`].
+pub fn has_room(link: &Link, slots: usize) -> bool {
+    let mut queues = QUEUES.lock().unwrap();
+    let line = queues.entry(link.id).or_default();
+
+    let room = line.tickets.len() < line.available(slots);
+    if room {
+        line.reservations.push_back(Instant::now());
+    }
+
+    if line.is_empty() {
+        queues.remove(&link.id);
+    }
+
+    room
+}
+
+/// The position of a waiting visitor. Visitors whose ticket is unknown, e.g. because it timed
+/// out, are ready as well, reloading the link puts them back in line if there is one.
+fn status(link: &Link, slots: usize, ticket: Option<&str>) -> QueueStatus {
+    let mut queues = QUEUES.lock().unwrap();
+    let Some(line) = queues.get_mut(&link.id) else {
+        return QueueStatus {
+            position: None,
+            ready: true,
+        };
+    };
+
+    let available = line.available(slots);
+    let position = line.position(ticket);
+    if let Some(position) = position {
+        line.tickets[position].last_seen = Instant::now();
+    }
+
+    QueueStatus {
+        position,
+        ready: position
+            .map(|position| position < available)
+            .unwrap_or(true),
+    }
+}
+
+/// The page visitors see while they wait, it polls the queue endpoint and reloads the link as
+/// soon as it's the visitor's turn.
+pub fn waiting_page(link: &Link, ticket: &str, position: usize, secure: &str) -> Response {
+    let status_url = html::escape(&format!("/api/links/{}/queue", link.url));
+    let refresh_secs = 10 * POLL_INTERVAL_SECS;
+    let poll_millis = POLL_INTERVAL_SECS * 1000;
+
+    let page = html::page(
+        "Waiting room",
+        &format!(
+            r#"    <noscript><meta http-equiv="refresh" content="{refresh_secs}"></noscript>
+    <style>
+        body {{ font-family: sans-serif; max-width: 32rem; margin: 15vh auto 0; padding: 1rem; text-align: center; }}
+        .position {{ font-size: 3rem; font-weight: bold; color: #570df8; margin: 1rem 0; }}
+    </style>"#
+        ),
+        &format!(
+            r#"    <h1>You are in line</h1>
+    <p>Every target of this hurlurl is full right now. Keep this page open, you are sent on as soon as a place opens up.</p>
+    <p>Your place in line</p>
+    <div class="position" id="position">{}</div>
+    <script>
+        setInterval(async () => {{
+            const response = await fetch("{status_url}", {{ credentials: "same-origin" }});
+            if (!response.ok) return;
+            const status = await response.json();
+            if (status.ready) location.reload();
+            else if (status.position !== null) document.getElementById("position").textContent = status.position + 1;
+        }}, {poll_millis});
+    </script>"#,
+            position + 1
+        ),
+    );
+
+    let cookie = format!(
+        "{}={ticket}; Path=/; Max-Age={TICKET_COOKIE_MAX_AGE_SECS}; HttpOnly; SameSite=Lax{secure}",
+        cookie_name(link),
+    );
+
+    (
+        StatusCode::OK,
+        [
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (header::SET_COOKIE, cookie),
+        ],
+        Html(page),
+    )
+        .into_response()
+}
+
+/// Polled by the waiting room page to show the position of the visitor.
+pub async fn queue_status(
+    Path(params): Path<Params>,
+    State(pool): State<Pool>,
+    namespace: Namespace,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let (link, target_results) =
+        get_link_and_targets(&mut connection, namespace.domain_id(), &params.link)
+            .await
+            .map_err(ApiError::not_found("link"))?;
+
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(status(
+            &link,
+            free_slots(&target_results),
+            ticket(&headers, &link),
+        )),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A link that is only used by one test, the lines of all tests live in the same map.
+    fn link(id: i32) -> Link {
+        serde_json::from_value(json!({
+            "id": id,
+            "url": format!("queue-{id}"),
+            "redirects": 0,
+            "permanentRedirect": false,
+            "fraud": false,
+        }))
+        .unwrap()
+    }
+
+    fn target(capacity: Option<i32>, redirects: i32, active: bool) -> Target {
+        Target {
+            id: 1,
+            link_id: 1,
+            target_url: "https://example.com".to_string(),
+            redirects,
+            label: None,
+            description: None,
+            color: None,
+            weight: 1,
+            capacity,
+            active,
+        }
+    }
+
+    fn waiting(admission: Admission) -> (String, usize) {
+        match admission {
+            Admission::Waiting { ticket, position } => (ticket, position),
+            Admission::Admitted => panic!("the visitor was admitted"),
+            Admission::Rejected => panic!("the visitor was rejected"),
+        }
+    }
+
+    #[test]
+    fn free_slots_only_count_active_targets() {
+        assert_eq!(free_slots(&[]), 0);
+        assert_eq!(free_slots(&[target(Some(10), 4, true)]), 6);
+        assert_eq!(free_slots(&[target(Some(10), 12, true)]), 0);
+        assert_eq!(free_slots(&[target(None, 4, true)]), usize::MAX);
+        assert_eq!(
+            free_slots(&[target(Some(10), 4, true), target(None, 0, false)]),
+            6
+        );
+        assert_eq!(free_slots(&[target(None, 0, false)]), 0);
+    }
+
+    #[test]
+    fn bursts_dont_get_more_places_than_are_free() {
+        let link = link(-1);
+
+        assert!(matches!(admit(&link, 2, None), Admission::Admitted));
+        assert!(matches!(admit(&link, 2, None), Admission::Admitted));
+
+        // The targets haven't counted the two visits yet, but their places are taken
+        let (_, position) = waiting(admit(&link, 2, None));
+        assert_eq!(position, 0);
+    }
+
+    #[test]
+    fn visitors_are_admitted_in_the_order_they_arrived() {
+        let link = link(-2);
+
+        let (first, position) = waiting(admit(&link, 0, None));
+        assert_eq!(position, 0);
+        let (second, position) = waiting(admit(&link, 0, None));
+        assert_eq!(position, 1);
+
+        // One place opens up, it belongs to the first visitor in line
+        let (ticket, position) = waiting(admit(&link, 1, Some(&second)));
+        assert_eq!((ticket.as_str(), position), (second.as_str(), 1));
+        assert!(matches!(admit(&link, 1, Some(&first)), Admission::Admitted));
+
+        // The first visitor's place is reserved, the second one moves up but keeps waiting
+        let (_, position) = waiting(admit(&link, 1, Some(&second)));
+        assert_eq!(position, 0);
+        assert!(matches!(
+            admit(&link, 2, Some(&second)),
+            Admission::Admitted
+        ));
+    }
+
+    #[test]
+    fn resolving_doesnt_skip_the_line() {
+        let empty = link(-3);
+        assert!(has_room(&empty, 1));
+        assert!(!has_room(&empty, 1), "the place is reserved");
+
+        let waited_for = link(-4);
+        waiting(admit(&waited_for, 0, None));
+        assert!(!has_room(&waited_for, 1));
+        assert!(has_room(&waited_for, 2));
+        assert!(!has_room(&waited_for, 0));
+    }
+
+    #[test]
+    fn status_shows_the_position_in_line() {
+        let link = link(-5);
+        let unknown = status(&link, 0, Some("unknown"));
+        assert_eq!((unknown.position, unknown.ready), (None, true));
+
+        let (first, _) = waiting(admit(&link, 0, None));
+        let (second, _) = waiting(admit(&link, 0, None));
+
+        let status_of = |ticket: &str, slots| {
+            let status = status(&link, slots, Some(ticket));
+            (status.position, status.ready)
+        };
+        assert_eq!(status_of(&first, 0), (Some(0), false));
+        assert_eq!(status_of(&second, 0), (Some(1), false));
+        assert_eq!(status_of(&first, 1), (Some(0), true));
+        assert_eq!(status_of(&second, 1), (Some(1), false));
+        assert_eq!(status_of("unknown", 0), (None, true));
+    }
+}
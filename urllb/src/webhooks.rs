@@ -0,0 +1,284 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha2::Sha256;
+
+use crate::proxy::{is_public_host, PublicResolver};
+use crate::Config;
+
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+/// The first retry waits this long, every further one twice as long as the one before.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("The webhook url is invalid or not public")]
+    InvalidUrl,
+    #[error("Calling the webhook failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("The webhook answered with status {0}")]
+    Status(StatusCode),
+    #[error("The webhook response is larger than {MAX_RESPONSE_BYTES} bytes")]
+    TooLarge,
+}
+
+impl WebhookError {
+    /// Whether trying again later might help, client errors other than rate limits won't go away.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            WebhookError::InvalidUrl | WebhookError::TooLarge => false,
+            WebhookError::Request(_) => true,
+            WebhookError::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+}
+
+/// The outcome of sending a webhook, after all attempts.
+pub struct Delivery {
+    pub attempts: u32,
+    pub result: Result<Vec<u8>, WebhookError>,
+}
+
+/// The `x-hurlurl-signature` header of a webhook request. Receivers recompute the HMAC-SHA256
+/// of `{timestamp}.{body}` with their secret and should reject old timestamps.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+
+    format!(
+        "t={timestamp},v1={}",
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Sends signed json requests to the webhooks of links.
+pub struct Webhooks {
+    client: reqwest::Client,
+    max_attempts: u32,
+    allow_private: bool,
+}
+
+impl Webhooks {
+    pub fn new(config: &Config) -> Self {
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.webhook_timeout_secs))
+            .redirect(reqwest::redirect::Policy::none());
+
+        if !config.webhook_allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        Self {
+            client: builder.build().expect("Failed to build webhook client"),
+            max_attempts: config.webhook_max_attempts.max(1),
+            allow_private: config.webhook_allow_private,
+        }
+    }
+
+    /// Posts the body to the webhook, retrying transient failures with exponential backoff.
+    /// Every attempt is signed with a fresh timestamp.
+    pub async fn post(&self, url: &str, secret: &str, body: &[u8]) -> Delivery {
+        let mut attempts = 0;
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            attempts += 1;
+            let result = self.send(url, secret, body).await;
+
+            match result {
+                Err(err) if err.is_transient() && attempts < self.max_attempts => {
+                    tracing::debug!("Webhook {} failed, retrying: {}", url, err);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return Delivery { attempts, result },
+            }
+        }
+    }
+
+    /// A single signed attempt, returns the response body.
+    #[tracing::instrument(skip_all)]
+    pub async fn send(
+        &self,
+        url: &str,
+        secret: &str,
+        body: &[u8],
+    ) -> Result<Vec<u8>, WebhookError> {
+        let url = Url::parse(url).map_err(|_| WebhookError::InvalidUrl)?;
+        if !matches!(url.scheme(), "http" | "https")
+            || (!self.allow_private && !is_public_host(&url))
+        {
+            return Err(WebhookError::InvalidUrl);
+        }
+
+        let mut response = self
+            .client
+            .post(url)
+            .header("content-type", "application/json")
+            .header(
+                "x-hurlurl-signature",
+                signature(secret, Utc::now().timestamp(), body),
+            )
+            .body(body.to_vec())
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(WebhookError::Status(response.status()));
+        }
+
+        let mut response_body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if response_body.len() + chunk.len() > MAX_RESPONSE_BYTES {
+                return Err(WebhookError::TooLarge);
+            }
+            response_body.extend_from_slice(&chunk);
+        }
+
+        Ok(response_body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use axum::body::Bytes;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+
+    use super::*;
+    use crate::test_support::config_with;
+
+    /// The signature header and body of every request a webhook got.
+    type Requests = Arc<Mutex<Vec<(String, Bytes)>>>;
+
+    /// A local webhook that fails with the given statuses first, then answers `ok`. Returns its
+    /// url and the requests it got.
+    fn spawn_webhook(failures: Vec<StatusCode>) -> (String, Requests) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let received = requests.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                let signature = headers["x-hurlurl-signature"].to_str().unwrap().to_string();
+                received.lock().unwrap().push((signature, body));
+
+                match failures.get(calls.fetch_add(1, Ordering::SeqCst)) {
+                    Some(status) => (*status, "failed"),
+                    None => (StatusCode::OK, "ok"),
+                }
+            }),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr: SocketAddr = server.local_addr();
+        tokio::spawn(server);
+
+        (format!("http://{addr}/hook"), requests)
+    }
+
+    fn webhooks(max_attempts: &str) -> Webhooks {
+        Webhooks::new(&config_with(&[
+            ("WEBHOOK_ALLOW_PRIVATE", "true"),
+            ("WEBHOOK_MAX_ATTEMPTS", max_attempts),
+        ]))
+    }
+
+    #[test]
+    fn signatures_cover_the_timestamp_and_body() {
+        // Computed independently, e.g. with python's hmac module
+        assert_eq!(
+            signature("secret", 1_700_000_000, br#"{"event":"capacity_low"}"#),
+            "t=1700000000,v1=fa26107df3941ef733d0e591c8f42f092435eb96f3fddb16f0b9ee21aa0f53fd"
+        );
+        assert_ne!(
+            signature("secret", 1_700_000_001, b"{}"),
+            signature("secret", 1_700_000_000, b"{}")
+        );
+        assert_ne!(
+            signature("other", 1_700_000_000, b"{}"),
+            signature("secret", 1_700_000_000, b"{}")
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_are_signed() {
+        let (url, requests) = spawn_webhook(vec![]);
+
+        let delivery = webhooks("1").post(&url, "secret", b"{}").await;
+        assert_eq!(delivery.result.unwrap(), b"ok");
+
+        let (header, body) = requests.lock().unwrap()[0].clone();
+        let timestamp = header
+            .strip_prefix("t=")
+            .and_then(|header| header.split_once(','))
+            .map(|(timestamp, _)| timestamp.parse::<i64>().unwrap())
+            .unwrap();
+        assert!((Utc::now().timestamp() - timestamp).abs() < 60);
+        assert_eq!(header, signature("secret", timestamp, &body));
+        assert_eq!(&body[..], b"{}");
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let (url, requests) = spawn_webhook(vec![StatusCode::SERVICE_UNAVAILABLE]);
+
+        let delivery = webhooks("3").post(&url, "secret", b"{}").await;
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.result.unwrap(), b"ok");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_the_last_attempt() {
+        let (url, _) = spawn_webhook(vec![StatusCode::INTERNAL_SERVER_ERROR; 5]);
+
+        let delivery = webhooks("2").post(&url, "secret", b"{}").await;
+        assert_eq!(delivery.attempts, 2);
+        assert!(matches!(
+            delivery.result,
+            Err(WebhookError::Status(StatusCode::INTERNAL_SERVER_ERROR))
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (url, _) = spawn_webhook(vec![StatusCode::BAD_REQUEST]);
+
+        let delivery = webhooks("3").post(&url, "secret", b"{}").await;
+        assert_eq!(delivery.attempts, 1);
+        assert!(matches!(
+            delivery.result,
+            Err(WebhookError::Status(StatusCode::BAD_REQUEST))
+        ));
+    }
+
+    #[tokio::test]
+    async fn private_urls_are_refused() {
+        let webhooks = Webhooks::new(&config_with(&[("WEBHOOK_MAX_ATTEMPTS", "3")]));
+
+        for url in [
+            "http://127.0.0.1:9/hook",
+            "http://[::1]/hook",
+            "ftp://example.com",
+        ] {
+            let delivery = webhooks.post(url, "secret", b"{}").await;
+            assert_eq!(delivery.attempts, 1, "{url}");
+            assert!(matches!(delivery.result, Err(WebhookError::InvalidUrl)));
+        }
+    }
+}
@@ -0,0 +1,662 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use nanoid::nanoid;
+
+use shared::schema::{workspace_invites, workspace_members, workspaces};
+
+use crate::account::{hash_token, AccountAuth};
+use crate::db::{Connection, Pool};
+use crate::error::{self, Api, ApiError, Error};
+use crate::models::{
+    Account, CreateWorkspaceDto, InviteDto, Link, NewWorkspace, NewWorkspaceInvite,
+    NewWorkspaceMember, Workspace, WorkspaceDto, WorkspaceInvite, WorkspaceMember, WorkspaceRole,
+    WorkspaceRoleDto,
+};
+use crate::service::{get_workspace_links, into_link_dtos};
+use crate::stats;
+
+#[tracing::instrument(skip_all)]
+pub async fn member_role<'c>(
+    connection: &mut Connection<'c>,
+    workspace: i32,
+    account: i32,
+) -> error::Result<Option<WorkspaceRole>> {
+    Ok(workspace_members::table
+        .find((workspace, account))
+        .select(workspace_members::role)
+        .first::<WorkspaceRole>(connection)
+        .await
+        .optional()?)
+}
+
+/// Returns the role of the account in the workspace if it passes the check.
+/// Accounts outside of the workspace get a 404, so they can't probe for workspaces.
+async fn require_role<'c>(
+    connection: &mut Connection<'c>,
+    workspace: i32,
+    account: &Account,
+    check: fn(&WorkspaceRole) -> bool,
+) -> Result<WorkspaceRole, ApiError> {
+    let role = member_role(connection, workspace, account.id)
+        .await?
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                "workspace_not_found",
+                "The workspace doesn't exist",
+            )
+        })?;
+
+    if check(&role) {
+        Ok(role)
+    } else {
+        Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "insufficient_role",
+            "Your role in the workspace doesn't allow this",
+        ))
+    }
+}
+
+/// The role an account has on a link. Links in a workspace use the role in that workspace,
+/// links outside of one are fully controlled by the account that created them.
+pub async fn link_role<'c>(
+    connection: &mut Connection<'c>,
+    link: &Link,
+    account: Option<&Account>,
+) -> error::Result<Option<WorkspaceRole>> {
+    let Some(account) = account else {
+        return Ok(None);
+    };
+
+    match link.workspace_id {
+        Some(workspace) => member_role(connection, workspace, account.id).await,
+        None if link.account_id == Some(account.id) => Ok(Some(WorkspaceRole::Owner)),
+        None => Ok(None),
+    }
+}
+
+/// Creates a workspace with the requesting account as its owner.
+#[tracing::instrument(skip_all)]
+pub async fn create_workspace(
+    AccountAuth(account): AccountAuth,
+    State(pool): State<Pool>,
+    Api(Json(body)): Api<Json<CreateWorkspaceDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(
+            ApiError::bad_request("invalid_name", "The workspace needs a name")
+                .with_field("name", "required"),
+        );
+    }
+
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+    let owner = account.id;
+
+    let workspace = connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                let workspace = diesel::insert_into(workspaces::table)
+                    .values(NewWorkspace { name: &name })
+                    .get_result::<Workspace>(connection)
+                    .await?;
+
+                diesel::insert_into(workspace_members::table)
+                    .values(NewWorkspaceMember {
+                        workspace_id: workspace.id,
+                        account_id: owner,
+                        role: WorkspaceRole::Owner,
+                    })
+                    .execute(connection)
+                    .await?;
+
+                Ok(workspace)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(WorkspaceDto {
+            workspace,
+            role: WorkspaceRole::Owner,
+        }),
+    ))
+}
+
+/// Lists the workspaces the account is a member of.
+#[tracing::instrument(skip_all)]
+pub async fn list_workspaces(
+    AccountAuth(account): AccountAuth,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    let memberships = workspaces::table
+        .inner_join(workspace_members::table)
+        .filter(workspace_members::account_id.eq(account.id))
+        .order(workspaces::id.asc())
+        .select((workspaces::all_columns, workspace_members::role))
+        .load::<(Workspace, WorkspaceRole)>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(Json(
+        memberships
+            .into_iter()
+            .map(|(workspace, role)| WorkspaceDto { workspace, role })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+pub async fn workspace_links(
+    AccountAuth(account): AccountAuth,
+    Api(Path(workspace)): Api<Path<i32>>,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    require_role(&mut connection, workspace, &account, |_| true).await?;
+
+    let links = get_workspace_links(&mut connection, workspace).await?;
+
+    Ok(Json(into_link_dtos(&mut connection, links).await?))
+}
+
+pub async fn workspace_stats(
+    AccountAuth(account): AccountAuth,
+    Api(Path(workspace)): Api<Path<i32>>,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    require_role(&mut connection, workspace, &account, |_| true).await?;
+
+    let stats = stats::workspace_stats(&mut connection, workspace)
+        .await
+        .map_err(Error::Stats)?;
+
+    Ok(Json(stats))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn members(
+    AccountAuth(account): AccountAuth,
+    Api(Path(workspace)): Api<Path<i32>>,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    require_role(&mut connection, workspace, &account, |_| true).await?;
+
+    let member_list = workspace_members::table
+        .filter(workspace_members::workspace_id.eq(workspace))
+        .order(workspace_members::created_at.asc())
+        .load::<WorkspaceMember>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(Json(member_list))
+}
+
+/// Creates a single use invite code that adds the account redeeming it with the given role.
+#[tracing::instrument(skip_all)]
+pub async fn create_invite(
+    AccountAuth(account): AccountAuth,
+    Api(Path(workspace)): Api<Path<i32>>,
+    State(pool): State<Pool>,
+    Api(Json(body)): Api<Json<WorkspaceRoleDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    require_role(
+        &mut connection,
+        workspace,
+        &account,
+        WorkspaceRole::can_manage_members,
+    )
+    .await?;
+
+    let code = nanoid!(32);
+
+    diesel::insert_into(workspace_invites::table)
+        .values(NewWorkspaceInvite {
+            workspace_id: workspace,
+            code_hash: &hash_token(&code),
+            role: body.role,
+        })
+        .execute(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(InviteDto {
+            code,
+            role: body.role,
+        }),
+    ))
+}
+
+/// Redeems an invite code, accounts that already are members get the role of the invite.
+#[tracing::instrument(skip_all)]
+pub async fn accept_invite(
+    AccountAuth(account): AccountAuth,
+    Path(code): Path<String>,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+    let member = account.id;
+    let code_hash = hash_token(&code);
+
+    let joined = connection
+        .transaction::<_, Error, _>(|connection| {
+            async move {
+                // Deleting the invite first makes sure it can only be used once
+                let invite = diesel::delete(
+                    workspace_invites::table.filter(workspace_invites::code_hash.eq(&code_hash)),
+                )
+                .get_result::<WorkspaceInvite>(connection)
+                .await
+                .optional()?;
+
+                let Some(invite) = invite else {
+                    return Ok(None);
+                };
+
+                diesel::insert_into(workspace_members::table)
+                    .values(NewWorkspaceMember {
+                        workspace_id: invite.workspace_id,
+                        account_id: member,
+                        role: invite.role,
+                    })
+                    .on_conflict((
+                        workspace_members::workspace_id,
+                        workspace_members::account_id,
+                    ))
+                    .do_update()
+                    .set(workspace_members::role.eq(invite.role))
+                    .execute(connection)
+                    .await?;
+
+                let workspace = workspaces::table
+                    .find(invite.workspace_id)
+                    .first::<Workspace>(connection)
+                    .await?;
+
+                Ok(Some(WorkspaceDto {
+                    workspace,
+                    role: invite.role,
+                }))
+            }
+            .scope_boxed()
+        })
+        .await?
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                "invite_not_found",
+                "The invite doesn't exist or was already used",
+            )
+        })?;
+
+    Ok(Json(joined))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_member(
+    AccountAuth(account): AccountAuth,
+    Api(Path((workspace, member))): Api<Path<(i32, i32)>>,
+    State(pool): State<Pool>,
+    Api(Json(body)): Api<Json<WorkspaceRoleDto>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    require_role(
+        &mut connection,
+        workspace,
+        &account,
+        WorkspaceRole::can_manage_members,
+    )
+    .await?;
+
+    let role = member_role(&mut connection, workspace, member)
+        .await?
+        .ok_or_else(member_not_found)?;
+
+    if role == WorkspaceRole::Owner
+        && body.role != WorkspaceRole::Owner
+        && is_last_owner(&mut connection, workspace).await?
+    {
+        return Err(last_owner());
+    }
+
+    let updated = diesel::update(workspace_members::table.find((workspace, member)))
+        .set(workspace_members::role.eq(body.role))
+        .get_result::<WorkspaceMember>(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(Json(updated))
+}
+
+/// Removes a member from the workspace, owners can remove anyone and members can leave.
+#[tracing::instrument(skip_all)]
+pub async fn remove_member(
+    AccountAuth(account): AccountAuth,
+    Api(Path((workspace, member))): Api<Path<(i32, i32)>>,
+    State(pool): State<Pool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut connection = pool.get().await.map_err(Error::PoolError)?;
+
+    if member != account.id {
+        require_role(
+            &mut connection,
+            workspace,
+            &account,
+            WorkspaceRole::can_manage_members,
+        )
+        .await?;
+    }
+
+    let role = member_role(&mut connection, workspace, member)
+        .await?
+        .ok_or_else(member_not_found)?;
+
+    // A workspace without owners could never get new members again
+    if role == WorkspaceRole::Owner && is_last_owner(&mut connection, workspace).await? {
+        return Err(last_owner());
+    }
+
+    diesel::delete(workspace_members::table.find((workspace, member)))
+        .execute(&mut connection)
+        .await
+        .map_err(Error::DieselError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn member_not_found() -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        "member_not_found",
+        "The account isn't a member of the workspace",
+    )
+}
+
+fn last_owner() -> ApiError {
+    ApiError::new(
+        StatusCode::CONFLICT,
+        "last_owner",
+        "A workspace needs at least one owner",
+    )
+}
+
+#[tracing::instrument(skip_all)]
+async fn is_last_owner<'c>(connection: &mut Connection<'c>, workspace: i32) -> error::Result<bool> {
+    let owners = workspace_members::table
+        .filter(workspace_members::workspace_id.eq(workspace))
+        .filter(workspace_members::role.eq(WorkspaceRole::Owner))
+        .count()
+        .get_result::<i64>(connection)
+        .await?;
+
+    Ok(owners <= 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::ErrorDto;
+    use crate::test_support::{create_test_account, create_test_link, json_response, pool};
+
+    /// The status and code of a request that should have failed.
+    async fn rejected<T>(result: Result<T, ApiError>) -> (StatusCode, String) {
+        let Err(error) = result else {
+            panic!("The request should have been rejected");
+        };
+        let (status, error) = json_response::<ErrorDto>(error).await;
+        (status, error.code)
+    }
+
+    async fn workspace(pool: &Pool, owner: &Account) -> i32 {
+        let response = create_workspace(
+            AccountAuth(owner.clone()),
+            State(pool.clone()),
+            Api(Json(CreateWorkspaceDto {
+                name: "Team".to_string(),
+            })),
+        )
+        .await
+        .unwrap();
+
+        let (status, workspace) = json_response::<WorkspaceDto>(response).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(workspace.role, WorkspaceRole::Owner);
+        workspace.workspace.id
+    }
+
+    async fn invite(
+        pool: &Pool,
+        inviting: &Account,
+        workspace: i32,
+        role: WorkspaceRole,
+    ) -> Result<String, ApiError> {
+        let response = create_invite(
+            AccountAuth(inviting.clone()),
+            Api(Path(workspace)),
+            State(pool.clone()),
+            Api(Json(WorkspaceRoleDto { role })),
+        )
+        .await?;
+
+        let (_, invite) = json_response::<InviteDto>(response).await;
+        Ok(invite.code)
+    }
+
+    async fn join(pool: &Pool, account: &Account, code: &str) -> Result<WorkspaceDto, ApiError> {
+        let response = accept_invite(
+            AccountAuth(account.clone()),
+            Path(code.to_string()),
+            State(pool.clone()),
+        )
+        .await?;
+
+        Ok(json_response::<WorkspaceDto>(response).await.1)
+    }
+
+    async fn member(pool: &Pool, owner: &Account, workspace: i32, role: WorkspaceRole) -> Account {
+        let account = create_test_account(pool).await;
+        let code = invite(pool, owner, workspace, role).await.unwrap();
+        assert_eq!(join(pool, &account, &code).await.unwrap().role, role);
+        account
+    }
+
+    async fn set_role(
+        pool: &Pool,
+        changing: &Account,
+        workspace: i32,
+        member: &Account,
+        role: WorkspaceRole,
+    ) -> Result<(), ApiError> {
+        update_member(
+            AccountAuth(changing.clone()),
+            Api(Path((workspace, member.id))),
+            State(pool.clone()),
+            Api(Json(WorkspaceRoleDto { role })),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    async fn remove(
+        pool: &Pool,
+        removing: &Account,
+        workspace: i32,
+        member: &Account,
+    ) -> Result<(), ApiError> {
+        remove_member(
+            AccountAuth(removing.clone()),
+            Api(Path((workspace, member.id))),
+            State(pool.clone()),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn invites_are_single_use() {
+        let pool = pool().await;
+        let owner = create_test_account(&pool).await;
+        let workspace = workspace(&pool, &owner).await;
+
+        let code = invite(&pool, &owner, workspace, WorkspaceRole::Editor)
+            .await
+            .unwrap();
+        let joined = join(&pool, &create_test_account(&pool).await, &code)
+            .await
+            .unwrap();
+        assert_eq!(joined.workspace.id, workspace);
+
+        let (status, code) =
+            rejected(join(&pool, &create_test_account(&pool).await, &code).await).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(code, "invite_not_found");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn only_owners_manage_members() {
+        let pool = pool().await;
+        let owner = create_test_account(&pool).await;
+        let workspace = workspace(&pool, &owner).await;
+        let editor = member(&pool, &owner, workspace, WorkspaceRole::Editor).await;
+        let viewer = member(&pool, &owner, workspace, WorkspaceRole::Viewer).await;
+        let outsider = create_test_account(&pool).await;
+
+        for account in [&editor, &viewer] {
+            let (status, code) =
+                rejected(invite(&pool, account, workspace, WorkspaceRole::Owner).await).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert_eq!(code, "insufficient_role");
+
+            let (status, _) =
+                rejected(set_role(&pool, account, workspace, account, WorkspaceRole::Owner).await)
+                    .await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+        }
+
+        let (status, _) = rejected(remove(&pool, &editor, workspace, &viewer).await).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Outsiders can't tell the workspace exists
+        let (status, code) =
+            rejected(invite(&pool, &outsider, workspace, WorkspaceRole::Viewer).await).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(code, "workspace_not_found");
+
+        // Members can always leave
+        remove(&pool, &viewer, workspace, &viewer).await.unwrap();
+        set_role(&pool, &owner, workspace, &editor, WorkspaceRole::Viewer)
+            .await
+            .unwrap();
+
+        let mut connection = pool.get().await.unwrap();
+        assert_eq!(
+            member_role(&mut connection, workspace, viewer.id)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            member_role(&mut connection, workspace, editor.id)
+                .await
+                .unwrap(),
+            Some(WorkspaceRole::Viewer)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn the_last_owner_stays() {
+        let pool = pool().await;
+        let owner = create_test_account(&pool).await;
+        let workspace = workspace(&pool, &owner).await;
+
+        let (status, code) = rejected(remove(&pool, &owner, workspace, &owner).await).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(code, "last_owner");
+
+        let (status, _) =
+            rejected(set_role(&pool, &owner, workspace, &owner, WorkspaceRole::Editor).await).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // With a second owner either of them can leave
+        let second = member(&pool, &owner, workspace, WorkspaceRole::Owner).await;
+        remove(&pool, &owner, workspace, &owner).await.unwrap();
+
+        let (status, _) = rejected(remove(&pool, &second, workspace, &second).await).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn link_roles() {
+        let pool = pool().await;
+        let owner = create_test_account(&pool).await;
+        let workspace = workspace(&pool, &owner).await;
+        let viewer = member(&pool, &owner, workspace, WorkspaceRole::Viewer).await;
+        let outsider = create_test_account(&pool).await;
+
+        let (shared, _, _) = create_test_link(
+            &pool,
+            json!({ "targets": [{ "targetUrl": "https://example.com" }], "workspace": workspace }),
+            None,
+        )
+        .await;
+        let (mut own, _, _) = create_test_link(
+            &pool,
+            json!({ "targets": [{ "targetUrl": "https://example.com" }] }),
+            None,
+        )
+        .await;
+        own.account_id = Some(outsider.id);
+
+        let mut connection = pool.get().await.unwrap();
+        assert_eq!(
+            link_role(&mut connection, &shared, Some(&viewer))
+                .await
+                .unwrap(),
+            Some(WorkspaceRole::Viewer)
+        );
+        assert_eq!(
+            link_role(&mut connection, &shared, Some(&outsider))
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            link_role(&mut connection, &own, Some(&outsider))
+                .await
+                .unwrap(),
+            Some(WorkspaceRole::Owner)
+        );
+        assert_eq!(
+            link_role(&mut connection, &own, Some(&owner))
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(link_role(&mut connection, &own, None).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use gloo_net::http::Response;
+use serde::de::DeserializeOwned;
+use shared::ErrorDto;
+use yew::prelude::*;
+
+/// For when the api can't be reached or doesn't answer with an [`ErrorDto`].
+pub fn unexpected_error(message: &str) -> ErrorDto {
+    ErrorDto {
+        code: "unexpected".to_string(),
+        message: message.to_string(),
+        fields: BTreeMap::new(),
+    }
+}
+
+/// The json body of a successful response, or the error the api answered with.
+pub async fn json_or_error<T: DeserializeOwned>(
+    result: Result<Response, gloo_net::Error>,
+) -> Result<T, ErrorDto> {
+    match result {
+        Ok(response) if response.ok() => response
+            .json::<T>()
+            .await
+            .map_err(|_| unexpected_error("The response couldn't be read")),
+        Ok(response) => Err(response.json::<ErrorDto>().await.unwrap_or_else(|_| {
+            unexpected_error(&format!("The request failed: {}", response.status_text()))
+        })),
+        Err(_) => Err(unexpected_error("hurlurl can't be reached right now")),
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ErrorAlertProps {
+    pub error: ErrorDto,
+}
+
+/// Shows the message of an api error together with what's wrong with each field.
+#[function_component(ErrorAlert)]
+pub fn error_alert(props: &ErrorAlertProps) -> Html {
+    let error = &props.error;
+
+    html! {
+        <div class="alert alert-error mt-4">
+            <div class="flex flex-col items-start">
+                <span>{&error.message}</span>
+                <ul class="list-disc list-inside">
+                    { for error.fields.iter().map(|(field, errors)| {
+                        let details = errors
+                            .iter()
+                            .map(|error| error.message.clone().unwrap_or_else(|| error.code.clone()))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        html! { <li>{format!("{field}: {details}")}</li> }
+                    }) }
+                </ul>
+            </div>
+        </div>
+    }
+}
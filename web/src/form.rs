@@ -1,13 +1,20 @@
+use crate::error_alert::{json_or_error, ErrorAlert};
+use crate::group_affinity_checkbox::GroupAffinityCheckbox;
 use crate::permanent_redirect_checkbox::PermanentRedirectCheckbox;
+use crate::session::{self, authorize};
+use crate::use_fetch::use_fetch;
 use crate::Route;
 use gloo_net::http::Request;
 use serde::{Deserialize, Serialize};
-use shared::{CreateLinkDto, CreateResult, CreateTargetDto};
+use shared::{
+    CreateLinkDto, CreateResult, CreateTargetDto, ErrorDto, LinkVisibility, PaymentProviderKind,
+    PublicConfig, QuoteDto,
+};
 use validator::Validate;
 use wasm_bindgen::prelude::wasm_bindgen;
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{Event, HtmlInputElement};
-use yew::{function_component, html, use_state, Callback, Html};
+use web_sys::{Event, HtmlInputElement, HtmlSelectElement};
+use yew::{function_component, html, use_effect_with_deps, use_state, Callback, Html};
 use yew_router::hooks::use_navigator;
 
 #[derive(Serialize, Deserialize)]
@@ -16,6 +23,13 @@ struct PlausibleProps {
     targets: usize,
 }
 
+/// The optional details of a target that can be edited in the form.
+enum TargetDetail {
+    Label,
+    Description,
+    Color,
+}
+
 #[wasm_bindgen]
 extern "C" {
     fn plausible(s: &str, props: JsValue);
@@ -25,6 +39,8 @@ extern "C" {
 pub fn form() -> Html {
     let targets = use_state::<Vec<CreateTargetDto>, _>(std::vec::Vec::new);
 
+    let config = use_fetch::<PublicConfig>("/api/config");
+
     let plausible_event = |props: PlausibleProps| {
         plausible(
             "Create hurlurl",
@@ -34,13 +50,94 @@ pub fn form() -> Html {
 
     let permanent_redirect = use_state(|| false);
 
-    let errors = targets
+    let group_affinity = use_state(|| false);
+
+    let picker = use_state(|| false);
+
+    let visibility = use_state(LinkVisibility::default);
+
+    let password = use_state(String::new);
+
+    let quote = use_state::<Option<QuoteDto>, _>(|| None);
+
+    let create_error = use_state::<Option<ErrorDto>, _>(|| None);
+
+    {
+        let quote = quote.clone();
+        use_effect_with_deps(
+            move |targets: &Vec<CreateTargetDto>| {
+                let targets = targets.clone();
+                let valid = targets.iter().all(|t| {
+                    t.expand()
+                        .map(|expanded| expanded.iter().all(|t| t.validate().is_ok()))
+                        .unwrap_or(false)
+                });
+                if targets.is_empty() || !valid {
+                    quote.set(None);
+                } else {
+                    wasm_bindgen_futures::spawn_local(async move {
+                        let result = authorize(Request::post("/api/links/quote"))
+                            .json(&CreateLinkDto {
+                                url: None,
+                                domain: None,
+                                workspace: session::workspace(),
+                                visibility: LinkVisibility::default(),
+                                password: None,
+                                signed: false,
+                                proxy: false,
+                                group_affinity: false,
+                                picker: false,
+                                permanent_redirect: false,
+                                targets,
+                                target_source: None,
+                                provisioning_webhook: None,
+                                provisioning_threshold: None,
+                                webhooks: vec![],
+                            })
+                            .unwrap()
+                            .send()
+                            .await;
+
+                        if let Ok(response) = result {
+                            quote.set(response.json::<QuoteDto>().await.ok());
+                        }
+                    });
+                }
+                || {}
+            },
+            (*targets).clone(),
+        );
+    }
+
+    let expansions = targets
         .iter()
-        .map(|target| {
-            if target.validate().is_ok() {
-                None
-            } else {
-                Some("Invalid URL")
+        .map(CreateTargetDto::expand)
+        .collect::<Vec<_>>();
+
+    let errors = expansions
+        .iter()
+        .map(|expansion| match expansion {
+            Err(error) => Some(error.to_string()),
+            Ok(expanded) => {
+                if let Some(errors) = expanded.iter().find_map(|target| target.validate().err()) {
+                    if errors.field_errors().contains_key("target_url") {
+                        Some("Invalid URL".to_string())
+                    } else {
+                        Some("Invalid label, description or color".to_string())
+                    }
+                } else if quote
+                    .as_ref()
+                    .map(|quote| {
+                        expanded
+                            .iter()
+                            .any(|target| quote.blocked_targets.contains(&target.target_url))
+                    })
+                    .unwrap_or(false)
+                {
+                    Some("This domain is blocked".to_string())
+                } else {
+                    None
+                }
             }
         })
         .collect::<Vec<_>>();
@@ -59,7 +156,10 @@ pub fn form() -> Html {
             input.set_value("");
 
             let mut targets_clone = (*targets).clone();
-            targets_clone.push(CreateTargetDto { target_url: value });
+            targets_clone.push(CreateTargetDto {
+                target_url: value,
+                ..Default::default()
+            });
             targets.set(targets_clone);
         })
     };
@@ -80,32 +180,85 @@ pub fn form() -> Html {
         })
     };
 
+    let on_detail_change = {
+        let targets = targets.clone();
+        Callback::from(move |(e, i, detail): (Event, usize, TargetDetail)| {
+            let value = e
+                .target()
+                .unwrap()
+                .dyn_ref::<HtmlInputElement>()
+                .unwrap()
+                .value();
+            let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+
+            let mut targets_clone = (*targets).clone();
+            match detail {
+                TargetDetail::Label => targets_clone[i].label = value,
+                TargetDetail::Description => targets_clone[i].description = value,
+                TargetDetail::Color => targets_clone[i].color = value,
+            }
+            targets.set(targets_clone);
+        })
+    };
+
     let create_link = {
         let targets = targets.clone();
         let navigator = navigator.clone();
         let permanent_redirect = permanent_redirect.clone();
+        let group_affinity = group_affinity.clone();
+        let picker = picker.clone();
+        let visibility = visibility.clone();
+        let password = password.clone();
+        let create_error = create_error.clone();
         Callback::from(move |_| {
             let targets = targets.clone();
             let navigator = navigator.clone();
             let permanent_redirect = permanent_redirect.clone();
+            let group_affinity = *group_affinity;
+            let picker = *picker;
+            let visibility = visibility.clone();
+            let password = Some((*password).clone()).filter(|password| !password.is_empty());
+            let create_error = create_error.clone();
             wasm_bindgen_futures::spawn_local(async move {
-                let response = Request::post("/api/links")
+                let result = authorize(Request::post("/api/links"))
                     .header("Content-Type", "application/json")
                     .json(&CreateLinkDto {
                         url: None,
+                        domain: None,
+                        workspace: session::workspace(),
+                        visibility: *visibility,
+                        password,
+                        signed: false,
+                        proxy: false,
+                        group_affinity,
+                        picker,
                         permanent_redirect: *permanent_redirect,
                         targets: (*targets).clone(),
+                        target_source: None,
+                        provisioning_webhook: None,
+                        provisioning_threshold: None,
+                        webhooks: vec![],
                     })
                     .unwrap()
                     .send()
-                    .await
-                    .unwrap()
-                    .json::<CreateResult>()
-                    .await
-                    .unwrap();
+                    .await;
+
+                let response = json_or_error::<CreateResult>(result).await;
+
+                let response = match response {
+                    Ok(response) => response,
+                    Err(error) => {
+                        create_error.set(Some(error));
+                        return;
+                    }
+                };
+                create_error.set(None);
 
                 match response {
                     CreateResult::Link(link) => {
+                        if let Some(token) = &link.management_token {
+                            session::set_link_token(&link.link.url, token);
+                        }
                         navigator.push(&Route::Link {
                             link: link.link.url,
                         });
@@ -127,6 +280,52 @@ pub fn form() -> Html {
         })
     };
 
+    let on_visibility_change = {
+        let visibility = visibility.clone();
+        Callback::from(move |e: Event| {
+            let value = e
+                .target()
+                .unwrap()
+                .dyn_ref::<HtmlSelectElement>()
+                .unwrap()
+                .value();
+
+            visibility.set(match value.as_str() {
+                "stats_only" => LinkVisibility::StatsOnly,
+                "private" => LinkVisibility::Private,
+                _ => LinkVisibility::Public,
+            });
+        })
+    };
+
+    let group_click = {
+        let group_affinity = group_affinity.clone();
+        Callback::from(move |_| {
+            group_affinity.set(!*group_affinity);
+        })
+    };
+
+    let picker_click = {
+        let picker = picker.clone();
+        Callback::from(move |_| {
+            picker.set(!*picker);
+        })
+    };
+
+    let on_password_change = {
+        let password = password.clone();
+        Callback::from(move |e: Event| {
+            let value = e
+                .target()
+                .unwrap()
+                .dyn_ref::<HtmlInputElement>()
+                .unwrap()
+                .value();
+
+            password.set(value);
+        })
+    };
+
     let redirect_click = {
         let permanent_redirect = permanent_redirect.clone();
         Callback::from(move |_| {
@@ -138,21 +337,63 @@ pub fn form() -> Html {
         <>
             { targets.iter().enumerate().map(|(i, target)| {
                 let on_target_change = on_target_change.clone();
+                let on_label_change = { let on_detail_change = on_detail_change.clone(); move |e| on_detail_change.emit((e, i, TargetDetail::Label)) };
+                let on_description_change = { let on_detail_change = on_detail_change.clone(); move |e| on_detail_change.emit((e, i, TargetDetail::Description)) };
+                let on_color_change = { let on_detail_change = on_detail_change.clone(); move |e| on_detail_change.emit((e, i, TargetDetail::Color)) };
                 html! {
                     <div class="form-control">
                         <input
                             autofocus={true}
                             type="text"
                             placeholder="Enter URLs"
-                            class={format!("input input-bordered {}", errors[i].map(|_e| "input-error").unwrap_or(""))}
+                            class={format!("input input-bordered {}", errors[i].as_ref().map(|_e| "input-error").unwrap_or(""))}
                             value={Some(target.target_url.clone())}
                             onchange={move |e| {on_target_change.emit((e, i));}}
                         />
-                        if let Some(error) = errors[i] {
+                        if let Some(error) = &errors[i] {
                             <label class="label">
                                 <span class="label-text-alt text-error">{error}</span>
                             </label>
                         }
+                        if let Ok(expanded) = &expansions[i] {
+                            if expanded.len() > 1 {
+                                <details class="mt-1">
+                                    <summary class="cursor-pointer text-sm opacity-60">{format!("Expands to {} targets", expanded.len())}</summary>
+                                    <ul class="text-sm break-all mt-2">
+                                        { expanded.iter().map(|target| html! { <li>{&target.target_url}</li> }).collect::<Html>() }
+                                    </ul>
+                                </details>
+                            }
+                        }
+                        <details class="mt-1">
+                            <summary class="cursor-pointer text-sm opacity-60">{"Label, description and color"}</summary>
+                            <div class="flex flex-col gap-2 mt-2">
+                                <div class="flex gap-2">
+                                    <input
+                                        type="text"
+                                        placeholder="Label, e.g. Room 1"
+                                        maxlength="64"
+                                        class="input input-bordered input-sm flex-1"
+                                        value={target.label.clone().unwrap_or_default()}
+                                        onchange={on_label_change}
+                                    />
+                                    <input
+                                        type="color"
+                                        class="h-8 w-12 cursor-pointer"
+                                        value={target.color.clone().unwrap_or_else(|| "#570df8".to_string())}
+                                        onchange={on_color_change}
+                                    />
+                                </div>
+                                <input
+                                    type="text"
+                                    placeholder="Description"
+                                    maxlength="500"
+                                    class="input input-bordered input-sm"
+                                    value={target.description.clone().unwrap_or_default()}
+                                    onchange={on_description_change}
+                                />
+                            </div>
+                        </details>
                     </div>
                 }
             }).collect::<Html>() }
@@ -160,17 +401,94 @@ pub fn form() -> Html {
 
             <div class="form-control">
                 <input type="text" placeholder="Enter URLs" class="input input-bordered" onchange={add_target.clone()} />
+                <label class="label">
+                    <span class="label-text-alt opacity-60">{"Add many similar URLs at once with templates like room-{1..20} or {eu,us,asia}"}</span>
+                </label>
             </div>
 
             <PermanentRedirectCheckbox on_click={redirect_click} checked={*permanent_redirect} disabled={false} />
 
-            <div>
-                {"You will need to pay 1€ via Stripe. (No signup required!) "}
-                <label for="paid-dialog" class="cursor-pointer link">
-                    {"Why?"}
+            <GroupAffinityCheckbox on_click={group_click} checked={*group_affinity} disabled={false} />
+
+            <div class="form-control">
+                <label class="label cursor-pointer justify-start gap-4">
+                    <input type="checkbox" checked={*picker} onclick={picker_click} class="checkbox checkbox-primary" />
+                    <span class="label-text">{"Let visitors choose where to go"}</span>
+                </label>
+            </div>
+
+            <div class="form-control">
+                <label class="label">
+                    <span class="label-text">{"Who can see the stats"}</span>
+                </label>
+                <select class="select select-bordered" onchange={on_visibility_change}>
+                    <option value="public" selected={*visibility == LinkVisibility::Public}>{"Anyone with the link"}</option>
+                    <option value="stats_only" selected={*visibility == LinkVisibility::StatsOnly}>{"Anyone, but hide the targets"}</option>
+                    <option value="private" selected={*visibility == LinkVisibility::Private}>{"Only me"}</option>
+                </select>
+            </div>
+
+            <div class="form-control">
+                <label class="label">
+                    <span class="label-text">{"Password (optional)"}</span>
                 </label>
+                <input
+                    type="password"
+                    placeholder="Visitors need to enter it before they are redirected"
+                    class="input input-bordered"
+                    maxlength="128"
+                    value={(*password).clone()}
+                    onchange={on_password_change}
+                />
             </div>
 
+            { if let Some(quote) = quote.as_ref() {
+                if let Some(price) = quote.price.as_ref().filter(|_| quote.payment_required) {
+                    html! {
+                        <div>
+                            {format!("This hurlurl costs {price} via Stripe. (No signup required!) ")}
+                            <label for="paid-dialog" class="cursor-pointer link">
+                                {"Why?"}
+                            </label>
+                        </div>
+                    }
+                } else if quote.approval_required {
+                    html! {
+                        <div>
+                            {"This hurlurl needs to be approved by an admin before it starts working."}
+                        </div>
+                    }
+                } else if quote.whitelisted && config.as_ref().and_then(|config| config.price.as_ref()).is_some() {
+                    html! {
+                        <div>
+                            {"This hurlurl is free, because its targets are whitelisted. "}
+                            <label for="paid-dialog" class="cursor-pointer link">
+                                {"Why?"}
+                            </label>
+                        </div>
+                    }
+                } else {
+                    html! {}
+                }
+            } else {
+                match config.as_ref().map(|config| (config.payment_provider, config.price.as_ref())) {
+                    Some((PaymentProviderKind::Stripe, Some(price))) => html! {
+                        <div>
+                            {format!("You will need to pay {price} via Stripe. (No signup required!) ")}
+                            <label for="paid-dialog" class="cursor-pointer link">
+                                {"Why?"}
+                            </label>
+                        </div>
+                    },
+                    Some((PaymentProviderKind::ManualApproval, _)) => html! {
+                        <div>
+                            {"New hurlurls need to be approved by an admin before they start working."}
+                        </div>
+                    },
+                    _ => html! {},
+                }
+            } }
+
             <input type="checkbox" id="paid-dialog" class="modal-toggle" />
             <div class="modal">
                 <div class="modal-box">
@@ -210,6 +528,10 @@ pub fn form() -> Html {
             <div class="form-control">
                 <button class="btn btn-primary" onclick={create_link} disabled={has_error || targets.is_empty()}>{ "Create hurlurl" }</button>
             </div>
+
+            if let Some(error) = &*create_error {
+                <ErrorAlert error={error.clone()}/>
+            }
         </>
     }
 }
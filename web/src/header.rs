@@ -1,7 +1,12 @@
-use yew::{function_component, html, Html, Properties};
+use shared::WorkspaceDto;
+use wasm_bindgen::JsCast;
+use web_sys::{window, Event, HtmlInputElement, HtmlSelectElement};
+use yew::{function_component, html, Callback, Html, Properties};
 
 use yew_router::prelude::*;
 
+use crate::session;
+use crate::use_fetch::use_fetch;
 use crate::Route;
 
 #[derive(Properties, PartialEq)]
@@ -11,19 +16,88 @@ pub struct HeaderProps {
 
 #[function_component(Header)]
 pub fn header(props: &HeaderProps) -> Html {
+    let sign_in = Callback::from(|e: Event| {
+        let value = e
+            .target()
+            .unwrap()
+            .dyn_ref::<HtmlInputElement>()
+            .unwrap()
+            .value();
+
+        if !value.trim().is_empty() {
+            session::set_token(Some(value.trim()));
+            window().unwrap().location().reload().unwrap();
+        }
+    });
+
     html! {
         <div class="navbar bg-base-100">
-            <Link<Route> to={Route::Home} classes="btn btn-ghost normal-case text-xl">
-                {"hurlurl"}
-                if let Some(link) = &props.link {
-                    <span class="opacity-50">
-                        <span class="mx-1">
-                            {"/"}
+            <div class="flex-1">
+                <Link<Route> to={Route::Home} classes="btn btn-ghost normal-case text-xl">
+                    {"hurlurl"}
+                    if let Some(link) = &props.link {
+                        <span class="opacity-50">
+                            <span class="mx-1">
+                                {"/"}
+                            </span>
+                            {link}
                         </span>
-                        {link}
-                    </span>
+                    }
+                </Link<Route>>
+            </div>
+            <div class="flex-none">
+                if session::token().is_some() {
+                    <WorkspaceSwitcher/>
+                } else {
+                    <div class="dropdown dropdown-end">
+                        <label tabindex="0" class="btn btn-ghost">{"Sign in"}</label>
+                        <div tabindex="0" class="dropdown-content card card-compact p-2 shadow bg-base-100 w-72">
+                            <input type="password" placeholder="API token" class="input input-bordered" onchange={sign_in} />
+                        </div>
+                    </div>
                 }
-            </Link<Route>>
+            </div>
+        </div>
+    }
+}
+
+/// Lets signed in users choose the workspace their new links are created in.
+#[function_component(WorkspaceSwitcher)]
+fn workspace_switcher() -> Html {
+    let workspaces = use_fetch::<Vec<WorkspaceDto>>("/api/workspaces");
+    let selected = session::workspace();
+
+    let switch_workspace = Callback::from(|e: Event| {
+        let value = e
+            .target()
+            .unwrap()
+            .dyn_ref::<HtmlSelectElement>()
+            .unwrap()
+            .value();
+
+        session::set_workspace(value.parse().ok());
+        window().unwrap().location().reload().unwrap();
+    });
+
+    let sign_out = Callback::from(|_| {
+        session::set_token(None);
+        window().unwrap().location().reload().unwrap();
+    });
+
+    html! {
+        <div class="flex items-center gap-2">
+            <select class="select select-bordered select-sm" onchange={switch_workspace}>
+                <option value="" selected={selected.is_none()}>{"Personal"}</option>
+                { for workspaces.iter().flatten().map(|workspace| html! {
+                    <option
+                        value={workspace.workspace.id.to_string()}
+                        selected={selected == Some(workspace.workspace.id)}
+                    >
+                        {&workspace.workspace.name}
+                    </option>
+                }) }
+            </select>
+            <button class="btn btn-ghost btn-sm" onclick={sign_out}>{"Sign out"}</button>
         </div>
     }
 }
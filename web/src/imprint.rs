@@ -1,10 +1,16 @@
-use web_sys::window;
+use shared::PublicConfig;
 use yew::{function_component, html, Html};
 
+use crate::use_fetch::use_fetch;
+
 #[function_component(Imprint)]
 pub fn imprint() -> Html {
+    let config = use_fetch::<PublicConfig>("/api/config");
+
     // if you deploy hurlurl to your own domain you can update the imprint / legal notice here
-    let is_hurlurl = window().unwrap().location().hostname().unwrap() == "hurlurl.com";
+    let is_hurlurl = config
+        .map(|config| config.public_base_url == "https://hurlurl.com")
+        .unwrap_or(false);
 
     html! {
         <div>
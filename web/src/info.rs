@@ -1,13 +1,21 @@
+use chrono::SubsecRound;
+use gloo_net::http::Request;
 use web_sys::window;
 use yew::function_component;
 use yew::prelude::*;
 
 use crate::header::Header;
 
-use shared::LinkDto;
+use shared::{
+    CreateResult, ErrorDto, LinkDto, LinkVisibility, PaymentProviderKind, PaymentStatus,
+    PublicConfig,
+};
 
+use crate::error_alert::{json_or_error, ErrorAlert};
+use crate::group_affinity_checkbox::GroupAffinityCheckbox;
 use crate::permanent_redirect_checkbox::PermanentRedirectCheckbox;
-use crate::use_fetch::use_fetch;
+use crate::session::{self, authorize};
+use crate::use_fetch::{use_fetch, use_fetch_result};
 
 #[derive(Properties, PartialEq)]
 pub struct InfoProps {
@@ -18,13 +26,73 @@ pub struct InfoProps {
 pub fn info(props: &InfoProps) -> Html {
     let link = props.link.clone();
 
-    let data = use_fetch::<LinkDto>(&format!("/api/links/{link}"));
+    // Links that went through a checkout get their management token back in the fragment
+    let location = window().unwrap().location();
+    if let Some(token) = location
+        .hash()
+        .ok()
+        .and_then(|hash| hash.strip_prefix("#token=").map(str::to_string))
+    {
+        session::set_link_token(&link, &token);
+        location.set_hash("").ok();
+    }
+
+    let token = session::link_token(&link);
+    // Only the creator of the link or an account that may own it can start a checkout
+    let can_pay = token.is_some() || session::token().is_some();
+    let result = use_fetch_result::<LinkDto>(&format!("/api/links/{link}"), token);
+    let data = result.clone().and_then(Result::ok);
+    let config = use_fetch::<PublicConfig>("/api/config");
+
+    // Until the config is loaded, the current origin is the best guess for the public url
+    let base_url = config
+        .as_ref()
+        .map(|config| config.public_base_url.clone())
+        .unwrap_or_else(|| window().unwrap().location().origin().unwrap());
+    let base_host = base_url
+        .split_once("://")
+        .map(|(_, host)| host)
+        .unwrap_or(&base_url)
+        .to_string();
+
+    // Links on a custom domain are served from there instead of the public url
+    let (base_url, base_host) = match data.as_ref().and_then(|data| data.domain.clone()) {
+        Some(domain) => (format!("https://{domain}"), domain),
+        None => (base_url, base_host),
+    };
 
     let copy_link = {
         let link = link.clone();
+        let base_url = base_url.clone();
         Callback::from(move |_| {
             let clipboard = window().unwrap().navigator().clipboard();
-            let _ = clipboard.write_text(&format!("https://hurlurl.com/{link}"));
+            let _ = clipboard.write_text(&format!("{base_url}/{link}"));
+        })
+    };
+
+    let checkout_error = use_state::<Option<ErrorDto>, _>(|| None);
+
+    let complete_payment = {
+        let link = link.clone();
+        let checkout_error = checkout_error.clone();
+        Callback::from(move |_| {
+            let link = link.clone();
+            let checkout_error = checkout_error.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let mut request = authorize(Request::post(&format!("/api/links/{link}/checkout")));
+                if let Some(token) = session::link_token(&link) {
+                    request = request.header("X-Management-Token", &token);
+                }
+
+                match json_or_error::<CreateResult>(request.send().await).await {
+                    Ok(CreateResult::StripeRedirect(url)) => {
+                        checkout_error.set(None);
+                        window().unwrap().location().set_href(&url).unwrap();
+                    }
+                    Ok(CreateResult::Link(_)) => checkout_error.set(None),
+                    Err(error) => checkout_error.set(Some(error)),
+                }
+            });
         })
     };
 
@@ -33,8 +101,44 @@ pub fn info(props: &InfoProps) -> Html {
             <Header link={link.clone()}/>
             <div class="flex items-center justify-center md:bg-base-200 min-h-screen">
 
-                { if let Some(data) = data.as_ref() {
-                    let link = format!("https://hurlurl.com/{}", data.link.url);
+                { if let Some(data) = data.as_ref().filter(|data| matches!(
+                    data.link.payment_status,
+                    Some(PaymentStatus::Pending) | Some(PaymentStatus::Failed)
+                )) {
+                    let pending = data.link.payment_status == Some(PaymentStatus::Pending);
+                    if config.as_ref().map(|config| config.payment_provider) == Some(PaymentProviderKind::ManualApproval) {
+                        html!{<div class="card bg-base-100 p-8 md:shadow max-w-md">
+                            if pending {
+                                <h1 class="text-2xl mb-2">{"Waiting for approval"}</h1>
+                                <p>{"This hurlurl needs to be approved by an admin before it starts working. Check back later!"}</p>
+                            } else {
+                                <h1 class="text-2xl mb-2">{"Rejected"}</h1>
+                                <p>{"This hurlurl has been rejected by an admin."}</p>
+                            }
+                        </div>}
+                    } else {
+                        html!{<div class="card bg-base-100 p-8 md:shadow max-w-md">
+                            <h1 class="text-2xl mb-2">{"Payment required"}</h1>
+                            <p class="mb-6">
+                                if pending {
+                                    {"We haven't received the payment for this hurlurl yet. If you closed the checkout page, you can start a new one here."}
+                                } else {
+                                    {"The checkout for this hurlurl has expired. Complete the payment to activate it."}
+                                }
+                            </p>
+                            if can_pay {
+                                <button class="btn btn-primary" onclick={complete_payment}>{"Complete payment"}</button>
+                            } else {
+                                <p class="opacity-60">{"Only the creator of this hurlurl can complete the payment."}</p>
+                            }
+                            if let Some(error) = &*checkout_error {
+                                <ErrorAlert error={error.clone()}/>
+                            }
+                        </div>}
+                    }
+                } else if let Some(data) = data.as_ref() {
+                    let link = format!("{base_url}/{}", data.link.url);
+                    let qr_url = format!("/api/links/{}/qr", data.link.url);
                     html!{<div class="card bg-base-100 p-8 md:shadow">
 
                         <h1 class="text-xl md:text-3xl mb-2 flex items-center">
@@ -48,12 +152,42 @@ pub fn info(props: &InfoProps) -> Html {
 
                         <PermanentRedirectCheckbox checked={data.link.permanent_redirect} disabled={true} />
 
+                        if data.link.group_affinity {
+                            <GroupAffinityCheckbox checked={true} disabled={true} />
+                        }
+
+                        if data.password_protected {
+                            <p class="opacity-60">{"Visitors need to enter a password before they are redirected."}</p>
+                        }
+
+                        if data.link.picker {
+                            <p class="opacity-60">{"Visitors choose their target on a landing page that shows how many visitors each one already got."}</p>
+                        }
+
+                        if data.link.proxy {
+                            <p class="opacity-60">{"Requests to this hurlurl are forwarded to its targets instead of being redirected."}</p>
+                        }
+
+                        if data.signed {
+                            <p class="opacity-60">{"This hurlurl can only be opened through personal, signed urls."}</p>
+                        }
+
+                        <h1 class="text-2xl mt-5 mb-2">{"QR code"}</h1>
+
+                        <div class="flex items-center gap-4">
+                            <img class="w-32 h-32" src={format!("{qr_url}?format=svg")} alt="QR code" />
+                            <div class="flex flex-col gap-2">
+                                <a class="btn btn-sm" href={format!("{qr_url}?format=svg")} download={format!("hurlurl-{}.svg", data.link.url)}>{"Download SVG"}</a>
+                                <a class="btn btn-sm" href={format!("{qr_url}?format=png&size=1024")} download={format!("hurlurl-{}.png", data.link.url)}>{"Download PNG"}</a>
+                            </div>
+                        </div>
+
                         <h1 class="text-2xl mt-5 mb-2">{"Link stats"}</h1>
 
                         <div class="stats shadow bg-white">
 
                             <div class="stat">
-                                <a href={link} target="_blank" class="stat-title">{"hurlurl.com/"}{&data.link.url}</a>
+                                <a href={link} target="_blank" class="stat-title">{&base_host}{"/"}{&data.link.url}</a>
                                 <div class="stat-value text-primary">{&data.link.redirects}</div>
                                 <div class="stat-desc">{"clicks"}</div>
                             </div>
@@ -62,26 +196,142 @@ pub fn info(props: &InfoProps) -> Html {
 
                         <h1 class="text-2xl mt-5 mb-2">{"Target stats"}</h1>
 
-                        <div class="stats stats-vertical shadow bg-white">
+                        if let Some(provisioning) = &data.provisioning {
+                            <div class="mb-4">
+                                <p class="opacity-60 break-all">
+                                    {format!("New targets are requested from {} when fewer than {} more visitors fit", provisioning.url, provisioning.threshold)}
+                                    if let Some(remaining) = provisioning.remaining_capacity {
+                                        {format!(", {remaining} currently do.")}
+                                    } else {
+                                        {", but some targets have no capacity limit."}
+                                    }
+                                </p>
+                                if !provisioning.events.is_empty() {
+                                    <div class="overflow-x-auto mt-2">
+                                        <table class="table table-compact w-full">
+                                            <thead>
+                                                <tr>
+                                                    <th>{"Time"}</th>
+                                                    <th>{"Remaining"}</th>
+                                                    <th>{"Attempts"}</th>
+                                                    <th>{"Result"}</th>
+                                                </tr>
+                                            </thead>
+                                            <tbody>
+                                                { for provisioning.events.iter().map(|event| html! {
+                                                    <tr>
+                                                        <td>{event.created_at.trunc_subsecs(0).to_string()}</td>
+                                                        <td>{event.remaining_capacity}</td>
+                                                        <td>{event.attempts}</td>
+                                                        <td class="whitespace-normal">
+                                                            if let Some(error) = &event.error {
+                                                                <span class="text-error">{error}</span>
+                                                            } else {
+                                                                {format!("{} targets added", event.added_targets)}
+                                                            }
+                                                        </td>
+                                                    </tr>
+                                                }) }
+                                            </tbody>
+                                        </table>
+                                    </div>
+                                }
+                            </div>
+                        }
 
-                            { for data.targets.iter().map(|target| html! {
-                                <div class="stat">
-                                    <a href={target.target_url.clone()} target="_blank" class="stat-title">{&target.target_url}</a>
-                                    <div class="stat-value text-primary">{&target.redirects}</div>
-                                    <div class="stat-desc">{"redirects"}</div>
-                                </div>
-                            }) }
+                        if let Some(source) = &data.target_source {
+                            <div class="mb-4">
+                                <p class="opacity-60 break-all">
+                                    {"The targets are loaded from "}
+                                    <a class="link" href={source.url.clone()} target="_blank">{&source.url}</a>
+                                    if let Some(checked_at) = source.checked_at {
+                                        {format!(", last checked {}", checked_at.trunc_subsecs(0))}
+                                    }
+                                </p>
+                                if let Some(error) = &source.error {
+                                    <div class="alert alert-warning mt-2">
+                                        <span>{format!("{error}. The targets below are from the last successful update.")}</span>
+                                    </div>
+                                }
+                            </div>
+                        }
 
-                        </div>
+                        // Every link has targets, they are only missing if they were hidden from us
+                        if data.targets.is_empty() {
+                            <p class="opacity-60">{"The targets of this hurlurl are hidden."}</p>
+                        } else {
+                            <div class="stats stats-vertical shadow bg-white">
+
+                                { for data.targets.iter().map(|target| {
+                                    let groups = data.link.group_affinity.then(|| data.target_groups
+                                        .iter()
+                                        .find(|groups| groups.target_id == target.id)
+                                        .map(|groups| groups.groups)
+                                        .unwrap_or(0));
+                                    html! {
+                                        <div class="stat">
+                                            <a href={target.target_url.clone()} target="_blank" class="stat-title flex items-center gap-2">
+                                                if let Some(color) = &target.color {
+                                                    <span class="inline-block w-3 h-3 rounded-full" style={format!("background: {color}")}></span>
+                                                }
+                                                {target.display_name()}
+                                            </a>
+                                            if target.label.is_some() {
+                                                <div class="text-xs opacity-60 break-all">{&target.target_url}</div>
+                                            }
+                                            if let Some(description) = &target.description {
+                                                <div class="text-sm">{description}</div>
+                                            }
+                                            <div class="stat-value text-primary">{&target.redirects}</div>
+                                            <div class="stat-desc">
+                                                {"redirects"}
+                                                if let Some(capacity) = target.capacity {
+                                                    {format!(" of {capacity}")}
+                                                    if target.is_full() {
+                                                        {", full"}
+                                                    }
+                                                }
+                                                if let Some(groups) = groups {
+                                                    {format!(" from {groups} {}", if groups == 1 { "group" } else { "groups" })}
+                                                }
+                                                if target.weight != 1 {
+                                                    {format!(", weight {}", target.weight)}
+                                                }
+                                            </div>
+                                        </div>
+                                    }
+                                }) }
+
+                            </div>
+                        }
 
                         <div class="alert alert-info shadow-lg mt-8">
                             <div>
                                 <svg xmlns="http://www.w3.org/2000/svg" fill="none" viewBox="0 0 24 24" class="stroke-current flex-shrink-0 w-6 h-6"><path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M13 16h-1v-4h-1m1-4h.01M21 12a9 9 0 11-18 0 9 9 0 0118 0z"></path></svg>
-                                <span>{"This page is accessible by anyone with the link"}</span>
+                                <span>{match data.link.visibility {
+                                    LinkVisibility::Public => "This page is accessible by anyone with the link",
+                                    LinkVisibility::StatsOnly => "The clicks on this page are visible to anyone with the link, the targets only to its owners",
+                                    LinkVisibility::Private => "This page is only accessible to the owners of this hurlurl",
+                                }}</span>
                             </div>
                         </div>
 
                     </div>}
+                } else if let Some(Err(401 | 403)) = result {
+                    html!{<div class="card bg-base-100 p-8 md:shadow max-w-md">
+                        <h1 class="text-2xl mb-2">{"Private hurlurl"}</h1>
+                        <p>{"The stats of this hurlurl are only visible to its owners."}</p>
+                    </div>}
+                } else if let Some(Err(404)) = result {
+                    html!{<div class="card bg-base-100 p-8 md:shadow max-w-md">
+                        <h1 class="text-2xl mb-2">{"Not found"}</h1>
+                        <p>{"There is no hurlurl with this name."}</p>
+                    </div>}
+                } else if let Some(Err(_)) = result {
+                    html!{<div class="card bg-base-100 p-8 md:shadow max-w-md">
+                        <h1 class="text-2xl mb-2">{"Something went wrong"}</h1>
+                        <p>{"The hurlurl couldn't be loaded, please try again later."}</p>
+                    </div>}
                 } else {
                     html!{"Loading..."}
                 }}
@@ -7,12 +7,15 @@ use imprint::Imprint;
 use crate::home::Home;
 use crate::info::Info;
 
+mod error_alert;
 mod form;
+mod group_affinity_checkbox;
 mod header;
 mod home;
 mod imprint;
 mod info;
 mod permanent_redirect_checkbox;
+mod session;
 mod total_stats;
 mod use_fetch;
 
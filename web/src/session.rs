@@ -0,0 +1,63 @@
+use gloo_net::http::Request;
+use web_sys::{window, Storage};
+
+const TOKEN_KEY: &str = "hurlurl-token";
+const WORKSPACE_KEY: &str = "hurlurl-workspace";
+const LINK_TOKEN_PREFIX: &str = "hurlurl-link-token-";
+
+fn storage() -> Option<Storage> {
+    window()?.local_storage().ok()?
+}
+
+/// The api token of the account the user signed in with.
+pub fn token() -> Option<String> {
+    storage()?.get_item(TOKEN_KEY).ok()?
+}
+
+pub fn set_token(token: Option<&str>) {
+    if let Some(storage) = storage() {
+        match token {
+            Some(token) => storage.set_item(TOKEN_KEY, token).ok(),
+            None => storage.remove_item(TOKEN_KEY).ok(),
+        };
+        // Workspaces belong to the account, so the selection can't be kept
+        storage.remove_item(WORKSPACE_KEY).ok();
+    }
+}
+
+/// The workspace new links are created in, none for personal links.
+pub fn workspace() -> Option<i32> {
+    storage()?.get_item(WORKSPACE_KEY).ok()??.parse().ok()
+}
+
+pub fn set_workspace(workspace: Option<i32>) {
+    if let Some(storage) = storage() {
+        match workspace {
+            Some(workspace) => storage.set_item(WORKSPACE_KEY, &workspace.to_string()).ok(),
+            None => storage.remove_item(WORKSPACE_KEY).ok(),
+        };
+    }
+}
+
+/// The management token of a link that was created in this browser.
+pub fn link_token(link: &str) -> Option<String> {
+    storage()?
+        .get_item(&format!("{LINK_TOKEN_PREFIX}{link}"))
+        .ok()?
+}
+
+pub fn set_link_token(link: &str, token: &str) {
+    if let Some(storage) = storage() {
+        storage
+            .set_item(&format!("{LINK_TOKEN_PREFIX}{link}"), token)
+            .ok();
+    }
+}
+
+/// Adds the token of the signed in account to a request.
+pub fn authorize(request: Request) -> Request {
+    match token() {
+        Some(token) => request.header("Authorization", &format!("Bearer {token}")),
+        None => request,
+    }
+}
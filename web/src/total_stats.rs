@@ -1,10 +1,16 @@
+use crate::session;
 use crate::use_fetch::use_fetch;
 use shared::TotalStats as Stats;
 use yew::{function_component, html, Html};
 
 #[function_component(TotalStats)]
 pub fn total_stats() -> Html {
-    let data: Option<Stats> = use_fetch("/api/stats");
+    // Members of a workspace see the stats of its links instead of the global ones
+    let url = match session::workspace() {
+        Some(workspace) => format!("/api/workspaces/{workspace}/stats"),
+        None => "/api/stats".to_string(),
+    };
+    let data: Option<Stats> = use_fetch(&url);
 
     let value = |val| {
         if let Some(val) = val {
@@ -4,6 +4,8 @@ use serde::de::DeserializeOwned;
 use wasm_bindgen_futures::spawn_local;
 use yew::{hook, use_effect_with_deps, use_state};
 
+use crate::session::authorize;
+
 #[hook]
 pub fn use_fetch<T: DeserializeOwned + Clone + 'static>(url: &str) -> Option<T> {
     let data = use_state::<Option<T>, _>(|| None);
@@ -29,9 +31,57 @@ async fn fetch<T>(url: &str) -> Result<T, String>
 where
     T: DeserializeOwned,
 {
-    Request::get(url)
+    authorize(Request::get(url))
         .send()
         .and_then(|response| async move { response.json().await })
         .await
         .map_err(|error| error.to_string())
 }
+
+/// Like `use_fetch`, but keeps the status code of failed requests, e.g. to tell links that
+/// don't exist apart from links the user isn't allowed to see. The management token of a link
+/// is sent along if there is one.
+#[hook]
+pub fn use_fetch_result<T: DeserializeOwned + Clone + 'static>(
+    url: &str,
+    management_token: Option<String>,
+) -> Option<Result<T, u16>> {
+    let data = use_state::<Option<Result<T, u16>>, _>(|| None);
+
+    let cloned_data = data.clone();
+    use_effect_with_deps(
+        |(url, management_token)| {
+            let url = url.clone();
+            let management_token = management_token.clone();
+            spawn_local(async move {
+                cloned_data.set(Some(
+                    fetch_with_status::<T>(&url, management_token.as_deref()).await,
+                ));
+            });
+            || {}
+        },
+        (url.to_string(), management_token),
+    );
+
+    (*data).clone()
+}
+
+/// Fetches json, failing with the status code of the response, or 0 if there was none.
+async fn fetch_with_status<T>(url: &str, management_token: Option<&str>) -> Result<T, u16>
+where
+    T: DeserializeOwned,
+{
+    let mut request = authorize(Request::get(url));
+    if let Some(token) = management_token {
+        request = request.header("X-Management-Token", token);
+    }
+
+    let response = request.send().await.map_err(|_| 0u16)?;
+
+    let status = response.status();
+    if !response.ok() {
+        return Err(status);
+    }
+
+    response.json().await.map_err(|_| status)
+}